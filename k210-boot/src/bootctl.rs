@@ -0,0 +1,181 @@
+//! A/B启动：SPI闪存中的两个内核槽位，和记录从哪个槽位启动的启动控制记录。
+//!
+//! 每个槽位存放一个程序镜像（见`payload`模块），A槽位在`FLASH_PAYLOAD_OFFSET`，
+//! B槽位紧跟在它之后。新写入的槽位成为当前槽位并得到若干次尝试机会，每次启动消耗一次；
+//! 内核用厂商SBI调用标记启动成功之后不再消耗。机会用完仍没有成功时回退到另一个槽位。
+//!
+//! 启动控制记录有两份副本，各占一个4KiB的扇区，从`FLASH_BOOT_CONTROL_OFFSET`开始紧挨着存放。
+//! 启动时使用代数较新的有效副本，更新时写到另一份副本上并把代数加一，
+//! 写入时掉电只会破坏正在写的副本，和`env`模块的两份副本相同。每份副本（小端序）：
+//!
+//! | 偏移 | 长度 | 内容                 |
+//! |------|------|----------------------|
+//! | 0    | 8    | 魔数`K210BCTL`       |
+//! | 8    | 1    | 当前槽位，0为A，1为B |
+//! | 9    | 1    | 剩余的尝试次数       |
+//! | 10   | 1    | 启动成功标志         |
+//! | 11   | 1    | 代数，每次写入加一   |
+//! | 12   | 4    | 前12字节的CRC32      |
+use crate::config;
+use crate::payload::crc32;
+
+pub const BOOT_CONTROL_MAGIC: [u8; 8] = *b"K210BCTL";
+pub const BOOT_CONTROL_SIZE: usize = 16;
+/// 每份副本占用的闪存扇区大小
+pub const BOOT_CONTROL_SECTOR_SIZE: usize = 0x1000;
+
+/// 新写入的槽位默认的尝试次数
+pub const DEFAULT_TRIES: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    A,
+    B,
+}
+
+impl Slot {
+    pub fn other(self) -> Slot {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+
+    /// 槽位中程序镜像在闪存中的偏移量
+    pub fn flash_offset(self) -> usize {
+        match self {
+            Slot::A => config::FLASH_PAYLOAD_OFFSET,
+            Slot::B => config::FLASH_PAYLOAD_OFFSET + config::FLASH_SLOT_SIZE,
+        }
+    }
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootControl {
+    pub active: Slot,
+    pub tries: u8,
+    pub successful: bool,
+    pub generation: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootControlError {
+    // 副本是空的：没有写过，或者擦除之后没有写完
+    BadMagic,
+    BadCrc { expected: u32, actual: u32 },
+    BadSlot(u8),
+}
+
+/// 本次启动的决定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// 当前槽位已经成功启动过
+    Confirmed(Slot),
+    /// 尝试启动当前槽位，之后还剩`tries_left`次机会
+    Trial { slot: Slot, tries_left: u8 },
+    /// 当前槽位的机会已经用完，改为启动另一个槽位
+    Fallback { from: Slot, to: Slot },
+}
+
+impl Decision {
+    pub fn slot(self) -> Slot {
+        match self {
+            Decision::Confirmed(slot) | Decision::Trial { slot, .. } => slot,
+            Decision::Fallback { to, .. } => to,
+        }
+    }
+}
+
+impl BootControl {
+    /// 刚写入新程序的槽位：成为当前槽位，还没有成功启动过
+    pub fn new(active: Slot, tries: u8) -> Self {
+        BootControl {
+            active,
+            tries,
+            successful: false,
+            generation: 0,
+        }
+    }
+
+    pub fn parse(bytes: &[u8; BOOT_CONTROL_SIZE]) -> Result<Self, BootControlError> {
+        if bytes[..8] != BOOT_CONTROL_MAGIC {
+            return Err(BootControlError::BadMagic);
+        }
+        let expected = u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]);
+        let actual = crc32(&bytes[..12]);
+        if expected != actual {
+            return Err(BootControlError::BadCrc { expected, actual });
+        }
+        let active = match bytes[8] {
+            0 => Slot::A,
+            1 => Slot::B,
+            n => return Err(BootControlError::BadSlot(n)),
+        };
+        Ok(BootControl {
+            active,
+            tries: bytes[9],
+            successful: bytes[10] != 0,
+            generation: bytes[11],
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; BOOT_CONTROL_SIZE] {
+        let mut bytes = [0; BOOT_CONTROL_SIZE];
+        bytes[..8].copy_from_slice(&BOOT_CONTROL_MAGIC);
+        bytes[8] = self.active.index() as u8;
+        bytes[9] = self.tries;
+        bytes[10] = self.successful as u8;
+        bytes[11] = self.generation;
+        let crc = crc32(&bytes[..12]);
+        bytes[12..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// 决定本次启动哪个槽位，并更新记录；记录改变时调用者要把它写回闪存
+    pub fn select(&mut self) -> Decision {
+        if self.successful {
+            return Decision::Confirmed(self.active);
+        }
+        if self.tries > 0 {
+            self.tries -= 1;
+            return Decision::Trial {
+                slot: self.active,
+                tries_left: self.tries,
+            };
+        }
+        let from = self.active;
+        self.fall_back();
+        Decision::Fallback {
+            from,
+            to: self.active,
+        }
+    }
+
+    /// 放弃当前槽位，改用另一个槽位；认为它是上一次成功启动的版本
+    pub fn fall_back(&mut self) {
+        self.active = self.active.other();
+        self.tries = 0;
+        self.successful = true;
+    }
+
+    /// 内核确认当前槽位启动成功；返回记录是否改变
+    pub fn mark_successful(&mut self) -> bool {
+        let changed = !self.successful;
+        self.successful = true;
+        changed
+    }
+}
+
+/// 第`index`份副本在闪存中的偏移量
+pub fn copy_offset(index: usize) -> usize {
+    config::FLASH_BOOT_CONTROL_OFFSET + index * BOOT_CONTROL_SECTOR_SIZE
+}
+
+/// 选出两份副本中当前的一份，返回它的序号；都无效时返回`None`
+pub fn current(copies: [Option<BootControl>; 2]) -> Option<usize> {
+    crate::env::current(copies.map(|copy| copy.map(|record| record.generation)))
+}
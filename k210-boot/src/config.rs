@@ -0,0 +1,256 @@
+//! 启动配置：固件位置和大小上限、下一阶段程序的入口、设备树的位置。
+//!
+//! 默认布局：RustSBI位于`0x80000000`，最多124KiB；内核从`0x80020000`开始，
+//! 它前面的4KiB是交接区，存放给固件的启动信息。编译时可以用环境变量覆盖，例如：
+//!
+//! ```text
+//! K210_PAYLOAD_ADDRESS=0x80040000 K210_SBI_SIZE_LIMIT=0x3f000 cargo k210
+//! ```
+//!
+//! 数值支持十进制和带`0x`前缀的十六进制。配置不合法时所有使用这个包的程序都会编译失败。
+
+/// 通用SRAM的起始地址和大小，不包括AI SRAM
+pub const RAM_START: usize = 0x8000_0000;
+pub const RAM_SIZE: usize = 6 * 1024 * 1024;
+pub const RAM_END: usize = RAM_START + RAM_SIZE;
+
+/// RustSBI镜像的起始地址，也是芯片启动后跳转的地址
+pub const SBI_START: usize = RAM_START;
+
+/// RustSBI镜像（包括.bss段）最多占用的空间，链接脚本用它作为存储区域的长度
+pub const SBI_SIZE_LIMIT: usize = parse_or(option_env!("K210_SBI_SIZE_LIMIT"), 0x1_f000);
+
+/// 下一阶段程序的入口地址，进入时a0为核编号，a1为设备树地址
+pub const PAYLOAD_ADDRESS: usize = parse_or(option_env!("K210_PAYLOAD_ADDRESS"), 0x8002_0000);
+
+/// 下一阶段程序在合并后的镜像中的偏移量
+pub const PAYLOAD_OFFSET: usize = PAYLOAD_ADDRESS - SBI_START;
+
+/// 交接区紧挨在下一阶段程序之前，合并镜像时在这里放入给固件的启动信息：
+/// 开头是`fw_dynamic_info`结构，`HANDOFF_INITRD_OFFSET`处是初始内存盘的描述头，
+/// `HANDOFF_IMAGE_SIZE_OFFSET`处是带签名尾部的内核镜像长度（小端序u64，没有签名时为0），
+/// `HANDOFF_COMPRESSED_SIZE_OFFSET`处是压缩后的内核长度（小端序u64，内核是LZ4帧，没有压缩时为0）
+pub const HANDOFF_SIZE: usize = 0x1000;
+pub const HANDOFF_ADDRESS: usize = PAYLOAD_ADDRESS - HANDOFF_SIZE;
+pub const HANDOFF_OFFSET: usize = HANDOFF_ADDRESS - SBI_START;
+pub const HANDOFF_INITRD_OFFSET: usize = 0x40;
+pub const HANDOFF_IMAGE_SIZE_OFFSET: usize = 0x60;
+pub const HANDOFF_COMPRESSED_SIZE_OFFSET: usize = 0x68;
+
+/// 初始内存盘的加载地址。为`None`时xtask把它紧接着放在内核之后的第一个4K对齐的地址；
+/// 放在SRAM末尾等更高的地址时，合并镜像会被填充到那个位置
+pub const INITRD_ADDRESS: Option<usize> = match option_env!("K210_INITRD_ADDRESS") {
+    Some(s) => Some(parse(s)),
+    None => None,
+};
+
+/// 设备树的位置。为`None`时直接传递固件内嵌的设备树；
+/// 否则启动前把设备树复制到这个地址，避免内核覆盖固件所在的内存后设备树失效
+pub const DTB_ADDRESS: Option<usize> = match option_env!("K210_DTB_ADDRESS") {
+    Some(s) => Some(parse(s)),
+    None => None,
+};
+
+/// 复制设备树时为它预留的空间
+pub const DTB_SIZE_LIMIT: usize = 0x3000;
+
+/// 从闪存启动时，下一阶段程序镜像（见`payload`模块）在SPI闪存中的偏移量。
+/// 闪存开头是芯片启动时加载的RustSBI
+pub const FLASH_PAYLOAD_OFFSET: usize =
+    parse_or(option_env!("K210_FLASH_PAYLOAD_OFFSET"), 0x10_0000);
+
+/// A/B启动时每个槽位的大小，B槽位紧跟在A槽位（`FLASH_PAYLOAD_OFFSET`）之后，见`bootctl`模块
+pub const FLASH_SLOT_SIZE: usize = parse_or(option_env!("K210_FLASH_SLOT_SIZE"), 0x60_0000);
+
+/// 启动控制记录在闪存中的偏移量，两份副本紧挨着，独占一个64KiB的块，不能和槽位重叠
+pub const FLASH_BOOT_CONTROL_OFFSET: usize =
+    parse_or(option_env!("K210_FLASH_BOOT_CONTROL_OFFSET"), 0xf_0000);
+
+/// 启动环境变量在闪存中的偏移量，两份副本紧挨着，独占一个64KiB的块，见`env`模块
+pub const FLASH_ENV_OFFSET: usize = parse_or(option_env!("K210_FLASH_ENV_OFFSET"), 0xe_0000);
+
+/// 从SD卡启动时，FAT32分区中内核文件的路径
+pub const SD_KERNEL_PATH: &str = match option_env!("K210_SD_KERNEL") {
+    Some(path) => path,
+    None => "/kernel.bin",
+};
+
+/// 从SD卡启动时设备树文件的路径；文件不存在时使用内嵌的设备树
+pub const SD_DTB_PATH: &str = match option_env!("K210_SD_DTB") {
+    Some(path) => path,
+    None => "/k210.dtb",
+};
+
+/// 串口下载模式：不为0时启动后总是先等待YMODEM传输内核，否则只在按住按键时等待
+pub const SERIAL_BOOT: bool = parse_or(option_env!("K210_SERIAL_BOOT"), 0) != 0;
+
+/// 进入串口下载模式的按键所在的IO，低电平表示按下；默认是开发板上的BOOT键，
+/// 复位时按住它会进入芯片的ISP模式，应当在复位后再按下
+pub const SERIAL_BOOT_PIN: usize = parse_or(option_env!("K210_SERIAL_BOOT_PIN"), 16);
+
+/// 启动时的跟踪掩码，位0到位2依次是SBI调用、指令模拟和异常转交；启动时可以用环境变量`trace_mask`
+/// 或者启动监视器的`tracemask`命令修改。只有打开rustsbi-k210的`trace`特性时有效
+pub const TRACE_MASK: usize = parse_or(option_env!("K210_TRACE_MASK"), 0);
+
+/// 启动监视器：进入下一阶段程序之前等待按键的毫秒数，期间按任意键进入监视器。
+/// 只有打开rustsbi-k210的`monitor`特性时有效，为0时不等待
+pub const MONITOR_WAIT_MS: usize = parse_or(option_env!("K210_MONITOR_WAIT_MS"), 1000);
+
+/// GDB连接时停下来交给GDB的异常，按mcause的编号组成的掩码；默认是非法指令和访问错误，
+/// 不包括页异常。只有打开rustsbi-k210的`gdb-stub`特性时有效
+pub const GDB_CATCH: usize = parse_or(option_env!("K210_GDB_CATCH"), 0xa6);
+
+/// 进入内核之前启动看门狗的超时毫秒数，内核需要在超时之前通过厂商SBI调用喂狗，否则芯片复位。
+/// 只有打开rustsbi-k210的`watchdog`特性时有效，为0时不启动，由内核决定是否打开
+pub const WATCHDOG_TIMEOUT_MS: usize = parse_or(option_env!("K210_WATCHDOG_TIMEOUT_MS"), 10_000);
+
+/// 验证启动使用的公钥文件，内容是64个十六进制字符；相对路径从工作区的根目录开始。
+/// 打开rustsbi-k210的`verified-boot`特性时必须设置
+pub const VERIFY_KEY: Option<&str> = option_env!("K210_VERIFY_KEY");
+
+/// 写入设备树`/chosen/bootargs`的内核命令行，为`None`时保留设备树中原有的值
+pub const BOOTARGS: Option<&str> = option_env!("K210_BOOTARGS");
+
+/// 以上配置读取的环境变量，构建脚本用它们决定何时重新运行
+pub const ENV_VARS: &[&str] = &[
+    "K210_SBI_SIZE_LIMIT",
+    "K210_PAYLOAD_ADDRESS",
+    "K210_DTB_ADDRESS",
+    "K210_BOOTARGS",
+    "K210_INITRD_ADDRESS",
+    "K210_FLASH_PAYLOAD_OFFSET",
+    "K210_FLASH_SLOT_SIZE",
+    "K210_FLASH_BOOT_CONTROL_OFFSET",
+    "K210_FLASH_ENV_OFFSET",
+    "K210_SD_KERNEL",
+    "K210_SD_DTB",
+    "K210_SERIAL_BOOT",
+    "K210_SERIAL_BOOT_PIN",
+    "K210_TRACE_MASK",
+    "K210_MONITOR_WAIT_MS",
+    "K210_GDB_CATCH",
+    "K210_WATCHDOG_TIMEOUT_MS",
+    "K210_VERIFY_KEY",
+];
+
+const _: () = {
+    assert!(
+        SBI_SIZE_LIMIT % 8 == 0,
+        "SBI size limit must be 8-byte aligned"
+    );
+    assert!(
+        SBI_START + SBI_SIZE_LIMIT <= HANDOFF_ADDRESS,
+        "RustSBI image would overlap the handoff area before the payload"
+    );
+    assert!(
+        PAYLOAD_ADDRESS % 8 == 0,
+        "payload entry must be 8-byte aligned"
+    );
+    assert!(
+        PAYLOAD_ADDRESS < RAM_END,
+        "payload entry is outside of SRAM"
+    );
+    assert!(
+        crate::fw_dynamic::FW_DYNAMIC_INFO_SIZE <= HANDOFF_INITRD_OFFSET
+            && HANDOFF_INITRD_OFFSET + crate::initrd::INITRD_HEADER_SIZE
+                <= HANDOFF_IMAGE_SIZE_OFFSET
+            && HANDOFF_IMAGE_SIZE_OFFSET + 8 <= HANDOFF_COMPRESSED_SIZE_OFFSET
+            && HANDOFF_COMPRESSED_SIZE_OFFSET + 8 <= HANDOFF_SIZE,
+        "handoff area entries overlap"
+    );
+    assert!(
+        FLASH_PAYLOAD_OFFSET + 2 * FLASH_SLOT_SIZE <= 1 << 24,
+        "both flash slots must fit in a 24-bit flash address"
+    );
+    // 烧写工具按64KiB的块擦除闪存
+    assert!(
+        FLASH_PAYLOAD_OFFSET % 0x1_0000 == 0
+            && FLASH_SLOT_SIZE % 0x1_0000 == 0
+            && FLASH_BOOT_CONTROL_OFFSET % 0x1_0000 == 0
+            && FLASH_ENV_OFFSET % 0x1_0000 == 0,
+        "flash slots, the boot control record and the environment must be 64KiB aligned"
+    );
+    assert!(
+        FLASH_BOOT_CONTROL_OFFSET < FLASH_PAYLOAD_OFFSET
+            || FLASH_BOOT_CONTROL_OFFSET >= FLASH_PAYLOAD_OFFSET + 2 * FLASH_SLOT_SIZE,
+        "boot control record would overlap the flash slots"
+    );
+    assert!(
+        FLASH_BOOT_CONTROL_OFFSET < 1 << 24,
+        "boot control record must fit in a 24-bit flash address"
+    );
+    assert!(TRACE_MASK & !0b111 == 0, "unknown bits in the trace mask");
+    assert!(
+        WATCHDOG_TIMEOUT_MS <= crate::watchdog::MAX_TIMEOUT_MS,
+        "watchdog timeout is longer than the watchdog can count"
+    );
+    assert!(
+        (FLASH_ENV_OFFSET < FLASH_PAYLOAD_OFFSET
+            || FLASH_ENV_OFFSET >= FLASH_PAYLOAD_OFFSET + 2 * FLASH_SLOT_SIZE)
+            && FLASH_ENV_OFFSET != FLASH_BOOT_CONTROL_OFFSET,
+        "environment would overlap the flash slots or the boot control record"
+    );
+    assert!(
+        FLASH_ENV_OFFSET < 1 << 24,
+        "environment must fit in a 24-bit flash address"
+    );
+    assert!(SERIAL_BOOT_PIN < 48, "K210 only has IO0 to IO47");
+    if let Some(initrd) = INITRD_ADDRESS {
+        assert!(
+            initrd >= PAYLOAD_ADDRESS && initrd < RAM_END,
+            "initrd must be loaded into SRAM above the payload entry"
+        );
+    }
+    if let Some(dtb) = DTB_ADDRESS {
+        assert!(dtb % 8 == 0, "device tree address must be 8-byte aligned");
+        assert!(
+            dtb >= SBI_START + SBI_SIZE_LIMIT && dtb + DTB_SIZE_LIMIT <= RAM_END,
+            "device tree must lie in SRAM outside of the RustSBI image"
+        );
+        assert!(
+            dtb + DTB_SIZE_LIMIT <= HANDOFF_ADDRESS || dtb > PAYLOAD_ADDRESS,
+            "device tree would overlap the handoff area or the payload entry"
+        );
+    }
+};
+
+const fn parse_or(value: Option<&str>, default: usize) -> usize {
+    match value {
+        Some(s) => parse(s),
+        None => default,
+    }
+}
+
+// 编译期解析数值，格式不对时编译失败
+const fn parse(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let (radix, mut i) =
+        if bytes.len() > 2 && bytes[0] == b'0' && (bytes[1] == b'x' || bytes[1] == b'X') {
+            (16, 2)
+        } else {
+            (10, 0)
+        };
+    assert!(i < bytes.len(), "empty number in boot configuration");
+    let mut value: usize = 0;
+    while i < bytes.len() {
+        let digit = match bytes[i] {
+            b'_' => {
+                i += 1;
+                continue;
+            }
+            b @ b'0'..=b'9' => (b - b'0') as usize,
+            b @ b'a'..=b'f' if radix == 16 => (b - b'a' + 10) as usize,
+            b @ b'A'..=b'F' if radix == 16 => (b - b'A' + 10) as usize,
+            _ => panic!("invalid number in boot configuration"),
+        };
+        value = match value.checked_mul(radix) {
+            Some(v) => match v.checked_add(digit) {
+                Some(v) => v,
+                None => panic!("number in boot configuration overflows"),
+            },
+            None => panic!("number in boot configuration overflows"),
+        };
+        i += 1;
+    }
+    value
+}
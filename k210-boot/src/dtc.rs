@@ -0,0 +1,546 @@
+//! 把设备树源文件（DTS）编译为扁平设备树（FDT），供`rustsbi-k210`的构建脚本使用，
+//! 不需要在构建环境中安装dtc。
+//!
+//! 只支持这个项目用到的语法：`/dts-v1/;`、节点和标签、`//`和`/* */`注释，
+//! 以及由字符串、`<...>`单元（数字和`&label`引用）、`[...]`字节组成的属性值。
+//! 被引用的节点会按出现顺序分配phandle，编号从显式写出的最大phandle之后开始；
+//! 已经写了`phandle = <N>`的节点沿用原来的值。
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_END: u32 = 9;
+const HEADER_SIZE: usize = 40;
+const RSVMAP_SIZE: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DtsError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for DtsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+pub type Result<T> = core::result::Result<T, DtsError>;
+
+/// 编译设备树源文件，返回FDT
+pub fn compile(source: &str) -> Result<Vec<u8>> {
+    let mut parser = Parser {
+        src: source.as_bytes(),
+        pos: 0,
+        line: 1,
+    };
+    let mut root = parser.parse()?;
+    assign_phandles(&mut root)?;
+    Ok(write_fdt(&root))
+}
+
+struct Node {
+    name: String,
+    labels: Vec<String>,
+    properties: Vec<Property>,
+    children: Vec<Node>,
+}
+
+struct Property {
+    name: String,
+    value: Vec<Value>,
+    line: usize,
+}
+
+// 属性值由逗号分隔的几部分组成
+enum Value {
+    Bytes(Vec<u8>),
+    Cells(Vec<Cell>),
+}
+
+enum Cell {
+    Number(u32),
+    Reference(String),
+}
+
+struct Parser<'a> {
+    src: &'a [u8],
+    pos: usize,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn parse(&mut self) -> Result<Node> {
+        self.skip_space()?;
+        if !self.eat_str("/dts-v1/") {
+            return Err(self.error("expected /dts-v1/"));
+        }
+        self.expect(b';')?;
+        let mut root: Option<Node> = None;
+        loop {
+            self.skip_space()?;
+            if self.pos == self.src.len() {
+                break;
+            }
+            if !self.eat(b'/') {
+                return Err(self.error("expected root node"));
+            }
+            let node = self.parse_node_body(String::new(), Vec::new())?;
+            // 多次出现的根节点合并在一起
+            match root.as_mut() {
+                Some(root) => merge(root, node),
+                None => root = Some(node),
+            }
+        }
+        root.ok_or_else(|| self.error("missing root node"))
+    }
+
+    // 已经读过节点名，从`{`开始读到`};`
+    fn parse_node_body(&mut self, name: String, labels: Vec<String>) -> Result<Node> {
+        self.skip_space()?;
+        self.expect(b'{')?;
+        let mut node = Node {
+            name,
+            labels,
+            properties: Vec::new(),
+            children: Vec::new(),
+        };
+        loop {
+            self.skip_space()?;
+            if self.eat(b'}') {
+                self.skip_space()?;
+                self.expect(b';')?;
+                return Ok(node);
+            }
+            let line = self.line;
+            let mut word = self.word()?;
+            let mut labels = Vec::new();
+            self.skip_space()?;
+            // 一个节点可以有多个标签
+            while self.eat(b':') {
+                labels.push(word);
+                self.skip_space()?;
+                word = self.word()?;
+                self.skip_space()?;
+            }
+            match self.peek() {
+                Some(b'{') => {
+                    // 子节点可以出现在属性之前，写入时统一排在属性后面
+                    let child = self.parse_node_body(word, labels)?;
+                    node.children.push(child);
+                }
+                Some(b'=') => {
+                    self.pos += 1;
+                    let value = self.parse_value()?;
+                    self.expect(b';')?;
+                    set_property(&mut node, word, value, line);
+                }
+                Some(b';') => {
+                    self.pos += 1;
+                    set_property(&mut node, word, Vec::new(), line);
+                }
+                _ => return Err(self.error("expected '{', '=' or ';'")),
+            }
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Vec<Value>> {
+        let mut value = Vec::new();
+        loop {
+            self.skip_space()?;
+            match self.peek() {
+                Some(b'"') => value.push(Value::Bytes(self.string()?)),
+                Some(b'<') => value.push(Value::Cells(self.cells()?)),
+                Some(b'[') => value.push(Value::Bytes(self.bytes()?)),
+                _ => return Err(self.error("expected property value")),
+            }
+            self.skip_space()?;
+            if !self.eat(b',') {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>> {
+        self.expect(b'"')?;
+        let mut out = Vec::new();
+        loop {
+            let c = self
+                .next()
+                .ok_or_else(|| self.error("unterminated string"))?;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let e = self
+                        .next()
+                        .ok_or_else(|| self.error("unterminated string"))?;
+                    out.push(match e {
+                        b'n' => b'\n',
+                        b't' => b'\t',
+                        b'0' => 0,
+                        other => other,
+                    });
+                }
+                b'\n' => return Err(self.error("newline in string")),
+                other => out.push(other),
+            }
+        }
+        out.push(0);
+        Ok(out)
+    }
+
+    fn cells(&mut self) -> Result<Vec<Cell>> {
+        self.expect(b'<')?;
+        let mut cells = Vec::new();
+        loop {
+            self.skip_space()?;
+            if self.eat(b'>') {
+                return Ok(cells);
+            }
+            if self.eat(b'&') {
+                cells.push(Cell::Reference(self.word()?));
+            } else {
+                cells.push(Cell::Number(self.number()?));
+            }
+        }
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>> {
+        self.expect(b'[')?;
+        let mut out = Vec::new();
+        loop {
+            self.skip_space()?;
+            if self.eat(b']') {
+                return Ok(out);
+            }
+            let hi = self.hex_digit()?;
+            let lo = self.hex_digit()?;
+            out.push(hi << 4 | lo);
+        }
+    }
+
+    fn hex_digit(&mut self) -> Result<u8> {
+        let c = self
+            .next()
+            .ok_or_else(|| self.error("unterminated byte string"))?;
+        (c as char)
+            .to_digit(16)
+            .map(|d| d as u8)
+            .ok_or_else(|| self.error("invalid hex digit"))
+    }
+
+    fn number(&mut self) -> Result<u32> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric()) {
+            self.pos += 1;
+        }
+        let text = core::str::from_utf8(&self.src[start..self.pos]).unwrap_or("");
+        let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => text.parse(),
+        };
+        match parsed {
+            Ok(v) if v <= u32::MAX as u64 => Ok(v as u32),
+            _ => Err(self.error("invalid cell value")),
+        }
+    }
+
+    // 节点名、属性名和标签
+    fn word(&mut self) -> Result<String> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || b",._+-#?@".contains(&c))
+        {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(self.error("expected a name"));
+        }
+        Ok(String::from_utf8_lossy(&self.src[start..self.pos]).to_string())
+    }
+
+    fn skip_space(&mut self) -> Result<()> {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_ascii_whitespace() => {
+                    self.next();
+                }
+                Some(b'/') if self.src.get(self.pos + 1) == Some(&b'/') => {
+                    while !matches!(self.next(), Some(b'\n') | None) {}
+                }
+                Some(b'/') if self.src.get(self.pos + 1) == Some(&b'*') => {
+                    self.pos += 2;
+                    loop {
+                        match self.next() {
+                            Some(b'*') if self.eat(b'/') => break,
+                            Some(_) => {}
+                            None => return Err(self.error("unterminated comment")),
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == b'\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        if self.peek() == Some(c) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_str(&mut self, s: &str) -> bool {
+        if self.src[self.pos..].starts_with(s.as_bytes()) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<()> {
+        self.skip_space()?;
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(&alloc::format!("expected '{}'", c as char)))
+        }
+    }
+
+    fn error(&self, message: &str) -> DtsError {
+        DtsError {
+            line: self.line,
+            message: message.to_string(),
+        }
+    }
+}
+
+// 同名属性以后出现的为准
+fn set_property(node: &mut Node, name: String, value: Vec<Value>, line: usize) {
+    node.properties.retain(|p| p.name != name);
+    node.properties.push(Property { name, value, line });
+}
+
+fn merge(into: &mut Node, from: Node) {
+    for label in from.labels {
+        if !into.labels.contains(&label) {
+            into.labels.push(label);
+        }
+    }
+    for p in from.properties {
+        set_property(into, p.name, p.value, p.line);
+    }
+    for child in from.children {
+        match into.children.iter_mut().find(|c| c.name == child.name) {
+            Some(existing) => merge(existing, child),
+            None => into.children.push(child),
+        }
+    }
+}
+
+// 给被引用的节点分配phandle，并把引用替换为数值
+fn assign_phandles(root: &mut Node) -> Result<()> {
+    let mut referenced = Vec::new();
+    collect_references(root, &mut referenced);
+    let mut table: Vec<(String, u32)> = Vec::new();
+    // 自动分配的phandle不能和显式写出的重复
+    let mut next = max_phandle(root) + 1;
+    assign(root, &referenced, &mut table, &mut next);
+    resolve(root, &table)
+}
+
+// 节点中写成`phandle = <N>`的值
+fn explicit_phandle(node: &Node) -> Option<u32> {
+    let p = node.properties.iter().find(|p| p.name == "phandle")?;
+    match p.value.as_slice() {
+        [Value::Cells(cells)] => match cells.as_slice() {
+            [Cell::Number(n)] => Some(*n),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn max_phandle(node: &Node) -> u32 {
+    node.children
+        .iter()
+        .map(max_phandle)
+        .chain(explicit_phandle(node))
+        .max()
+        .unwrap_or(0)
+}
+
+fn collect_references(node: &Node, out: &mut Vec<String>) {
+    for p in &node.properties {
+        for v in &p.value {
+            if let Value::Cells(cells) = v {
+                for c in cells {
+                    if let Cell::Reference(label) = c {
+                        if !out.contains(label) {
+                            out.push(label.clone());
+                        }
+                    }
+                }
+            }
+        }
+    }
+    for child in &node.children {
+        collect_references(child, out);
+    }
+}
+
+fn assign(node: &mut Node, referenced: &[String], table: &mut Vec<(String, u32)>, next: &mut u32) {
+    if node.labels.iter().any(|l| referenced.contains(l)) {
+        let phandle = match explicit_phandle(node) {
+            Some(phandle) => phandle,
+            None => {
+                let phandle = *next;
+                *next += 1;
+                let line = node.properties.last().map(|p| p.line).unwrap_or(0);
+                set_property(
+                    node,
+                    "phandle".to_string(),
+                    alloc::vec![Value::Cells(alloc::vec![Cell::Number(phandle)])],
+                    line,
+                );
+                phandle
+            }
+        };
+        for label in &node.labels {
+            table.push((label.clone(), phandle));
+        }
+    }
+    for child in &mut node.children {
+        assign(child, referenced, table, next);
+    }
+}
+
+fn resolve(node: &mut Node, table: &[(String, u32)]) -> Result<()> {
+    for p in &mut node.properties {
+        for v in &mut p.value {
+            if let Value::Cells(cells) = v {
+                for c in cells.iter_mut() {
+                    if let Cell::Reference(label) = c {
+                        let phandle = table
+                            .iter()
+                            .find(|(l, _)| l == label)
+                            .map(|(_, h)| *h)
+                            .ok_or_else(|| DtsError {
+                                line: p.line,
+                                message: alloc::format!("undefined label &{}", label),
+                            })?;
+                        *c = Cell::Number(phandle);
+                    }
+                }
+            }
+        }
+    }
+    for child in &mut node.children {
+        resolve(child, table)?;
+    }
+    Ok(())
+}
+
+fn write_fdt(root: &Node) -> Vec<u8> {
+    let mut structure = Vec::new();
+    let mut strings = Vec::new();
+    write_node(root, &mut structure, &mut strings);
+    push_u32(&mut structure, FDT_END);
+
+    let off_struct = HEADER_SIZE + RSVMAP_SIZE;
+    let off_strings = off_struct + structure.len();
+    let totalsize = off_strings + strings.len();
+    let mut out = Vec::with_capacity(totalsize);
+    for field in [
+        FDT_MAGIC,
+        totalsize as u32,
+        off_struct as u32,
+        off_strings as u32,
+        HEADER_SIZE as u32,
+        17, // version
+        16, // last_comp_version
+        0,  // boot_cpuid_phys
+        strings.len() as u32,
+        structure.len() as u32,
+    ] {
+        push_u32(&mut out, field);
+    }
+    out.extend_from_slice(&[0; RSVMAP_SIZE]);
+    out.extend_from_slice(&structure);
+    out.extend_from_slice(&strings);
+    out
+}
+
+fn write_node(node: &Node, out: &mut Vec<u8>, strings: &mut Vec<u8>) {
+    push_u32(out, FDT_BEGIN_NODE);
+    out.extend_from_slice(node.name.as_bytes());
+    out.push(0);
+    pad(out);
+    for p in &node.properties {
+        let mut value = Vec::new();
+        for v in &p.value {
+            match v {
+                Value::Bytes(bytes) => value.extend_from_slice(bytes),
+                Value::Cells(cells) => {
+                    for c in cells {
+                        if let Cell::Number(n) = c {
+                            value.extend_from_slice(&n.to_be_bytes());
+                        }
+                    }
+                }
+            }
+        }
+        push_u32(out, FDT_PROP);
+        push_u32(out, value.len() as u32);
+        push_u32(out, string_offset(strings, &p.name));
+        out.extend_from_slice(&value);
+        pad(out);
+    }
+    for child in &node.children {
+        write_node(child, out, strings);
+    }
+    push_u32(out, FDT_END_NODE);
+}
+
+fn string_offset(strings: &mut Vec<u8>, name: &str) -> u32 {
+    let mut start = 0;
+    while start < strings.len() {
+        let len = strings[start..].iter().position(|&b| b == 0).unwrap_or(0);
+        if &strings[start..start + len] == name.as_bytes() {
+            return start as u32;
+        }
+        start += len + 1;
+    }
+    strings.extend_from_slice(name.as_bytes());
+    strings.push(0);
+    start as u32
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn pad(out: &mut Vec<u8>) {
+    while out.len() % 4 != 0 {
+        out.push(0);
+    }
+}
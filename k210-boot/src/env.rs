@@ -0,0 +1,238 @@
+//! 保存在SPI闪存中的启动环境变量，格式和U-Boot的环境变量相似。
+//!
+//! 闪存中有两份副本，各占一个4KiB的扇区。每份副本（小端序）：
+//!
+//! | 偏移 | 长度 | 内容                           |
+//! |------|------|--------------------------------|
+//! | 0    | 4    | 其余部分的CRC32                |
+//! | 4    | 1    | 代数，每次保存加一             |
+//! | 5    | 4091 | `key=value\0`形式的变量，以空串结尾 |
+//!
+//! 启动时使用两份中有效并且代数较新的一份；保存时代数加一，写到另一份上。
+//! 这样写入时掉电也总有一份完整的副本，两个扇区轮流擦写。
+use crate::payload::crc32;
+
+pub const ENV_SIZE: usize = 0x1000;
+pub const ENV_DATA_SIZE: usize = ENV_SIZE - ENV_DATA_OFFSET;
+const ENV_GENERATION_OFFSET: usize = 4;
+const ENV_DATA_OFFSET: usize = 5;
+
+/// 控制台的波特率
+pub const ENV_BAUDRATE: &str = "baudrate";
+/// 下一阶段程序的入口地址，覆盖启动配置和交接区中的值
+pub const ENV_PAYLOAD_ADDRESS: &str = "payload_address";
+/// 写入设备树`/chosen/bootargs`的内核命令行
+pub const ENV_BOOTARGS: &str = "bootargs";
+/// 启动时的跟踪掩码，只有用`trace`特性编译时有效
+pub const ENV_TRACE_MASK: &str = "trace_mask";
+/// 启动方式，见`BootMode`
+pub const ENV_BOOT_MODE: &str = "bootmode";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvError {
+    BadCrc { expected: u32, actual: u32 },
+    // 变量不是`key=value`的形式，或者没有结尾
+    Malformed,
+    // 变量名为空，或者含有`=`和空字符
+    BadName,
+    // 值含有空字符
+    BadValue,
+    Full,
+}
+
+pub struct Env {
+    bytes: [u8; ENV_SIZE],
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Env {
+    /// 没有变量的环境，保存之前还需要`seal`
+    pub const fn new() -> Self {
+        Env {
+            bytes: [0; ENV_SIZE],
+        }
+    }
+
+    /// 检查一份副本，返回它的代数
+    pub fn check(bytes: &[u8; ENV_SIZE]) -> Result<u8, EnvError> {
+        let expected = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let actual = crc32(&bytes[ENV_GENERATION_OFFSET..]);
+        if expected != actual {
+            return Err(EnvError::BadCrc { expected, actual });
+        }
+        let mut data = &bytes[ENV_DATA_OFFSET..];
+        loop {
+            let len = data
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or(EnvError::Malformed)?;
+            if len == 0 {
+                break;
+            }
+            let entry = core::str::from_utf8(&data[..len]).map_err(|_| EnvError::Malformed)?;
+            match entry.split_once('=') {
+                Some((name, _)) if !name.is_empty() => {}
+                _ => return Err(EnvError::Malformed),
+            }
+            data = &data[len + 1..];
+        }
+        Ok(bytes[ENV_GENERATION_OFFSET])
+    }
+
+    /// 读取闪存时直接写入这里，之后必须用`check`检查
+    pub fn raw_mut(&mut self) -> &mut [u8; ENV_SIZE] {
+        &mut self.bytes
+    }
+
+    /// 要写入闪存的一份副本
+    pub fn as_bytes(&self) -> &[u8; ENV_SIZE] {
+        &self.bytes
+    }
+
+    pub fn generation(&self) -> u8 {
+        self.bytes[ENV_GENERATION_OFFSET]
+    }
+
+    /// 设置代数并重新计算CRC32
+    pub fn seal(&mut self, generation: u8) {
+        self.bytes[ENV_GENERATION_OFFSET] = generation;
+        let crc = crc32(&self.bytes[ENV_GENERATION_OFFSET..]);
+        self.bytes[..4].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    /// 所有变量，按保存的顺序
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.bytes[ENV_DATA_OFFSET..]
+            .split(|&b| b == 0)
+            .take_while(|entry| !entry.is_empty())
+            .filter_map(|entry| core::str::from_utf8(entry).ok()?.split_once('='))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.iter()
+            .find(|&(n, _)| n == name)
+            .map(|(_, value)| value)
+    }
+
+    /// 设置变量，新的值放在最后；返回内容是否改变，没有改变时不必写入闪存
+    pub fn set(&mut self, name: &str, value: &str) -> Result<bool, EnvError> {
+        if name.is_empty() || name.contains(['=', '\0']) {
+            return Err(EnvError::BadName);
+        }
+        if value.contains('\0') {
+            return Err(EnvError::BadValue);
+        }
+        if self.get(name) == Some(value) {
+            return Ok(false);
+        }
+        let used = self.used_len();
+        let old = self.find(name).map_or(0, |(_, len)| len + 1);
+        // 新的变量和结尾的空串
+        if used - old + name.len() + value.len() + 2 + 1 > ENV_DATA_SIZE {
+            return Err(EnvError::Full);
+        }
+        self.remove(name);
+        let data = &mut self.bytes[ENV_DATA_OFFSET..];
+        let mut at = used - old;
+        for part in [name.as_bytes(), b"=", value.as_bytes(), b"\0"] {
+            data[at..at + part.len()].copy_from_slice(part);
+            at += part.len();
+        }
+        data[at] = 0;
+        Ok(true)
+    }
+
+    /// 删除变量，返回它是否存在
+    pub fn remove(&mut self, name: &str) -> bool {
+        let Some((start, len)) = self.find(name) else {
+            return false;
+        };
+        let used = self.used_len();
+        let data = &mut self.bytes[ENV_DATA_OFFSET..];
+        data.copy_within(start + len + 1..used, start);
+        data[used - len - 1..used].fill(0);
+        true
+    }
+
+    // 变量在数据区中的位置和长度，不包括结尾的空字符
+    fn find(&self, name: &str) -> Option<(usize, usize)> {
+        let mut start = 0;
+        for entry in self.bytes[ENV_DATA_OFFSET..].split(|&b| b == 0) {
+            if entry.is_empty() {
+                break;
+            }
+            if entry.len() > name.len()
+                && entry.starts_with(name.as_bytes())
+                && entry[name.len()] == b'='
+            {
+                return Some((start, entry.len()));
+            }
+            start += entry.len() + 1;
+        }
+        None
+    }
+
+    // 数据区中变量占用的长度，不包括结尾的空串
+    fn used_len(&self) -> usize {
+        self.bytes[ENV_DATA_OFFSET..]
+            .split(|&b| b == 0)
+            .take_while(|entry| !entry.is_empty())
+            .map(|entry| entry.len() + 1)
+            .sum()
+    }
+}
+
+/// 根据两份副本的代数选出当前的一份，返回它的序号；都无效时返回`None`
+pub fn current(generations: [Option<u8>; 2]) -> Option<usize> {
+    match generations {
+        [Some(a), Some(b)] => Some(if (b.wrapping_sub(a) as i8) > 0 { 1 } else { 0 }),
+        [Some(_), None] => Some(0),
+        [None, Some(_)] => Some(1),
+        [None, None] => None,
+    }
+}
+
+/// 解析十进制或带`0x`前缀的十六进制数值
+pub fn parse_number(s: &str) -> Option<usize> {
+    let s = s.trim();
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// 环境变量`bootmode`的值，决定启动时从哪里加载下一阶段程序
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootMode {
+    /// 依次尝试编译时打开的所有方式，和没有设置时相同
+    Auto,
+    /// 只启动内存中（合并镜像里）的程序
+    Ram,
+    Flash,
+    Sd,
+    /// 总是等待串口传输内核，不需要按键
+    Serial,
+}
+
+impl BootMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "auto" => BootMode::Auto,
+            "ram" => BootMode::Ram,
+            "flash" => BootMode::Flash,
+            "sd" => BootMode::Sd,
+            "serial" => BootMode::Serial,
+            _ => return None,
+        })
+    }
+
+    /// 这种启动方式是否尝试从source加载
+    pub fn loads(self, source: BootMode) -> bool {
+        self == BootMode::Auto || self == source
+    }
+}
@@ -0,0 +1,392 @@
+//! 只读的FAT32文件系统，用于从SD卡加载下一阶段程序。
+//!
+//! 支持带MBR分区表的磁盘（使用第一个FAT32分区）和不分区、整个磁盘就是一个文件系统的情况。
+//! 查找文件时按长文件名或8.3短文件名匹配，不区分ASCII字母的大小写。扇区大小必须是512字节。
+
+pub const BLOCK_SIZE: usize = 512;
+
+/// 按512字节的块读取的存储设备
+pub trait BlockDevice {
+    type Error;
+    fn read_block(
+        &mut self,
+        lba: u32,
+        buf: &mut [u8; BLOCK_SIZE],
+    ) -> core::result::Result<(), Self::Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatError<E> {
+    Device(E),
+    // 没有找到FAT32文件系统
+    NoFilesystem,
+    NotFound,
+    NotAFile,
+    NotADirectory,
+    FileTooLarge { size: u32 },
+    // 簇链或目录项超出了文件系统的范围
+    Corrupted,
+}
+
+pub type Result<T, E> = core::result::Result<T, FatError<E>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileEntry {
+    pub first_cluster: u32,
+    pub size: u32,
+    pub is_dir: bool,
+}
+
+pub struct Volume<D> {
+    device: D,
+    fat_start: u32,
+    data_start: u32,
+    sectors_per_cluster: u32,
+    root_cluster: u32,
+    cluster_count: u32,
+    buf: [u8; BLOCK_SIZE],
+}
+
+const PARTITION_TABLE_OFFSET: usize = 446;
+const PARTITION_TYPE_FAT32_CHS: u8 = 0x0b;
+const PARTITION_TYPE_FAT32_LBA: u8 = 0x0c;
+
+const DIR_ENTRY_SIZE: usize = 32;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_LONG_NAME: u8 = 0x0f;
+const ENTRY_END: u8 = 0x00;
+const ENTRY_DELETED: u8 = 0xe5;
+// 短文件名的第一个字节真的是0xe5时，目录项中存放的是0x05
+const ENTRY_KANJI_E5: u8 = 0x05;
+const LFN_LAST: u8 = 0x40;
+const LFN_CHARS: usize = 13;
+const LFN_MAX: usize = 255;
+
+const FAT_ENTRY_MASK: u32 = 0x0fff_ffff;
+const FAT_END_OF_CHAIN: u32 = 0x0fff_fff8;
+
+impl<D: BlockDevice> Volume<D> {
+    pub fn open(device: D) -> Result<Self, D::Error> {
+        let mut volume = Volume {
+            device,
+            fat_start: 0,
+            data_start: 0,
+            sectors_per_cluster: 0,
+            root_cluster: 0,
+            cluster_count: 0,
+            buf: [0; BLOCK_SIZE],
+        };
+        volume.read(0)?;
+        if volume.mount(0).is_ok() {
+            return Ok(volume);
+        }
+        if le16(&volume.buf, 510) != 0xaa55 {
+            return Err(FatError::NoFilesystem);
+        }
+        let mut partitions = [0u32; 4];
+        for (i, start) in partitions.iter_mut().enumerate() {
+            let entry = &volume.buf[PARTITION_TABLE_OFFSET + i * 16..][..16];
+            if matches!(
+                entry[4],
+                PARTITION_TYPE_FAT32_CHS | PARTITION_TYPE_FAT32_LBA
+            ) {
+                *start = le32(entry, 8);
+            }
+        }
+        for start in partitions.into_iter().filter(|&start| start != 0) {
+            volume.read(start)?;
+            if volume.mount(start).is_ok() {
+                return Ok(volume);
+            }
+        }
+        Err(FatError::NoFilesystem)
+    }
+
+    // 从缓冲区中的引导扇区读取文件系统参数，只接受FAT32
+    fn mount(&mut self, start: u32) -> core::result::Result<(), ()> {
+        let b = &self.buf;
+        let sectors_per_cluster = b[13] as u32;
+        let reserved = le16(b, 14) as u32;
+        let fats = b[16] as u32;
+        let total = match le16(b, 19) {
+            0 => le32(b, 32),
+            total => total as u32,
+        };
+        let fat_size = le32(b, 36);
+        let fat32 = le16(b, 510) == 0xaa55
+            && le16(b, 11) as usize == BLOCK_SIZE
+            && sectors_per_cluster.is_power_of_two()
+            && reserved != 0
+            && fats != 0
+            && le16(b, 17) == 0
+            && le16(b, 22) == 0
+            && fat_size != 0;
+        if !fat32 {
+            return Err(());
+        }
+        let data_offset = reserved + fats * fat_size;
+        let data_sectors = total.checked_sub(data_offset).ok_or(())?;
+        // 簇号从2开始，FAT表中的项数也限制了簇的数量
+        let cluster_count =
+            (data_sectors / sectors_per_cluster).min(fat_size * (BLOCK_SIZE as u32 / 4) - 2);
+        self.fat_start = start + reserved;
+        self.data_start = start + data_offset;
+        self.sectors_per_cluster = sectors_per_cluster;
+        self.root_cluster = le32(b, 44);
+        self.cluster_count = cluster_count;
+        if !self.valid_cluster(self.root_cluster) {
+            return Err(());
+        }
+        Ok(())
+    }
+
+    /// 按绝对路径查找文件或目录，路径以`/`分隔
+    pub fn find(&mut self, path: &str) -> Result<FileEntry, D::Error> {
+        let mut entry = FileEntry {
+            first_cluster: self.root_cluster,
+            size: 0,
+            is_dir: true,
+        };
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            if !entry.is_dir {
+                return Err(FatError::NotADirectory);
+            }
+            entry = self.find_in(entry.first_cluster, name)?;
+        }
+        Ok(entry)
+    }
+
+    /// 把整个文件读入buf，返回文件长度
+    pub fn read_file(&mut self, file: &FileEntry, buf: &mut [u8]) -> Result<usize, D::Error> {
+        if file.is_dir {
+            return Err(FatError::NotAFile);
+        }
+        let size = file.size as usize;
+        if size > buf.len() {
+            return Err(FatError::FileTooLarge { size: file.size });
+        }
+        let mut cluster = Some(file.first_cluster);
+        let mut done = 0;
+        while done < size {
+            let current = cluster.ok_or(FatError::Corrupted)?;
+            if !self.valid_cluster(current) {
+                return Err(FatError::Corrupted);
+            }
+            let lba = self.cluster_lba(current);
+            for sector in 0..self.sectors_per_cluster {
+                if done == size {
+                    break;
+                }
+                let len = (size - done).min(BLOCK_SIZE);
+                if len == BLOCK_SIZE {
+                    let block = (&mut buf[done..done + BLOCK_SIZE]).try_into().unwrap();
+                    self.device
+                        .read_block(lba + sector, block)
+                        .map_err(FatError::Device)?;
+                } else {
+                    self.read(lba + sector)?;
+                    buf[done..done + len].copy_from_slice(&self.buf[..len]);
+                }
+                done += len;
+            }
+            cluster = self.next_cluster(current)?;
+        }
+        Ok(size)
+    }
+
+    fn find_in(&mut self, dir_cluster: u32, name: &str) -> Result<FileEntry, D::Error> {
+        let mut long_name = LongName::new();
+        let mut cluster = Some(dir_cluster);
+        // 防止簇链成环
+        let mut remaining = self.cluster_count;
+        while let Some(current) = cluster {
+            if !self.valid_cluster(current) || remaining == 0 {
+                return Err(FatError::Corrupted);
+            }
+            remaining -= 1;
+            let lba = self.cluster_lba(current);
+            for sector in 0..self.sectors_per_cluster {
+                self.read(lba + sector)?;
+                for entry in self.buf.chunks_exact(DIR_ENTRY_SIZE) {
+                    match entry[0] {
+                        ENTRY_END => return Err(FatError::NotFound),
+                        ENTRY_DELETED => {
+                            long_name.reset();
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    let attr = entry[11];
+                    if attr & 0x3f == ATTR_LONG_NAME {
+                        long_name.push(entry);
+                        continue;
+                    }
+                    let matched = attr & ATTR_VOLUME_ID == 0
+                        && (long_name.matches(name, short_name_checksum(entry))
+                            || short_name_matches(entry, name));
+                    long_name.reset();
+                    if matched {
+                        let first_cluster = (le16(entry, 20) as u32) << 16 | le16(entry, 26) as u32;
+                        let is_dir = attr & ATTR_DIRECTORY != 0;
+                        // 根目录的“..”记录为0号簇
+                        let first_cluster = if is_dir && first_cluster == 0 {
+                            self.root_cluster
+                        } else {
+                            first_cluster
+                        };
+                        return Ok(FileEntry {
+                            first_cluster,
+                            size: le32(entry, 28),
+                            is_dir,
+                        });
+                    }
+                }
+            }
+            cluster = self.next_cluster(current)?;
+        }
+        Err(FatError::NotFound)
+    }
+
+    fn next_cluster(&mut self, cluster: u32) -> Result<Option<u32>, D::Error> {
+        let offset = cluster as usize * 4;
+        self.read(self.fat_start + (offset / BLOCK_SIZE) as u32)?;
+        let next = le32(&self.buf, offset % BLOCK_SIZE) & FAT_ENTRY_MASK;
+        if next >= FAT_END_OF_CHAIN {
+            Ok(None)
+        } else if self.valid_cluster(next) {
+            Ok(Some(next))
+        } else {
+            Err(FatError::Corrupted)
+        }
+    }
+
+    fn valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster - 2 < self.cluster_count
+    }
+
+    fn cluster_lba(&self, cluster: u32) -> u32 {
+        self.data_start + (cluster - 2) * self.sectors_per_cluster
+    }
+
+    fn read(&mut self, lba: u32) -> Result<(), D::Error> {
+        self.device
+            .read_block(lba, &mut self.buf)
+            .map_err(FatError::Device)
+    }
+}
+
+// 短文件名目录项之前的长文件名目录项，按序号倒序排列
+struct LongName {
+    chars: [u16; LFN_MAX],
+    len: usize,
+    next: u8,
+    checksum: u8,
+    complete: bool,
+}
+
+impl LongName {
+    fn new() -> Self {
+        LongName {
+            chars: [0; LFN_MAX],
+            len: 0,
+            next: 0,
+            checksum: 0,
+            complete: false,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.next = 0;
+        self.complete = false;
+    }
+
+    fn push(&mut self, entry: &[u8]) {
+        let order = entry[0] & 0x1f;
+        if entry[0] & LFN_LAST != 0 {
+            self.len = 0;
+            self.checksum = entry[13];
+        } else if order != self.next || entry[13] != self.checksum {
+            self.reset();
+            return;
+        }
+        if order == 0 || order as usize > LFN_MAX.div_ceil(LFN_CHARS) {
+            self.reset();
+            return;
+        }
+        let base = (order as usize - 1) * LFN_CHARS;
+        let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+        for (i, &offset) in offsets.iter().enumerate() {
+            let c = le16(entry, offset);
+            if c == 0 || c == 0xffff {
+                break;
+            }
+            if base + i < LFN_MAX {
+                self.chars[base + i] = c;
+                self.len = self.len.max(base + i + 1);
+            }
+        }
+        self.next = order - 1;
+        self.complete = order == 1;
+    }
+
+    // 所有长文件名目录项都已读取，并且属于后面的短文件名目录项
+    fn matches(&self, name: &str, checksum: u8) -> bool {
+        if !self.complete || self.len == 0 || self.checksum != checksum {
+            return false;
+        }
+        let mut chars = self.chars[..self.len].iter();
+        name.encode_utf16()
+            .all(|c| chars.next().is_some_and(|&d| fold(c) == fold(d)))
+            && chars.next().is_none()
+    }
+}
+
+fn fold(c: u16) -> u16 {
+    if c < 0x80 {
+        (c as u8).to_ascii_lowercase() as u16
+    } else {
+        c
+    }
+}
+
+fn short_name_matches(entry: &[u8], name: &str) -> bool {
+    let mut short = [0u8; 12];
+    let mut len = 0;
+    for (i, &c) in entry[..8].iter().enumerate() {
+        if c == b' ' {
+            break;
+        }
+        short[len] = if i == 0 && c == ENTRY_KANJI_E5 {
+            ENTRY_DELETED
+        } else {
+            c
+        };
+        len += 1;
+    }
+    if entry[8] != b' ' {
+        short[len] = b'.';
+        len += 1;
+        for &c in entry[8..11].iter().take_while(|&&c| c != b' ') {
+            short[len] = c;
+            len += 1;
+        }
+    }
+    short[..len].eq_ignore_ascii_case(name.as_bytes())
+}
+
+fn short_name_checksum(entry: &[u8]) -> u8 {
+    entry[..11]
+        .iter()
+        .fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c))
+}
+
+fn le16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn le32(bytes: &[u8], offset: usize) -> u32 {
+    let mut word = [0; 4];
+    word.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(word)
+}
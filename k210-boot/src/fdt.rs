@@ -0,0 +1,493 @@
+//! 扁平设备树（FDT）的就地编辑器。
+//!
+//! `Fdt::open_into`把设备树复制到缓冲区，整理成“头部、保留内存表、结构块、字符串块、空闲空间”的布局，
+//! 之后修改属性和增加节点时只需要移动结构块后面的数据。节点用它在结构块中的偏移量表示；
+//! 修改节点自己的属性或增加子节点不会改变它的偏移量，但位于它之后的节点的偏移量会失效，需要重新查找。
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_VERSION: u32 = 17;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+const HEADER_SIZE: usize = 40;
+const RSVMAP_ENTRY_SIZE: usize = 16;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+// 头部各字段的偏移量
+const OFF_MAGIC: usize = 0;
+const OFF_TOTALSIZE: usize = 4;
+const OFF_DT_STRUCT: usize = 8;
+const OFF_DT_STRINGS: usize = 12;
+const OFF_MEM_RSVMAP: usize = 16;
+const OFF_VERSION: usize = 20;
+const OFF_LAST_COMP_VERSION: usize = 24;
+const OFF_BOOT_CPUID_PHYS: usize = 28;
+const OFF_SIZE_DT_STRINGS: usize = 32;
+const OFF_SIZE_DT_STRUCT: usize = 36;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FdtError {
+    BadMagic,
+    // 需要第17版，结构块大小字段从这一版开始才有
+    BadVersion,
+    // 头部记录的偏移量或长度超出了设备树
+    Truncated,
+    // 结构块中出现了不认识的标记，或者节点没有正确结束
+    BadStructure,
+    // 缓冲区放不下修改后的设备树
+    NoSpace,
+    NotFound,
+}
+
+pub type Result<T> = core::result::Result<T, FdtError>;
+
+pub struct Fdt<'a> {
+    buf: &'a mut [u8],
+}
+
+impl<'a> Fdt<'a> {
+    /// 把设备树复制到缓冲区并整理布局，缓冲区剩余的空间用于之后的修改
+    pub fn open_into(blob: &[u8], buf: &'a mut [u8]) -> Result<Self> {
+        if blob.len() < HEADER_SIZE {
+            return Err(FdtError::Truncated);
+        }
+        if be32(blob, OFF_MAGIC) != FDT_MAGIC {
+            return Err(FdtError::BadMagic);
+        }
+        if be32(blob, OFF_VERSION) < FDT_VERSION {
+            return Err(FdtError::BadVersion);
+        }
+        let totalsize = be32(blob, OFF_TOTALSIZE) as usize;
+        if totalsize < HEADER_SIZE || totalsize > blob.len() {
+            return Err(FdtError::Truncated);
+        }
+        let blob = &blob[..totalsize];
+        let struct_range = range(blob, OFF_DT_STRUCT, OFF_SIZE_DT_STRUCT)?;
+        let strings_range = range(blob, OFF_DT_STRINGS, OFF_SIZE_DT_STRINGS)?;
+        // 结构块由32位的标记组成，保留内存表由64位数组成
+        if struct_range.start % 4 != 0
+            || struct_range.len() % 4 != 0
+            || be32(blob, OFF_MEM_RSVMAP) % 8 != 0
+        {
+            return Err(FdtError::BadStructure);
+        }
+        // 保留内存表以全零的表项结束
+        let rsvmap_start = be32(blob, OFF_MEM_RSVMAP) as usize;
+        let mut rsvmap_end = rsvmap_start;
+        loop {
+            let entry = blob
+                .get(rsvmap_end..rsvmap_end + RSVMAP_ENTRY_SIZE)
+                .ok_or(FdtError::Truncated)?;
+            rsvmap_end += RSVMAP_ENTRY_SIZE;
+            if entry.iter().all(|&b| b == 0) {
+                break;
+            }
+        }
+
+        let rsvmap_len = rsvmap_end - rsvmap_start;
+        let struct_len = struct_range.len();
+        let strings_len = strings_range.len();
+        let off_struct = HEADER_SIZE + rsvmap_len;
+        let off_strings = off_struct + struct_len;
+        let new_totalsize = off_strings + strings_len;
+        if new_totalsize > buf.len() {
+            return Err(FdtError::NoSpace);
+        }
+        buf[..HEADER_SIZE].fill(0);
+        buf[HEADER_SIZE..off_struct].copy_from_slice(&blob[rsvmap_start..rsvmap_end]);
+        buf[off_struct..off_strings].copy_from_slice(&blob[struct_range]);
+        buf[off_strings..new_totalsize].copy_from_slice(&blob[strings_range]);
+        set_be32(buf, OFF_MAGIC, FDT_MAGIC);
+        set_be32(buf, OFF_TOTALSIZE, new_totalsize as u32);
+        set_be32(buf, OFF_DT_STRUCT, off_struct as u32);
+        set_be32(buf, OFF_DT_STRINGS, off_strings as u32);
+        set_be32(buf, OFF_MEM_RSVMAP, HEADER_SIZE as u32);
+        set_be32(buf, OFF_VERSION, FDT_VERSION);
+        set_be32(buf, OFF_LAST_COMP_VERSION, FDT_LAST_COMP_VERSION);
+        set_be32(buf, OFF_BOOT_CPUID_PHYS, be32(blob, OFF_BOOT_CPUID_PHYS));
+        set_be32(buf, OFF_SIZE_DT_STRINGS, strings_len as u32);
+        set_be32(buf, OFF_SIZE_DT_STRUCT, struct_len as u32);
+
+        let fdt = Fdt { buf };
+        // 检查结构块是否完整，之后遍历时就不用再处理越界
+        let end = fdt.skip_node(0)?;
+        if fdt.token(end)? != FDT_END {
+            return Err(FdtError::BadStructure);
+        }
+        Ok(fdt)
+    }
+
+    /// 修改后的设备树
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.totalsize()]
+    }
+
+    pub fn totalsize(&self) -> usize {
+        be32(self.buf, OFF_TOTALSIZE) as usize
+    }
+
+    /// 按路径查找节点，例如`/cpus/cpu@0`；路径中不带`@`的部分可以匹配带单元地址的节点名
+    pub fn find_node(&self, path: &str) -> Option<usize> {
+        let mut node = 0;
+        for name in path.split('/').filter(|s| !s.is_empty()) {
+            node = self.subnode(node, name)?;
+        }
+        Some(node)
+    }
+
+    pub fn subnode(&self, parent: usize, name: &str) -> Option<usize> {
+        self.children(parent).find(|&child| {
+            let child_name = self.node_name(child);
+            child_name == name
+                || (!name.contains('@') && child_name.split('@').next() == Some(name))
+        })
+    }
+
+    /// 节点的直接子节点
+    pub fn children(&self, parent: usize) -> impl Iterator<Item = usize> + '_ {
+        let mut offset = self.first_after_properties(parent);
+        core::iter::from_fn(move || {
+            let child = offset?;
+            if self.token(child).ok()? != FDT_BEGIN_NODE {
+                offset = None;
+                return None;
+            }
+            offset = self.skip_node(child).ok();
+            Some(child)
+        })
+    }
+
+    pub fn node_name(&self, node: usize) -> &str {
+        let start = self.off_struct() + node + 4;
+        let len = self.buf[start..].iter().position(|&b| b == 0).unwrap_or(0);
+        core::str::from_utf8(&self.buf[start..start + len]).unwrap_or("")
+    }
+
+    pub fn property(&self, node: usize, name: &str) -> Option<&[u8]> {
+        let prop = self.find_property(node, name)?;
+        let start = self.off_struct() + prop + 12;
+        let len = self.be32_struct(prop + 4) as usize;
+        Some(&self.buf[start..start + len])
+    }
+
+    pub fn set_property(&mut self, node: usize, name: &str, value: &[u8]) -> Result<()> {
+        self.reserve(node, name, value.len())?;
+        if let Some(prop) = self.find_property(node, name) {
+            let old_len = self.be32_struct(prop + 4) as usize;
+            let data = self.off_struct() + prop + 12;
+            self.splice(data, align4(old_len), value)?;
+            set_be32(self.buf, self.off_struct() + prop + 4, value.len() as u32);
+            return Ok(());
+        }
+        let name_offset = self.string_offset(name)?;
+        // 新属性放在节点名后面，保证属性位于所有子节点之前
+        let at = self.off_struct() + self.name_end(node);
+        let mut head = [0u8; 12];
+        head[..4].copy_from_slice(&FDT_PROP.to_be_bytes());
+        head[4..8].copy_from_slice(&(value.len() as u32).to_be_bytes());
+        head[8..].copy_from_slice(&(name_offset as u32).to_be_bytes());
+        self.splice(at, 0, &head)?;
+        self.splice(at + head.len(), 0, value)
+    }
+
+    pub fn set_property_str(&mut self, node: usize, name: &str, value: &str) -> Result<()> {
+        let total = value.len() + 1;
+        self.set_property_with(node, name, total, |data| {
+            data[..value.len()].copy_from_slice(value.as_bytes());
+            data[value.len()] = 0;
+        })
+    }
+
+    pub fn set_property_u32(&mut self, node: usize, name: &str, value: u32) -> Result<()> {
+        self.set_property(node, name, &value.to_be_bytes())
+    }
+
+    /// 按单元写入一组32位数，例如`reg`和`ranges`
+    pub fn set_property_cells(&mut self, node: usize, name: &str, cells: &[u32]) -> Result<()> {
+        self.set_property_with(node, name, cells.len() * 4, |data| {
+            for (chunk, cell) in data.chunks_exact_mut(4).zip(cells) {
+                chunk.copy_from_slice(&cell.to_be_bytes());
+            }
+        })
+    }
+
+    /// 增加空的子节点；已经存在同名子节点时直接返回它
+    pub fn add_subnode(&mut self, parent: usize, name: &str) -> Result<usize> {
+        if let Some(node) = self.children(parent).find(|&c| self.node_name(c) == name) {
+            return Ok(node);
+        }
+        let at = self
+            .first_after_properties(parent)
+            .ok_or(FdtError::BadStructure)?;
+        let name_len = align4(name.len() + 1);
+        let total = 4 + name_len + 4;
+        let abs = self.off_struct() + at;
+        self.make_room(abs, total)?;
+        let data = &mut self.buf[abs..abs + total];
+        data.fill(0);
+        data[..4].copy_from_slice(&FDT_BEGIN_NODE.to_be_bytes());
+        data[4..4 + name.len()].copy_from_slice(name.as_bytes());
+        data[4 + name_len..].copy_from_slice(&FDT_END_NODE.to_be_bytes());
+        Ok(at)
+    }
+
+    fn set_property_with(
+        &mut self,
+        node: usize,
+        name: &str,
+        len: usize,
+        fill: impl FnOnce(&mut [u8]),
+    ) -> Result<()> {
+        // 先写入长度正确的占位值，再就地填写内容，避免在栈上准备任意长度的缓冲区
+        self.reserve(node, name, len)?;
+        self.set_property(node, name, &[])?;
+        let prop = self.find_property(node, name).ok_or(FdtError::NotFound)?;
+        let data = self.off_struct() + prop + 12;
+        self.make_room(data, align4(len))?;
+        set_be32(self.buf, self.off_struct() + prop + 4, len as u32);
+        let area = &mut self.buf[data..data + align4(len)];
+        area.fill(0);
+        fill(&mut area[..len]);
+        Ok(())
+    }
+
+    // 修改属性之前检查空间，空间不够时不做任何修改
+    fn reserve(&self, node: usize, name: &str, len: usize) -> Result<()> {
+        let needed = match self.find_property(node, name) {
+            Some(prop) => align4(len).saturating_sub(align4(self.be32_struct(prop + 4) as usize)),
+            None => {
+                let string = match self.find_string(name) {
+                    Some(_) => 0,
+                    None => name.len() + 1,
+                };
+                12 + align4(len) + string
+            }
+        };
+        if self.totalsize() + needed > self.buf.len() {
+            return Err(FdtError::NoSpace);
+        }
+        Ok(())
+    }
+
+    fn find_property(&self, node: usize, name: &str) -> Option<usize> {
+        let mut offset = self.name_end(node);
+        loop {
+            match self.token(offset).ok()? {
+                FDT_PROP => {
+                    let name_offset = self.be32_struct(offset + 8) as usize;
+                    if self.string_at(name_offset) == Some(name) {
+                        return Some(offset);
+                    }
+                    offset = self.skip_property(offset);
+                }
+                FDT_NOP => offset += 4,
+                _ => return None,
+            }
+        }
+    }
+
+    // 节点中属性之后第一个标记的位置，也就是第一个子节点或者FDT_END_NODE
+    fn first_after_properties(&self, node: usize) -> Option<usize> {
+        let mut offset = self.name_end(node);
+        loop {
+            match self.token(offset).ok()? {
+                FDT_PROP => offset = self.skip_property(offset),
+                FDT_NOP => offset += 4,
+                _ => return Some(offset),
+            }
+        }
+    }
+
+    // 跳过整个节点，返回节点结束之后的位置
+    fn skip_node(&self, node: usize) -> Result<usize> {
+        if self.token(node)? != FDT_BEGIN_NODE {
+            return Err(FdtError::BadStructure);
+        }
+        let mut depth = 0usize;
+        let mut offset = node;
+        loop {
+            match self.token(offset)? {
+                FDT_BEGIN_NODE => {
+                    depth += 1;
+                    offset = self.name_end(offset);
+                }
+                FDT_END_NODE => {
+                    depth -= 1;
+                    offset += 4;
+                    if depth == 0 {
+                        return Ok(offset);
+                    }
+                }
+                FDT_PROP => {
+                    if offset + 12 > self.size_struct() {
+                        return Err(FdtError::BadStructure);
+                    }
+                    // 属性名必须是字符串块中以0结尾的字符串
+                    let name_offset = self.be32_struct(offset + 8) as usize;
+                    if self.string_at(name_offset).is_none() {
+                        return Err(FdtError::BadStructure);
+                    }
+                    offset = self.skip_property(offset);
+                }
+                FDT_NOP => offset += 4,
+                _ => return Err(FdtError::BadStructure),
+            }
+            if offset > self.size_struct() {
+                return Err(FdtError::BadStructure);
+            }
+        }
+    }
+
+    fn skip_property(&self, prop: usize) -> usize {
+        prop + 12 + align4(self.be32_struct(prop + 4) as usize)
+    }
+
+    // 节点名之后的位置
+    fn name_end(&self, node: usize) -> usize {
+        let start = self.off_struct() + node + 4;
+        let end = self.off_struct() + self.size_struct();
+        let len = self.buf[start..end]
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(end - start);
+        node + 4 + align4(len + 1)
+    }
+
+    fn token(&self, offset: usize) -> Result<u32> {
+        if offset + 4 > self.size_struct() {
+            return Err(FdtError::BadStructure);
+        }
+        Ok(self.be32_struct(offset))
+    }
+
+    fn be32_struct(&self, offset: usize) -> u32 {
+        be32(self.buf, self.off_struct() + offset)
+    }
+
+    fn string_at(&self, offset: usize) -> Option<&str> {
+        let strings = &self.buf[self.off_strings()..self.off_strings() + self.size_strings()];
+        let s = strings.get(offset..)?;
+        let len = s.iter().position(|&b| b == 0)?;
+        core::str::from_utf8(&s[..len]).ok()
+    }
+
+    fn find_string(&self, name: &str) -> Option<usize> {
+        let strings = &self.buf[self.off_strings()..self.off_strings() + self.size_strings()];
+        let mut start = 0;
+        while start < strings.len() {
+            let Some(len) = strings[start..].iter().position(|&b| b == 0) else {
+                break;
+            };
+            if &strings[start..start + len] == name.as_bytes() {
+                return Some(start);
+            }
+            start += len + 1;
+        }
+        None
+    }
+
+    // 字符串块中名字的偏移量，没有时追加到字符串块末尾
+    fn string_offset(&mut self, name: &str) -> Result<usize> {
+        if let Some(offset) = self.find_string(name) {
+            return Ok(offset);
+        }
+        let offset = self.size_strings();
+        let at = self.totalsize();
+        if at + name.len() + 1 > self.buf.len() {
+            return Err(FdtError::NoSpace);
+        }
+        self.buf[at..at + name.len()].copy_from_slice(name.as_bytes());
+        self.buf[at + name.len()] = 0;
+        set_be32(
+            self.buf,
+            OFF_SIZE_DT_STRINGS,
+            (offset + name.len() + 1) as u32,
+        );
+        set_be32(self.buf, OFF_TOTALSIZE, (at + name.len() + 1) as u32);
+        Ok(offset)
+    }
+
+    // 把结构块中at开始的remove字节替换为insert（长度补齐到4字节）
+    fn splice(&mut self, at: usize, remove: usize, insert: &[u8]) -> Result<()> {
+        let insert_len = align4(insert.len());
+        if insert_len >= remove {
+            self.make_room(at + remove, insert_len - remove)?;
+        } else {
+            self.remove_bytes(at + insert_len, remove - insert_len);
+        }
+        let area = &mut self.buf[at..at + insert_len];
+        area.fill(0);
+        area[..insert.len()].copy_from_slice(insert);
+        Ok(())
+    }
+
+    // 在结构块的at处插入len字节的空间，后面的结构块和字符串块整体后移
+    fn make_room(&mut self, at: usize, len: usize) -> Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        let total = self.totalsize();
+        if total + len > self.buf.len() {
+            return Err(FdtError::NoSpace);
+        }
+        self.buf.copy_within(at..total, at + len);
+        self.resize_struct(len as isize);
+        Ok(())
+    }
+
+    fn remove_bytes(&mut self, at: usize, len: usize) {
+        let total = self.totalsize();
+        self.buf.copy_within(at + len..total, at);
+        self.resize_struct(-(len as isize));
+    }
+
+    fn resize_struct(&mut self, delta: isize) {
+        let resize = |v: usize| (v as isize + delta) as u32;
+        let (total, strings, size) = (self.totalsize(), self.off_strings(), self.size_struct());
+        set_be32(self.buf, OFF_TOTALSIZE, resize(total));
+        set_be32(self.buf, OFF_DT_STRINGS, resize(strings));
+        set_be32(self.buf, OFF_SIZE_DT_STRUCT, resize(size));
+    }
+
+    fn off_struct(&self) -> usize {
+        be32(self.buf, OFF_DT_STRUCT) as usize
+    }
+
+    fn size_struct(&self) -> usize {
+        be32(self.buf, OFF_SIZE_DT_STRUCT) as usize
+    }
+
+    fn off_strings(&self) -> usize {
+        be32(self.buf, OFF_DT_STRINGS) as usize
+    }
+
+    fn size_strings(&self) -> usize {
+        be32(self.buf, OFF_SIZE_DT_STRINGS) as usize
+    }
+}
+
+fn range(blob: &[u8], off: usize, size: usize) -> Result<core::ops::Range<usize>> {
+    let start = be32(blob, off) as usize;
+    let end = start + be32(blob, size) as usize;
+    if end > blob.len() {
+        return Err(FdtError::Truncated);
+    }
+    Ok(start..end)
+}
+
+fn be32(buf: &[u8], offset: usize) -> u32 {
+    let mut word = [0; 4];
+    word.copy_from_slice(&buf[offset..offset + 4]);
+    u32::from_be_bytes(word)
+}
+
+fn set_be32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+const fn align4(len: usize) -> usize {
+    (len + 3) & !3
+}
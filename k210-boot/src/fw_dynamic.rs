@@ -0,0 +1,118 @@
+//! OpenSBI的`fw_dynamic_info`结构，前一级引导程序用它告诉固件下一阶段程序的位置、
+//! 特权级和启动核。布局和OpenSBI的`include/sbi/fw_dynamic.h`相同，这里只支持64位。
+
+/// `fw_dynamic_info`的魔数，即字符串"OSBI"
+pub const FW_DYNAMIC_INFO_MAGIC: u64 = 0x4942_534f;
+/// 第2版增加了`boot_hart`字段
+pub const FW_DYNAMIC_INFO_VERSION_MAX: u64 = 2;
+
+pub const NEXT_MODE_U: u64 = 0;
+pub const NEXT_MODE_S: u64 = 1;
+pub const NEXT_MODE_M: u64 = 3;
+
+/// 不输出启动信息
+pub const OPTION_NO_BOOT_PRINTS: u64 = 1 << 0;
+
+/// 不指定启动核，由固件决定
+pub const BOOT_HART_ANY: u64 = u64::MAX;
+
+pub const FW_DYNAMIC_INFO_SIZE: usize = 6 * 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct FwDynamicInfo {
+    pub magic: u64,
+    pub version: u64,
+    pub next_addr: u64,
+    pub next_mode: u64,
+    pub options: u64,
+    pub boot_hart: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FwDynamicError {
+    BadMagic(u64),
+    UnsupportedVersion(u64),
+    // 只能进入特权级或用户级
+    UnsupportedMode(u64),
+    BadBootHart(u64),
+}
+
+impl FwDynamicInfo {
+    pub const fn new(next_addr: u64, next_mode: u64, options: u64, boot_hart: u64) -> Self {
+        FwDynamicInfo {
+            magic: FW_DYNAMIC_INFO_MAGIC,
+            version: FW_DYNAMIC_INFO_VERSION_MAX,
+            next_addr,
+            next_mode,
+            options,
+            boot_hart,
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < FW_DYNAMIC_INFO_SIZE {
+            return None;
+        }
+        let field = |i: usize| {
+            let mut word = [0; 8];
+            word.copy_from_slice(&bytes[i * 8..i * 8 + 8]);
+            u64::from_le_bytes(word)
+        };
+        Some(FwDynamicInfo {
+            magic: field(0),
+            version: field(1),
+            next_addr: field(2),
+            next_mode: field(3),
+            options: field(4),
+            boot_hart: field(5),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; FW_DYNAMIC_INFO_SIZE] {
+        let mut bytes = [0; FW_DYNAMIC_INFO_SIZE];
+        let fields = [
+            self.magic,
+            self.version,
+            self.next_addr,
+            self.next_mode,
+            self.options,
+            self.boot_hart,
+        ];
+        for (chunk, field) in bytes.chunks_exact_mut(8).zip(fields) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn validate(&self, num_harts: usize) -> Result<(), FwDynamicError> {
+        if self.magic != FW_DYNAMIC_INFO_MAGIC {
+            return Err(FwDynamicError::BadMagic(self.magic));
+        }
+        if self.version == 0 || self.version > FW_DYNAMIC_INFO_VERSION_MAX {
+            return Err(FwDynamicError::UnsupportedVersion(self.version));
+        }
+        if self.next_mode != NEXT_MODE_S && self.next_mode != NEXT_MODE_U {
+            return Err(FwDynamicError::UnsupportedMode(self.next_mode));
+        }
+        if let Some(hart) = self.boot_hart() {
+            if hart >= num_harts as u64 {
+                return Err(FwDynamicError::BadBootHart(hart));
+            }
+        }
+        Ok(())
+    }
+
+    /// 第1版没有`boot_hart`字段，和`BOOT_HART_ANY`一样返回`None`
+    pub fn boot_hart(&self) -> Option<u64> {
+        if self.version < 2 || self.boot_hart == BOOT_HART_ANY {
+            None
+        } else {
+            Some(self.boot_hart)
+        }
+    }
+
+    pub fn no_boot_prints(&self) -> bool {
+        self.options & OPTION_NO_BOOT_PRINTS != 0
+    }
+}
@@ -0,0 +1,741 @@
+//! GDB远程串行协议（RSP）的桩，由rustsbi-k210的`gdb-stub`特性使用。
+//!
+//! 这里只处理协议本身：收发包和校验和、解析请求、软件断点和单步。寄存器和内存通过`Target`
+//! 由固件访问，这样整个协议可以在主机上测试。只有一个线程，也就是停下来的核；寄存器按照
+//! GDB的RISC-V编号，0到31是x0到x31，32是pc，都是小端序的64位数。
+//!
+//! 单步的做法是预测下一条指令的地址（见`next_pc`），在那里临时写入断点后继续运行。
+
+/// 一个包的最大长度，`qSupported`时告诉GDB
+pub const PACKET_SIZE: usize = 0x400;
+/// GDB同时插入的软件断点数量上限
+pub const MAX_BREAKPOINTS: usize = 16;
+pub const NUM_REGISTERS: usize = 33;
+pub const PC: usize = 32;
+
+/// 停止原因，和GDB的信号编号相同
+pub const SIGILL: u8 = 4;
+pub const SIGTRAP: u8 = 5;
+pub const SIGSEGV: u8 = 11;
+
+const EBREAK: [u8; 4] = 0x0010_0073u32.to_le_bytes();
+const C_EBREAK: [u8; 2] = 0x9002u16.to_le_bytes();
+const SRET: u32 = 0x1020_0073;
+
+const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &b| sum.wrapping_add(b))
+}
+
+fn hex_digit(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// 解析地址和长度这样的十六进制数，高位在前
+pub fn parse_hex(s: &[u8]) -> Option<usize> {
+    if s.is_empty() || s.len() > 16 {
+        return None;
+    }
+    s.iter()
+        .try_fold(0, |n, &b| Some(n << 4 | hex_digit(b)? as usize))
+}
+
+/// 把十六进制串解码到dst，长度必须正好是dst的两倍
+pub fn decode_hex(s: &[u8], dst: &mut [u8]) -> Option<()> {
+    if s.len() != dst.len() * 2 {
+        return None;
+    }
+    for (pair, byte) in s.chunks(2).zip(dst) {
+        *byte = hex_digit(pair[0])? << 4 | hex_digit(pair[1])?;
+    }
+    Some(())
+}
+
+/// 从串口收到一个字节后的结果
+#[derive(Debug, PartialEq, Eq)]
+pub enum Event<'a> {
+    /// 校验和正确的包，已经去掉了转义；需要回复`+`
+    Packet(&'a [u8]),
+    /// 校验和错误或者太长，需要回复`-`让GDB重发
+    BadPacket,
+    /// GDB确认收到了上一个回复
+    Ack,
+    /// GDB要求重发上一个回复
+    Nack,
+    /// Ctrl-C
+    Interrupt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReadState {
+    Idle,
+    Data,
+    Escape,
+    Checksum(Option<u8>),
+}
+
+/// 把串口收到的字节拼成包：`$数据#两位十六进制校验和`
+pub struct PacketReader {
+    buf: [u8; PACKET_SIZE],
+    len: usize,
+    sum: u8,
+    overflow: bool,
+    state: ReadState,
+}
+
+impl Default for PacketReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PacketReader {
+    pub const fn new() -> Self {
+        PacketReader {
+            buf: [0; PACKET_SIZE],
+            len: 0,
+            sum: 0,
+            overflow: false,
+            state: ReadState::Idle,
+        }
+    }
+
+    pub fn feed(&mut self, byte: u8) -> Option<Event<'_>> {
+        match (self.state, byte) {
+            // 包以外的字节，除了确认和Ctrl-C都忽略
+            (ReadState::Idle, b'+') => return Some(Event::Ack),
+            (ReadState::Idle, b'-') => return Some(Event::Nack),
+            (ReadState::Idle, 0x03) => return Some(Event::Interrupt),
+            (ReadState::Idle, b'$') => self.start(),
+            (ReadState::Idle, _) => {}
+            // 包还没有结束又收到了开头，丢弃前面的部分
+            (ReadState::Data, b'$') => self.start(),
+            (ReadState::Data, b'#') => self.state = ReadState::Checksum(None),
+            (ReadState::Data, b'}') => {
+                self.sum = self.sum.wrapping_add(byte);
+                self.state = ReadState::Escape;
+            }
+            (ReadState::Data, _) | (ReadState::Escape, _) => {
+                self.sum = self.sum.wrapping_add(byte);
+                let data = if self.state == ReadState::Escape {
+                    byte ^ 0x20
+                } else {
+                    byte
+                };
+                if self.len < PACKET_SIZE {
+                    self.buf[self.len] = data;
+                    self.len += 1;
+                } else {
+                    self.overflow = true;
+                }
+                self.state = ReadState::Data;
+            }
+            (ReadState::Checksum(None), _) => match hex_digit(byte) {
+                Some(high) => self.state = ReadState::Checksum(Some(high)),
+                None => return self.bad(),
+            },
+            (ReadState::Checksum(Some(high)), _) => {
+                self.state = ReadState::Idle;
+                match hex_digit(byte) {
+                    Some(low) if high << 4 | low == self.sum && !self.overflow => {
+                        return Some(Event::Packet(&self.buf[..self.len]))
+                    }
+                    _ => return self.bad(),
+                }
+            }
+        }
+        None
+    }
+
+    fn start(&mut self) {
+        self.len = 0;
+        self.sum = 0;
+        self.overflow = false;
+        self.state = ReadState::Data;
+    }
+
+    fn bad(&mut self) -> Option<Event<'_>> {
+        self.state = ReadState::Idle;
+        Some(Event::BadPacket)
+    }
+}
+
+/// 要发送给GDB的一个包，保留到GDB确认为止，以便重发
+pub struct Response {
+    buf: [u8; PACKET_SIZE + 4],
+    len: usize,
+}
+
+impl Default for Response {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Response {
+    pub const fn new() -> Self {
+        let mut buf = [0; PACKET_SIZE + 4];
+        buf[0] = b'$';
+        Response { buf, len: 1 }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 1;
+    }
+
+    /// 写入数据，需要时转义；超过包的最大长度的部分被丢弃
+    pub fn push(&mut self, data: &[u8]) {
+        for &byte in data {
+            let escaped = matches!(byte, b'$' | b'#' | b'}' | b'*');
+            let needed = if escaped { 2 } else { 1 };
+            if self.len + needed > PACKET_SIZE + 1 {
+                return;
+            }
+            if escaped {
+                self.buf[self.len] = b'}';
+                self.buf[self.len + 1] = byte ^ 0x20;
+            } else {
+                self.buf[self.len] = byte;
+            }
+            self.len += needed;
+        }
+    }
+
+    /// 按十六进制写入，每个字节两个字符
+    pub fn push_hex(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.push(&[DIGITS[(byte >> 4) as usize], DIGITS[(byte & 0xf) as usize]]);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 1
+    }
+
+    /// 加上结尾和校验和，返回要发送的全部字节
+    pub fn finish(&mut self) -> &[u8] {
+        let sum = checksum(&self.buf[1..self.len]);
+        let end = [
+            b'#',
+            DIGITS[(sum >> 4) as usize],
+            DIGITS[(sum & 0xf) as usize],
+        ];
+        self.buf[self.len..self.len + 3].copy_from_slice(&end);
+        &self.buf[..self.len + 3]
+    }
+}
+
+/// GDB的请求，只列出桩支持的部分
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request<'a> {
+    /// `?`
+    StopReason,
+    /// `g`
+    ReadRegisters,
+    /// `G`，所有寄存器的十六进制
+    WriteRegisters(&'a [u8]),
+    /// `p`
+    ReadRegister(usize),
+    /// `P`，寄存器的值是十六进制的小端序字节
+    WriteRegister(usize, &'a [u8]),
+    /// `m`
+    ReadMemory { address: usize, len: usize },
+    /// `M`，数据是十六进制
+    WriteMemory { address: usize, data: &'a [u8] },
+    /// `c`和`C`，可以指定继续的地址；忽略`C`带的信号
+    Continue(Option<usize>),
+    /// `s`和`S`
+    Step(Option<usize>),
+    /// `Z0`
+    InsertBreakpoint { address: usize, kind: usize },
+    /// `z0`
+    RemoveBreakpoint { address: usize, kind: usize },
+    /// `qSupported`
+    Supported,
+    /// `qAttached`
+    Attached,
+    /// `H`，只有一个线程，总是成功
+    SetThread,
+    /// `D`
+    Detach,
+    /// `k`
+    Kill,
+    /// 桩不支持的请求，回复空包
+    Unsupported,
+}
+
+/// 解析一个包；格式错误时返回`None`，应当回复错误
+pub fn parse_request(packet: &[u8]) -> Option<Request<'_>> {
+    let Some((&command, args)) = packet.split_first() else {
+        return Some(Request::Unsupported);
+    };
+    Some(match command {
+        b'?' => Request::StopReason,
+        b'g' => Request::ReadRegisters,
+        b'G' => Request::WriteRegisters(args),
+        b'p' => Request::ReadRegister(parse_hex(args)?),
+        b'P' => {
+            let (number, value) = split(args, b'=')?;
+            Request::WriteRegister(parse_hex(number)?, value)
+        }
+        b'm' => {
+            let (address, len) = split(args, b',')?;
+            Request::ReadMemory {
+                address: parse_hex(address)?,
+                len: parse_hex(len)?,
+            }
+        }
+        b'M' => {
+            let (range, data) = split(args, b':')?;
+            let (address, len) = split(range, b',')?;
+            if parse_hex(len)? * 2 != data.len() {
+                return None;
+            }
+            Request::WriteMemory {
+                address: parse_hex(address)?,
+                data,
+            }
+        }
+        b'c' => Request::Continue(optional_hex(args)?),
+        b's' => Request::Step(optional_hex(args)?),
+        // `C信号;地址`
+        b'C' | b'S' => {
+            let address = match split(args, b';') {
+                Some((_, address)) => Some(parse_hex(address)?),
+                None => None,
+            };
+            if command == b'C' {
+                Request::Continue(address)
+            } else {
+                Request::Step(address)
+            }
+        }
+        b'Z' | b'z' => {
+            let (kind_of, rest) = split(args, b',')?;
+            if kind_of != b"0" {
+                return Some(Request::Unsupported);
+            }
+            let (address, kind) = split(rest, b',')?;
+            let (address, kind) = (parse_hex(address)?, parse_hex(kind)?);
+            if command == b'Z' {
+                Request::InsertBreakpoint { address, kind }
+            } else {
+                Request::RemoveBreakpoint { address, kind }
+            }
+        }
+        b'q' if args.starts_with(b"Supported") => Request::Supported,
+        b'q' if args == b"Attached" || args.starts_with(b"Attached:") => Request::Attached,
+        b'H' => Request::SetThread,
+        b'D' => Request::Detach,
+        b'k' => Request::Kill,
+        _ => Request::Unsupported,
+    })
+}
+
+fn split(s: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let at = s.iter().position(|&b| b == separator)?;
+    Some((&s[..at], &s[at + 1..]))
+}
+
+fn optional_hex(s: &[u8]) -> Option<Option<usize>> {
+    match s {
+        [] => Some(None),
+        _ => parse_hex(s).map(Some),
+    }
+}
+
+/// 被调试的核，由固件实现
+pub trait Target {
+    /// 按GDB的编号读取寄存器，x0总是0
+    fn register(&self, number: usize) -> usize;
+    /// 写入x0时忽略
+    fn set_register(&mut self, number: usize, value: usize);
+    /// 以特权级的地址空间访问内存，失败时返回`None`或`false`
+    fn read_byte(&mut self, address: usize) -> Option<u8>;
+    fn write_byte(&mut self, address: usize, value: u8) -> bool;
+    /// 修改了指令以后调用
+    fn sync_instructions(&mut self);
+    /// 单步执行sret时的下一条指令
+    fn sepc(&self) -> usize;
+}
+
+/// 处理完一个请求以后固件要做的事
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// 发送回复，继续等待下一个请求
+    Reply,
+    /// 回复不为空时发送，然后返回特权级继续运行
+    Resume,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Breakpoint {
+    address: usize,
+    len: usize,
+    original: [u8; 4],
+}
+
+pub struct Stub {
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    // 单步时临时插入的断点
+    step: Option<Breakpoint>,
+    attached: bool,
+    signal: u8,
+}
+
+impl Default for Stub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stub {
+    pub const fn new() -> Self {
+        Stub {
+            breakpoints: [None; MAX_BREAKPOINTS],
+            step: None,
+            attached: false,
+            signal: SIGTRAP,
+        }
+    }
+
+    /// GDB是否连接着；没有连接时断点和异常都交给特权级处理
+    pub fn attached(&self) -> bool {
+        self.attached
+    }
+
+    /// 断点异常是否由GDB插入的断点或单步引起
+    pub fn is_breakpoint(&self, address: usize) -> bool {
+        self.step.is_some_and(|step| step.address == address)
+            || self
+                .breakpoints
+                .iter()
+                .flatten()
+                .any(|breakpoint| breakpoint.address == address)
+    }
+
+    /// 核停下来时调用，移除单步的临时断点，在response中写入停止原因
+    pub fn stop(&mut self, signal: u8, target: &mut impl Target, response: &mut Response) {
+        if let Some(step) = self.step.take() {
+            restore(&step, target);
+        }
+        self.attached = true;
+        self.signal = signal;
+        self.stop_reason(response);
+    }
+
+    /// 处理一个包，在response中写入回复
+    pub fn handle(
+        &mut self,
+        packet: &[u8],
+        target: &mut impl Target,
+        response: &mut Response,
+    ) -> Action {
+        response.clear();
+        let Some(request) = parse_request(packet) else {
+            response.push(b"E01");
+            return Action::Reply;
+        };
+        match request {
+            Request::StopReason => self.stop_reason(response),
+            Request::ReadRegisters => {
+                for number in 0..NUM_REGISTERS {
+                    response.push_hex(&(target.register(number) as u64).to_le_bytes());
+                }
+            }
+            Request::WriteRegisters(hex) => {
+                // GDB可能只写前面的一部分寄存器
+                for (number, hex) in hex.chunks(16).enumerate().take(NUM_REGISTERS) {
+                    let mut value = [0; 8];
+                    if decode_hex(hex, &mut value).is_none() {
+                        response.push(b"E01");
+                        return Action::Reply;
+                    }
+                    target.set_register(number, u64::from_le_bytes(value) as usize);
+                }
+                response.push(b"OK");
+            }
+            Request::ReadRegister(number) if number < NUM_REGISTERS => {
+                response.push_hex(&(target.register(number) as u64).to_le_bytes())
+            }
+            // 浮点寄存器和CSR不可用
+            Request::ReadRegister(_) => response.push(b"xxxxxxxxxxxxxxxx"),
+            Request::WriteRegister(number, hex) => {
+                let mut value = [0; 8];
+                if number >= NUM_REGISTERS || decode_hex(hex, &mut value).is_none() {
+                    response.push(b"E01");
+                } else {
+                    target.set_register(number, u64::from_le_bytes(value) as usize);
+                    response.push(b"OK");
+                }
+            }
+            Request::ReadMemory { address, len } => {
+                // 回复最多能放下PACKET_SIZE / 2个字节
+                for i in 0..len.min(PACKET_SIZE / 2) {
+                    match target.read_byte(address.wrapping_add(i)) {
+                        Some(byte) => response.push_hex(&[byte]),
+                        None if i == 0 => response.push(b"E14"),
+                        None => break,
+                    }
+                }
+            }
+            Request::WriteMemory { address, data } => {
+                let mut ok = true;
+                for (i, hex) in data.chunks(2).enumerate() {
+                    let mut byte = [0];
+                    ok = decode_hex(hex, &mut byte).is_some()
+                        && target.write_byte(address.wrapping_add(i), byte[0]);
+                    if !ok {
+                        break;
+                    }
+                }
+                target.sync_instructions();
+                response.push(if ok { b"OK" } else { b"E14" });
+            }
+            Request::Continue(address) => {
+                if let Some(address) = address {
+                    target.set_register(PC, address);
+                }
+                return Action::Resume;
+            }
+            Request::Step(address) => {
+                if let Some(address) = address {
+                    target.set_register(PC, address);
+                }
+                return match self.insert_step(target) {
+                    Some(()) => Action::Resume,
+                    None => {
+                        response.push(b"E14");
+                        Action::Reply
+                    }
+                };
+            }
+            Request::InsertBreakpoint { address, kind } => {
+                let ok = self.insert(address, kind, target);
+                response.push(if ok { b"OK" } else { b"E14" });
+            }
+            Request::RemoveBreakpoint { address, .. } => {
+                let slot = self
+                    .breakpoints
+                    .iter_mut()
+                    .find(|breakpoint| breakpoint.is_some_and(|b| b.address == address));
+                if let Some(breakpoint) = slot.and_then(Option::take) {
+                    restore(&breakpoint, target);
+                }
+                response.push(b"OK");
+            }
+            Request::Supported => {
+                response.push(b"PacketSize=");
+                response.push_hex(&(PACKET_SIZE as u16).to_be_bytes());
+            }
+            Request::Attached => response.push(b"1"),
+            Request::SetThread => response.push(b"OK"),
+            Request::Detach | Request::Kill => {
+                if let Some(step) = self.step.take() {
+                    restore(&step, target);
+                }
+                for breakpoint in self.breakpoints.iter_mut() {
+                    if let Some(breakpoint) = breakpoint.take() {
+                        restore(&breakpoint, target);
+                    }
+                }
+                self.attached = false;
+                // `k`不需要回复
+                if request == Request::Detach {
+                    response.push(b"OK");
+                }
+                return Action::Resume;
+            }
+            Request::Unsupported => {}
+        }
+        Action::Reply
+    }
+
+    fn stop_reason(&self, response: &mut Response) {
+        response.clear();
+        response.push(b"S");
+        response.push_hex(&[self.signal]);
+    }
+
+    // kind是断点指令的长度，2或4
+    fn insert(&mut self, address: usize, kind: usize, target: &mut impl Target) -> bool {
+        if self.is_breakpoint(address) {
+            return true;
+        }
+        let Some(slot) = self.breakpoints.iter_mut().find(|b| b.is_none()) else {
+            return false;
+        };
+        match write_ebreak(address, kind, target) {
+            Some(breakpoint) => {
+                *slot = Some(breakpoint);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn insert_step(&mut self, target: &mut impl Target) -> Option<()> {
+        let pc = target.register(PC);
+        let ins = read_instruction(pc, target)?;
+        let next = next_pc(ins, pc, |r| target.register(r), target.sepc());
+        // 下一条指令已经有断点时不需要再插入
+        if self.is_breakpoint(next) {
+            return Some(());
+        }
+        let kind = instruction_len(read_instruction(next, target)?);
+        self.step = Some(write_ebreak(next, kind, target)?);
+        Some(())
+    }
+}
+
+fn read_instruction(address: usize, target: &mut impl Target) -> Option<u32> {
+    let mut bytes = [0; 4];
+    for (i, byte) in bytes.iter_mut().enumerate().take(2) {
+        *byte = target.read_byte(address.wrapping_add(i))?;
+    }
+    if instruction_len(u32::from_le_bytes(bytes)) == 4 {
+        for (i, byte) in bytes.iter_mut().enumerate().skip(2) {
+            *byte = target.read_byte(address.wrapping_add(i))?;
+        }
+    }
+    Some(u32::from_le_bytes(bytes))
+}
+
+fn write_ebreak(address: usize, kind: usize, target: &mut impl Target) -> Option<Breakpoint> {
+    let ebreak: &[u8] = match kind {
+        2 => &C_EBREAK,
+        4 => &EBREAK,
+        _ => return None,
+    };
+    let mut breakpoint = Breakpoint {
+        address,
+        len: kind,
+        original: [0; 4],
+    };
+    for i in 0..kind {
+        breakpoint.original[i] = target.read_byte(address.wrapping_add(i))?;
+    }
+    for (i, &byte) in ebreak.iter().enumerate() {
+        if !target.write_byte(address.wrapping_add(i), byte) {
+            restore(&breakpoint, target);
+            return None;
+        }
+    }
+    target.sync_instructions();
+    Some(breakpoint)
+}
+
+fn restore(breakpoint: &Breakpoint, target: &mut impl Target) {
+    for i in 0..breakpoint.len {
+        target.write_byte(breakpoint.address.wrapping_add(i), breakpoint.original[i]);
+    }
+    target.sync_instructions();
+}
+
+/// 指令的长度，压缩指令是2字节
+pub fn instruction_len(ins: u32) -> usize {
+    if ins & 0b11 == 0b11 {
+        4
+    } else {
+        2
+    }
+}
+
+/// 预测ins执行后的下一条指令地址：计算跳转和分支的目标，其它指令顺序执行。
+/// reg按编号读取通用寄存器，sepc是sret返回的地址
+pub fn next_pc(ins: u32, pc: usize, reg: impl Fn(usize) -> usize, sepc: usize) -> usize {
+    if instruction_len(ins) == 2 {
+        return next_pc_compressed(ins as u16, pc, reg);
+    }
+    let rs1 = (ins >> 15 & 0x1f) as usize;
+    let rs2 = (ins >> 20 & 0x1f) as usize;
+    match ins & 0x7f {
+        // jal
+        0x6f => {
+            let imm = bits(ins, 31, 31, 20)
+                | bits(ins, 30, 21, 1)
+                | bits(ins, 20, 20, 11)
+                | bits(ins, 19, 12, 12);
+            pc.wrapping_add(sign_extend(imm, 21))
+        }
+        // jalr
+        0x67 => reg(rs1).wrapping_add(sign_extend(ins >> 20, 12)) & !1,
+        // beq, bne, blt, bge, bltu, bgeu
+        0x63 => {
+            let (a, b) = (reg(rs1), reg(rs2));
+            let taken = match ins >> 12 & 0b111 {
+                0b000 => a == b,
+                0b001 => a != b,
+                0b100 => (a as isize) < (b as isize),
+                0b101 => (a as isize) >= (b as isize),
+                0b110 => a < b,
+                0b111 => a >= b,
+                _ => false,
+            };
+            if !taken {
+                return pc.wrapping_add(4);
+            }
+            let imm = bits(ins, 31, 31, 12)
+                | bits(ins, 30, 25, 5)
+                | bits(ins, 11, 8, 1)
+                | bits(ins, 7, 7, 11);
+            pc.wrapping_add(sign_extend(imm, 13))
+        }
+        _ if ins == SRET => sepc,
+        _ => pc.wrapping_add(4),
+    }
+}
+
+// RV64C中会跳转的压缩指令：c.j、c.beqz、c.bnez、c.jr、c.jalr
+fn next_pc_compressed(ins: u16, pc: usize, reg: impl Fn(usize) -> usize) -> usize {
+    let ins = ins as u32;
+    let funct3 = ins >> 13;
+    match (ins & 0b11, funct3) {
+        // c.j
+        (0b01, 0b101) => {
+            let imm = bits(ins, 12, 12, 11)
+                | bits(ins, 11, 11, 4)
+                | bits(ins, 10, 9, 8)
+                | bits(ins, 8, 8, 10)
+                | bits(ins, 7, 7, 6)
+                | bits(ins, 6, 6, 7)
+                | bits(ins, 5, 3, 1)
+                | bits(ins, 2, 2, 5);
+            pc.wrapping_add(sign_extend(imm, 12))
+        }
+        // c.beqz, c.bnez
+        (0b01, 0b110 | 0b111) => {
+            let value = reg(8 + (ins >> 7 & 0b111) as usize);
+            if (value == 0) != (funct3 == 0b110) {
+                return pc.wrapping_add(2);
+            }
+            let imm = bits(ins, 12, 12, 8)
+                | bits(ins, 11, 10, 3)
+                | bits(ins, 6, 5, 6)
+                | bits(ins, 4, 3, 1)
+                | bits(ins, 2, 2, 5);
+            pc.wrapping_add(sign_extend(imm, 9))
+        }
+        // c.jr, c.jalr；rs1为0时是c.ebreak
+        (0b10, 0b100) if ins >> 2 & 0x1f == 0 && ins >> 7 & 0x1f != 0 => {
+            reg((ins >> 7 & 0x1f) as usize) & !1
+        }
+        _ => pc.wrapping_add(2),
+    }
+}
+
+// 取出ins的hi到lo位，放到立即数的第to位开始
+fn bits(ins: u32, hi: u32, lo: u32, to: u32) -> u32 {
+    (ins >> lo & ((1 << (hi - lo + 1)) - 1)) << to
+}
+
+fn sign_extend(imm: u32, width: u32) -> usize {
+    let shift = 32 - width;
+    ((imm << shift) as i32 >> shift) as isize as usize
+}
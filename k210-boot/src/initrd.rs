@@ -0,0 +1,77 @@
+//! 初始内存盘（initrd）的描述头，放在交接区中，告诉固件内存盘被加载到了哪里。
+//! 固件据此在设备树的`/chosen`中加入`linux,initrd-start`和`linux,initrd-end`。
+
+/// 描述头的魔数，即字符串"K210IRD0"
+pub const INITRD_MAGIC: u64 = u64::from_le_bytes(*b"K210IRD0");
+pub const INITRD_HEADER_SIZE: usize = 3 * 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct InitrdHeader {
+    pub magic: u64,
+    /// 内存盘的物理地址
+    pub start: u64,
+    /// 内存盘的字节数
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitrdError {
+    BadMagic(u64),
+    // 内存盘为空，或者超出了SRAM
+    BadRange { start: u64, size: u64 },
+}
+
+impl InitrdHeader {
+    pub const fn new(start: u64, size: u64) -> Self {
+        InitrdHeader {
+            magic: INITRD_MAGIC,
+            start,
+            size,
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < INITRD_HEADER_SIZE {
+            return None;
+        }
+        let field = |i: usize| {
+            let mut word = [0; 8];
+            word.copy_from_slice(&bytes[i * 8..i * 8 + 8]);
+            u64::from_le_bytes(word)
+        };
+        Some(InitrdHeader {
+            magic: field(0),
+            start: field(1),
+            size: field(2),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; INITRD_HEADER_SIZE] {
+        let mut bytes = [0; INITRD_HEADER_SIZE];
+        for (chunk, field) in bytes
+            .chunks_exact_mut(8)
+            .zip([self.magic, self.start, self.size])
+        {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// 检查描述头，返回内存盘的起止地址，结束地址不包括在内
+    pub fn validate(&self, ram_start: u64, ram_end: u64) -> Result<(u64, u64), InitrdError> {
+        if self.magic != INITRD_MAGIC {
+            return Err(InitrdError::BadMagic(self.magic));
+        }
+        let end = self.start.checked_add(self.size);
+        match end {
+            Some(end) if self.size != 0 && self.start >= ram_start && end <= ram_end => {
+                Ok((self.start, end))
+            }
+            _ => Err(InitrdError::BadRange {
+                start: self.start,
+                size: self.size,
+            }),
+        }
+    }
+}
@@ -0,0 +1,25 @@
+//! 启动相关的定义，由`rustsbi-k210`、它的构建脚本和`xtask`共用，
+//! 保证固件、链接脚本和烧写工具看到的地址是一致的。
+#![no_std]
+// 固件使用的nightly工具链早于is_multiple_of稳定的版本（1.87），整除只能写成取余
+#![allow(unknown_lints, clippy::manual_is_multiple_of)]
+
+#[cfg(feature = "dtc")]
+extern crate alloc;
+
+pub mod bootctl;
+pub mod config;
+#[cfg(feature = "dtc")]
+pub mod dtc;
+pub mod env;
+pub mod fat;
+pub mod fdt;
+pub mod fw_dynamic;
+pub mod gdb;
+pub mod initrd;
+pub mod lz4;
+pub mod monitor;
+pub mod payload;
+pub mod signature;
+pub mod watchdog;
+pub mod ymodem;
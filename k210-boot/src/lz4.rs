@@ -0,0 +1,264 @@
+//! LZ4帧格式的解压，用于压缩后的内核。
+//!
+//! 支持标准的LZ4帧：独立或连续的数据块、数据块校验和、内容长度与内容校验和；
+//! 不支持预设字典。解压到一块连续的内存中，数据块之间的引用直接在输出中查找。
+use core::ops::Range;
+
+pub const LZ4_FRAME_MAGIC: u32 = 0x184d_2204;
+
+const FLG_VERSION_MASK: u8 = 0b1100_0000;
+const FLG_VERSION: u8 = 0b0100_0000;
+const FLG_BLOCK_CHECKSUM: u8 = 1 << 4;
+const FLG_CONTENT_SIZE: u8 = 1 << 3;
+const FLG_CONTENT_CHECKSUM: u8 = 1 << 2;
+const FLG_RESERVED: u8 = 1 << 1;
+const FLG_DICT_ID: u8 = 1 << 0;
+const BD_BLOCK_SIZE_SHIFT: u8 = 4;
+const BD_RESERVED: u8 = 0b1000_1111;
+// 数据块长度的最高位表示这一块没有压缩
+const BLOCK_UNCOMPRESSED: u32 = 1 << 31;
+const MIN_MATCH: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lz4Error {
+    // 不是LZ4帧
+    BadMagic,
+    UnsupportedVersion(u8),
+    // 保留位不为0，或者使用了预设字典
+    Unsupported,
+    HeaderChecksum,
+    BlockChecksum,
+    ContentChecksum { expected: u32, actual: u32 },
+    // 输入在帧结束之前就用完了
+    Truncated,
+    // 数据块的内容不合法
+    Corrupted,
+    OutputTooSmall,
+    ContentSize { expected: u64, actual: u64 },
+}
+
+/// 帧描述符中的信息
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameInfo {
+    /// 解压后的长度，压缩时没有记录则为`None`
+    pub content_size: Option<u64>,
+    pub block_max_size: usize,
+    pub block_checksum: bool,
+    pub content_checksum: bool,
+    /// 帧描述符的长度，数据块从这里开始
+    pub header_size: usize,
+}
+
+/// 解析并检查帧描述符
+pub fn frame_info(src: &[u8]) -> Result<FrameInfo, Lz4Error> {
+    let magic = read_u32(src, 0).ok_or(Lz4Error::BadMagic)?;
+    if magic != LZ4_FRAME_MAGIC {
+        return Err(Lz4Error::BadMagic);
+    }
+    let (&flg, &bd) = src.get(4).zip(src.get(5)).ok_or(Lz4Error::Truncated)?;
+    if flg & FLG_VERSION_MASK != FLG_VERSION {
+        return Err(Lz4Error::UnsupportedVersion(flg >> 6));
+    }
+    if flg & (FLG_RESERVED | FLG_DICT_ID) != 0 || bd & BD_RESERVED != 0 {
+        return Err(Lz4Error::Unsupported);
+    }
+    let block_max_size = match bd >> BD_BLOCK_SIZE_SHIFT {
+        4 => 64 << 10,
+        5 => 256 << 10,
+        6 => 1 << 20,
+        7 => 4 << 20,
+        _ => return Err(Lz4Error::Unsupported),
+    };
+    let mut end = 6;
+    let content_size = if flg & FLG_CONTENT_SIZE != 0 {
+        let low = read_u32(src, 6).ok_or(Lz4Error::Truncated)? as u64;
+        let high = read_u32(src, 10).ok_or(Lz4Error::Truncated)? as u64;
+        end += 8;
+        Some(high << 32 | low)
+    } else {
+        None
+    };
+    // 描述符校验和是FLG到内容长度的xxh32的第二个字节
+    let checksum = *src.get(end).ok_or(Lz4Error::Truncated)?;
+    if checksum != (xxh32(&src[4..end], 0) >> 8) as u8 {
+        return Err(Lz4Error::HeaderChecksum);
+    }
+    Ok(FrameInfo {
+        content_size,
+        block_max_size,
+        block_checksum: flg & FLG_BLOCK_CHECKSUM != 0,
+        content_checksum: flg & FLG_CONTENT_CHECKSUM != 0,
+        header_size: end + 1,
+    })
+}
+
+/// 把一个LZ4帧解压到dst开头，返回解压后的长度。帧中记录了内容长度和校验和时一并检查
+pub fn decompress(src: &[u8], dst: &mut [u8]) -> Result<usize, Lz4Error> {
+    let info = frame_info(src)?;
+    let mut input = info.header_size;
+    let mut output = 0;
+    loop {
+        let size = read_u32(src, input).ok_or(Lz4Error::Truncated)?;
+        input += 4;
+        if size == 0 {
+            break;
+        }
+        let len = (size & !BLOCK_UNCOMPRESSED) as usize;
+        if len > info.block_max_size {
+            return Err(Lz4Error::Corrupted);
+        }
+        let block = src.get(input..input + len).ok_or(Lz4Error::Truncated)?;
+        input += len;
+        if info.block_checksum {
+            let checksum = read_u32(src, input).ok_or(Lz4Error::Truncated)?;
+            input += 4;
+            if checksum != xxh32(block, 0) {
+                return Err(Lz4Error::BlockChecksum);
+            }
+        }
+        output = if size & BLOCK_UNCOMPRESSED != 0 {
+            let out = dst
+                .get_mut(output..output + len)
+                .ok_or(Lz4Error::OutputTooSmall)?;
+            out.copy_from_slice(block);
+            output + len
+        } else {
+            let limit = dst.len().min(output + info.block_max_size);
+            decompress_block(block, dst, output..limit)?
+        };
+    }
+    if info.content_checksum {
+        let expected = read_u32(src, input).ok_or(Lz4Error::Truncated)?;
+        let actual = xxh32(&dst[..output], 0);
+        if expected != actual {
+            return Err(Lz4Error::ContentChecksum { expected, actual });
+        }
+    }
+    if let Some(expected) = info.content_size {
+        if expected != output as u64 {
+            return Err(Lz4Error::ContentSize {
+                expected,
+                actual: output as u64,
+            });
+        }
+    }
+    Ok(output)
+}
+
+// 解压一个数据块，写入dst的range范围；匹配可以引用之前所有数据块的输出。返回写入后的位置
+fn decompress_block(src: &[u8], dst: &mut [u8], range: Range<usize>) -> Result<usize, Lz4Error> {
+    let (mut input, mut output) = (0, range.start);
+    let limit = range.end;
+    loop {
+        let token = *src.get(input).ok_or(Lz4Error::Corrupted)?;
+        input += 1;
+        let literals = read_length(src, &mut input, (token >> 4) as usize)?;
+        let from = src
+            .get(input..input + literals)
+            .ok_or(Lz4Error::Corrupted)?;
+        if output + literals > limit {
+            return Err(Lz4Error::OutputTooSmall);
+        }
+        dst[output..output + literals].copy_from_slice(from);
+        input += literals;
+        output += literals;
+        // 最后一个序列只有字面量
+        if input == src.len() {
+            return Ok(output);
+        }
+        let offset = src
+            .get(input..input + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .ok_or(Lz4Error::Corrupted)?;
+        input += 2;
+        if offset == 0 || offset > output {
+            return Err(Lz4Error::Corrupted);
+        }
+        let len = read_length(src, &mut input, (token & 0xf) as usize)? + MIN_MATCH;
+        if output + len > limit {
+            return Err(Lz4Error::OutputTooSmall);
+        }
+        // 匹配可以和输出重叠，必须逐字节复制
+        for i in output..output + len {
+            dst[i] = dst[i - offset];
+        }
+        output += len;
+    }
+}
+
+// 长度为15时后面还有若干字节，直到遇到不是255的字节
+fn read_length(src: &[u8], input: &mut usize, mut len: usize) -> Result<usize, Lz4Error> {
+    if len == 15 {
+        loop {
+            let byte = *src.get(*input).ok_or(Lz4Error::Corrupted)?;
+            *input += 1;
+            len += byte as usize;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Ok(len)
+}
+
+fn read_u32(src: &[u8], at: usize) -> Option<u32> {
+    let bytes = src.get(at..at + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+const PRIME32_1: u32 = 0x9e37_79b1;
+const PRIME32_2: u32 = 0x85eb_ca77;
+const PRIME32_3: u32 = 0xc2b2_ae3d;
+const PRIME32_4: u32 = 0x27d4_eb2f;
+const PRIME32_5: u32 = 0x1656_67b1;
+
+/// LZ4帧使用的xxHash32校验和
+pub fn xxh32(data: &[u8], seed: u32) -> u32 {
+    let round = |acc: u32, lane: u32| {
+        acc.wrapping_add(lane.wrapping_mul(PRIME32_2))
+            .rotate_left(13)
+            .wrapping_mul(PRIME32_1)
+    };
+    let mut chunks = data.chunks_exact(16);
+    let mut hash = if data.len() >= 16 {
+        let mut acc = [
+            seed.wrapping_add(PRIME32_1).wrapping_add(PRIME32_2),
+            seed.wrapping_add(PRIME32_2),
+            seed,
+            seed.wrapping_sub(PRIME32_1),
+        ];
+        for chunk in &mut chunks {
+            for (i, acc) in acc.iter_mut().enumerate() {
+                *acc = round(*acc, read_u32(chunk, i * 4).unwrap());
+            }
+        }
+        acc[0]
+            .rotate_left(1)
+            .wrapping_add(acc[1].rotate_left(7))
+            .wrapping_add(acc[2].rotate_left(12))
+            .wrapping_add(acc[3].rotate_left(18))
+    } else {
+        seed.wrapping_add(PRIME32_5)
+    };
+    hash = hash.wrapping_add(data.len() as u32);
+    let mut rest = chunks.remainder();
+    while rest.len() >= 4 {
+        let lane = read_u32(rest, 0).unwrap();
+        hash = hash
+            .wrapping_add(lane.wrapping_mul(PRIME32_3))
+            .rotate_left(17)
+            .wrapping_mul(PRIME32_4);
+        rest = &rest[4..];
+    }
+    for &byte in rest {
+        hash = hash
+            .wrapping_add((byte as u32).wrapping_mul(PRIME32_5))
+            .rotate_left(11)
+            .wrapping_mul(PRIME32_1);
+    }
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(PRIME32_2);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(PRIME32_3);
+    hash ^ (hash >> 16)
+}
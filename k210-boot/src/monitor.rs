@@ -0,0 +1,206 @@
+//! 启动监视器的命令行：行编辑和命令解析，由rustsbi-k210的`monitor`特性使用。
+//!
+//! 数值参数和环境变量一样支持十进制和带`0x`前缀的十六进制。
+use crate::env::parse_number;
+
+/// 一行命令的最大长度
+pub const LINE_MAX: usize = 128;
+/// `md`不指定长度时显示的字节数
+pub const DEFAULT_DUMP_LEN: usize = 64;
+
+/// 可以用名字访问的控制状态寄存器（特权级指令1.9.1版本），也可以直接写编号
+pub const CSR_NAMES: &[(&str, u16)] = &[
+    ("mstatus", 0x300),
+    ("misa", 0x301),
+    ("medeleg", 0x302),
+    ("mideleg", 0x303),
+    ("mie", 0x304),
+    ("mtvec", 0x305),
+    ("mscratch", 0x340),
+    ("mepc", 0x341),
+    ("mcause", 0x342),
+    ("mbadaddr", 0x343),
+    ("mip", 0x344),
+    ("mcycle", 0xb00),
+    ("minstret", 0xb02),
+    ("mvendorid", 0xf11),
+    ("marchid", 0xf12),
+    ("mimpid", 0xf13),
+    ("mhartid", 0xf14),
+];
+
+pub const HELP: &str = "\
+help                    show this message
+md <addr> [len]         dump memory
+mw <addr> <value>       write a 32-bit word
+csr [name] [value]      show all CSRs, or read or write one
+traps [hart]            show the trap history
+addr <addr>             set the payload address for this boot
+tracemask <mask>        set the trace mask
+bootargs [text]         set the kernel command line for this boot
+serial                  receive a kernel over YMODEM
+boot                    continue booting";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command<'a> {
+    Help,
+    Dump {
+        address: usize,
+        len: usize,
+    },
+    Write {
+        address: usize,
+        value: u32,
+    },
+    /// 没有名字时显示所有寄存器，没有值时只读取
+    Csr {
+        number: Option<u16>,
+        value: Option<usize>,
+    },
+    Traps {
+        hart: Option<usize>,
+    },
+    Address(usize),
+    TraceMask(usize),
+    /// 空串表示清除
+    Bootargs(&'a str),
+    Serial,
+    Boot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandError<'a> {
+    Unknown(&'a str),
+    MissingArgument,
+    TooManyArguments,
+    BadNumber(&'a str),
+    UnknownCsr(&'a str),
+}
+
+/// 解析一行命令，空行返回`None`
+pub fn parse(line: &str) -> Result<Option<Command<'_>>, CommandError<'_>> {
+    let line = line.trim();
+    let (name, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let rest = rest.trim_start();
+    let mut args = rest.split_whitespace();
+    let command = match name {
+        "" => return Ok(None),
+        "help" | "?" => Command::Help,
+        "md" => Command::Dump {
+            address: number(args.next())?,
+            len: args
+                .next()
+                .map_or(Ok(DEFAULT_DUMP_LEN), |s| number(Some(s)))?,
+        },
+        "mw" => {
+            let address = number(args.next())?;
+            let value = args.next();
+            let value = number(value)?
+                .try_into()
+                .map_err(|_| CommandError::BadNumber(value.unwrap()))?;
+            Command::Write { address, value }
+        }
+        "csr" => Command::Csr {
+            number: args.next().map(csr_number).transpose()?,
+            value: args.next().map(|s| number(Some(s))).transpose()?,
+        },
+        "traps" => Command::Traps {
+            hart: args.next().map(|s| number(Some(s))).transpose()?,
+        },
+        "addr" => Command::Address(number(args.next())?),
+        "tracemask" => Command::TraceMask(number(args.next())?),
+        // 命令行可以包含空格，保留原样
+        "bootargs" => return Ok(Some(Command::Bootargs(rest))),
+        "serial" => Command::Serial,
+        "boot" => Command::Boot,
+        _ => return Err(CommandError::Unknown(name)),
+    };
+    match args.next() {
+        Some(_) => Err(CommandError::TooManyArguments),
+        None => Ok(Some(command)),
+    }
+}
+
+fn number(arg: Option<&str>) -> Result<usize, CommandError<'_>> {
+    let arg = arg.ok_or(CommandError::MissingArgument)?;
+    parse_number(arg).ok_or(CommandError::BadNumber(arg))
+}
+
+/// 按名字或编号查找控制状态寄存器
+pub fn csr_number(name: &str) -> Result<u16, CommandError<'_>> {
+    if let Some(&(_, number)) = CSR_NAMES.iter().find(|(n, _)| *n == name) {
+        return Ok(number);
+    }
+    match parse_number(name) {
+        Some(number) if number <= 0xfff => Ok(number as u16),
+        _ => Err(CommandError::UnknownCsr(name)),
+    }
+}
+
+/// 按编号访问CSR的指令只能在运行时生成：读取时是`csrrs a1, csr, zero`，
+/// 写入时是`csrrw a1, csr, a1`，两种都把原来的值放在a1中
+pub fn csr_instruction(number: u16, write: bool) -> u32 {
+    const SYSTEM: u32 = 0x73;
+    const A1: u32 = 11;
+    let (funct3, rs1) = if write { (0b001, A1) } else { (0b010, 0) };
+    ((number as u32 & 0xfff) << 20) | (rs1 << 15) | (funct3 << 12) | (A1 << 7) | SYSTEM
+}
+
+/// 从串口逐个字节输入一行
+pub struct LineEditor {
+    buf: [u8; LINE_MAX],
+    len: usize,
+}
+
+/// 输入一个字节后终端上需要的操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    /// 回显这个字符
+    Echo(u8),
+    /// 删除前一个字符
+    Erase,
+    /// 一行结束，用`line`取出，之后用`clear`开始下一行
+    Enter,
+    Ignore,
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LineEditor {
+    pub const fn new() -> Self {
+        LineEditor {
+            buf: [0; LINE_MAX],
+            len: 0,
+        }
+    }
+
+    pub fn feed(&mut self, byte: u8) -> Edit {
+        match byte {
+            b'\r' | b'\n' => Edit::Enter,
+            // 退格和DEL
+            0x08 | 0x7f if self.len > 0 => {
+                self.len -= 1;
+                Edit::Erase
+            }
+            0x20..=0x7e if self.len < LINE_MAX => {
+                self.buf[self.len] = byte;
+                self.len += 1;
+                Edit::Echo(byte)
+            }
+            _ => Edit::Ignore,
+        }
+    }
+
+    pub fn line(&self) -> &str {
+        // 只接受可打印的ASCII字符
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
@@ -0,0 +1,205 @@
+//! 存放在SPI闪存中的下一阶段程序镜像：固定长度的描述头，后面紧跟程序本身。
+//!
+//! 描述头（小端序）：
+//!
+//! | 偏移 | 长度 | 内容                                  |
+//! |------|------|---------------------------------------|
+//! | 0    | 8    | 魔数`K210PAYL`                        |
+//! | 8    | 4    | 版本号，目前为1                        |
+//! | 12   | 4    | 描述头长度，目前为96                   |
+//! | 16   | 8    | 加载地址                              |
+//! | 24   | 8    | 入口地址                              |
+//! | 32   | 8    | 程序长度                              |
+//! | 40   | 4    | 标志，见`FLAG_SHA256`和`FLAG_LZ4`     |
+//! | 44   | 4    | 程序的CRC32                           |
+//! | 48   | 32   | 程序的SHA-256摘要                     |
+//! | 80   | 4    | 描述头前80字节的CRC32                  |
+//! | 84   | 12   | 保留，填0                             |
+use sha2::{Digest, Sha256};
+
+pub const PAYLOAD_MAGIC: [u8; 8] = *b"K210PAYL";
+pub const PAYLOAD_VERSION: u32 = 1;
+pub const PAYLOAD_HEADER_SIZE: usize = 96;
+
+/// 描述头带有程序的SHA-256摘要，加载后除了CRC32还要检查摘要
+pub const FLAG_SHA256: u32 = 1 << 0;
+/// 程序是LZ4帧（见`lz4`模块），解压后的长度记录在帧描述符中；
+/// 长度、CRC32和摘要都针对压缩后的数据，入口地址则要落在解压后的程序中
+pub const FLAG_LZ4: u32 = 1 << 1;
+
+const HEADER_CRC_OFFSET: usize = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadHeader {
+    pub version: u32,
+    pub load_address: u64,
+    pub entry: u64,
+    pub size: u64,
+    pub flags: u32,
+    pub crc32: u32,
+    pub sha256: [u8; 32],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderError {
+    TooShort,
+    // 这个位置没有镜像
+    BadMagic,
+    UnsupportedVersion(u32),
+    BadHeaderSize(u32),
+    HeaderCrc { expected: u32, actual: u32 },
+    // 入口地址不在加载的程序范围内
+    EntryOutOfRange,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyError {
+    Size { expected: u64, actual: u64 },
+    Crc32 { expected: u32, actual: u32 },
+    Sha256,
+}
+
+impl PayloadHeader {
+    /// 为程序生成描述头，with_sha256为真时同时记录SHA-256摘要
+    pub fn new(load_address: u64, entry: u64, payload: &[u8], with_sha256: bool) -> Self {
+        PayloadHeader {
+            version: PAYLOAD_VERSION,
+            load_address,
+            entry,
+            size: payload.len() as u64,
+            flags: if with_sha256 { FLAG_SHA256 } else { 0 },
+            crc32: crc32(payload),
+            sha256: if with_sha256 {
+                Sha256::digest(payload).into()
+            } else {
+                [0; 32]
+            },
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, HeaderError> {
+        if bytes.len() < PAYLOAD_HEADER_SIZE {
+            return Err(HeaderError::TooShort);
+        }
+        if bytes[..8] != PAYLOAD_MAGIC {
+            return Err(HeaderError::BadMagic);
+        }
+        let version = le32(bytes, 8);
+        if version != PAYLOAD_VERSION {
+            return Err(HeaderError::UnsupportedVersion(version));
+        }
+        let header_size = le32(bytes, 12);
+        if header_size as usize != PAYLOAD_HEADER_SIZE {
+            return Err(HeaderError::BadHeaderSize(header_size));
+        }
+        let expected = le32(bytes, HEADER_CRC_OFFSET);
+        let actual = crc32(&bytes[..HEADER_CRC_OFFSET]);
+        if expected != actual {
+            return Err(HeaderError::HeaderCrc { expected, actual });
+        }
+        let mut sha256 = [0; 32];
+        sha256.copy_from_slice(&bytes[48..80]);
+        let header = PayloadHeader {
+            version,
+            load_address: le64(bytes, 16),
+            entry: le64(bytes, 24),
+            size: le64(bytes, 32),
+            flags: le32(bytes, 40),
+            crc32: le32(bytes, 44),
+            sha256,
+        };
+        // 压缩的程序要读出帧描述符才知道解压后的长度，由调用者再用check_entry检查
+        if header.flags & FLAG_LZ4 == 0 {
+            header.check_entry(header.size)?;
+        } else if header.entry < header.load_address {
+            return Err(HeaderError::EntryOutOfRange);
+        }
+        Ok(header)
+    }
+
+    /// 检查入口地址是否落在从加载地址开始、长度为image_size的程序中
+    pub fn check_entry(&self, image_size: u64) -> Result<(), HeaderError> {
+        match self.load_address.checked_add(image_size) {
+            Some(end) if self.entry >= self.load_address && self.entry < end => Ok(()),
+            _ => Err(HeaderError::EntryOutOfRange),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; PAYLOAD_HEADER_SIZE] {
+        let mut bytes = [0; PAYLOAD_HEADER_SIZE];
+        bytes[..8].copy_from_slice(&PAYLOAD_MAGIC);
+        bytes[8..12].copy_from_slice(&self.version.to_le_bytes());
+        bytes[12..16].copy_from_slice(&(PAYLOAD_HEADER_SIZE as u32).to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.load_address.to_le_bytes());
+        bytes[24..32].copy_from_slice(&self.entry.to_le_bytes());
+        bytes[32..40].copy_from_slice(&self.size.to_le_bytes());
+        bytes[40..44].copy_from_slice(&self.flags.to_le_bytes());
+        bytes[44..48].copy_from_slice(&self.crc32.to_le_bytes());
+        bytes[48..80].copy_from_slice(&self.sha256);
+        let crc = crc32(&bytes[..HEADER_CRC_OFFSET]);
+        bytes[HEADER_CRC_OFFSET..HEADER_CRC_OFFSET + 4].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// 检查加载到内存中的程序
+    pub fn verify(&self, payload: &[u8]) -> Result<(), VerifyError> {
+        if payload.len() as u64 != self.size {
+            return Err(VerifyError::Size {
+                expected: self.size,
+                actual: payload.len() as u64,
+            });
+        }
+        let actual = crc32(payload);
+        if actual != self.crc32 {
+            return Err(VerifyError::Crc32 {
+                expected: self.crc32,
+                actual,
+            });
+        }
+        if self.flags & FLAG_SHA256 != 0 && Sha256::digest(payload)[..] != self.sha256[..] {
+            return Err(VerifyError::Sha256);
+        }
+        Ok(())
+    }
+}
+
+/// IEEE 802.3的CRC32，和zlib、`crc32`命令行工具的结果相同
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc = CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn le32(bytes: &[u8], offset: usize) -> u32 {
+    let mut word = [0; 4];
+    word.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(word)
+}
+
+fn le64(bytes: &[u8], offset: usize) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(word)
+}
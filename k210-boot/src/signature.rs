@@ -0,0 +1,114 @@
+//! 验证启动：内核后面附加的签名尾部。
+//!
+//! 签名的是内核的SHA-256摘要，尾部（小端序）紧跟在内核之后：
+//!
+//! | 偏移 | 长度 | 内容                    |
+//! |------|------|-------------------------|
+//! | 0    | 64   | 对摘要的Ed25519签名      |
+//! | 64   | 8    | 内核长度                 |
+//! | 72   | 8    | 魔数`K210SIG1`           |
+use ed25519_compact::{KeyPair, PublicKey, Seed, Signature};
+use sha2::{Digest, Sha256};
+
+pub const TRAILER_MAGIC: [u8; 8] = *b"K210SIG1";
+pub const TRAILER_SIZE: usize = 80;
+pub const PUBLIC_KEY_SIZE: usize = 32;
+/// 签名密钥的种子
+pub const SECRET_KEY_SIZE: usize = 32;
+pub const SIGNATURE_SIZE: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureError {
+    // 镜像末尾没有签名尾部
+    NoTrailer,
+    // 尾部记录的内核长度和镜像不符
+    BadLength { expected: u64, actual: u64 },
+    BadPublicKey,
+    BadSignature,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trailer {
+    pub signature: [u8; SIGNATURE_SIZE],
+    pub payload_size: u64,
+}
+
+impl Trailer {
+    /// 用签名密钥的种子为内核生成签名尾部
+    pub fn sign(payload: &[u8], secret_key: &[u8; SECRET_KEY_SIZE]) -> Self {
+        let key_pair = KeyPair::from_seed(Seed::new(*secret_key));
+        Trailer {
+            signature: *key_pair.sk.sign(digest(payload), None),
+            payload_size: payload.len() as u64,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; TRAILER_SIZE] {
+        let mut bytes = [0; TRAILER_SIZE];
+        bytes[..64].copy_from_slice(&self.signature);
+        bytes[64..72].copy_from_slice(&self.payload_size.to_le_bytes());
+        bytes[72..].copy_from_slice(&TRAILER_MAGIC);
+        bytes
+    }
+}
+
+/// 签名密钥对应的公钥
+pub fn public_key(secret_key: &[u8; SECRET_KEY_SIZE]) -> [u8; PUBLIC_KEY_SIZE] {
+    *KeyPair::from_seed(Seed::new(*secret_key)).pk
+}
+
+/// 把带有签名尾部的镜像拆分为内核和尾部
+pub fn split(image: &[u8]) -> Result<(&[u8], Trailer), SignatureError> {
+    let payload_len = image
+        .len()
+        .checked_sub(TRAILER_SIZE)
+        .ok_or(SignatureError::NoTrailer)?;
+    let (payload, bytes) = image.split_at(payload_len);
+    if bytes[72..] != TRAILER_MAGIC {
+        return Err(SignatureError::NoTrailer);
+    }
+    let mut signature = [0; SIGNATURE_SIZE];
+    signature.copy_from_slice(&bytes[..64]);
+    let mut size = [0; 8];
+    size.copy_from_slice(&bytes[64..72]);
+    let trailer = Trailer {
+        signature,
+        payload_size: u64::from_le_bytes(size),
+    };
+    if trailer.payload_size != payload_len as u64 {
+        return Err(SignatureError::BadLength {
+            expected: trailer.payload_size,
+            actual: payload_len as u64,
+        });
+    }
+    Ok((payload, trailer))
+}
+
+/// 检查带有签名尾部的镜像，返回内核的长度
+pub fn verify(image: &[u8], public_key: &[u8; PUBLIC_KEY_SIZE]) -> Result<usize, SignatureError> {
+    let (payload, trailer) = split(image)?;
+    let public_key = PublicKey::from_slice(public_key).map_err(|_| SignatureError::BadPublicKey)?;
+    let signature = Signature::new(trailer.signature);
+    public_key
+        .verify(digest(payload), &signature)
+        .map_err(|_| SignatureError::BadSignature)?;
+    Ok(payload.len())
+}
+
+pub fn digest(payload: &[u8]) -> [u8; 32] {
+    Sha256::digest(payload).into()
+}
+
+/// 解析十六进制文本形式的密钥，忽略首尾的空白
+pub fn parse_key(text: &str) -> Option<[u8; 32]> {
+    let text = text.trim().as_bytes();
+    if text.len() != 64 {
+        return None;
+    }
+    let mut key = [0; 32];
+    for (byte, pair) in key.iter_mut().zip(text.chunks(2)) {
+        let hex = |c: u8| (c as char).to_digit(16);
+        *byte = (hex(pair[0])? * 16 + hex(pair[1])?) as u8;
+    }
+    Some(key)
+}
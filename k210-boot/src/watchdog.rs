@@ -0,0 +1,72 @@
+//! 看门狗的超时换算和复位原因，由rustsbi-k210的`watchdog`特性使用。
+//!
+//! K210的看门狗只能按2的幂设置超时：计数`2^(16+top)`个周期后复位芯片，top从0到15。
+
+/// 看门狗的时钟：26MHz的IN0，分频阈值为0时再二分频
+pub const CLOCK_HZ: u64 = 13_000_000;
+pub const MAX_TOP: u32 = 15;
+/// 能设置的最长超时，大约165秒
+pub const MAX_TIMEOUT_MS: usize = top_timeout_ms(MAX_TOP);
+
+/// 不短于timeout_ms的最小top；为0或超过`MAX_TIMEOUT_MS`时返回`None`
+pub const fn timeout_top(timeout_ms: usize) -> Option<u32> {
+    if timeout_ms == 0 || timeout_ms > MAX_TIMEOUT_MS {
+        return None;
+    }
+    let cycles = timeout_ms as u64 * CLOCK_HZ / 1000;
+    let mut top = 0;
+    while (1u64 << (16 + top)) < cycles {
+        top += 1;
+    }
+    Some(top)
+}
+
+/// top对应的超时，向下取整到毫秒
+pub const fn top_timeout_ms(top: u32) -> usize {
+    ((1u64 << (16 + top)) * 1000 / CLOCK_HZ) as usize
+}
+
+/// 上一次复位的原因，来自SYSCTL的reset_status寄存器
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetReason {
+    PowerOn,
+    /// 复位引脚
+    Pin,
+    /// 看门狗超时，参数是看门狗的编号
+    Watchdog(u8),
+    /// 软件复位
+    Software,
+}
+
+const STATUS_PIN: u32 = 1 << 1;
+const STATUS_WDT0: u32 = 1 << 2;
+const STATUS_WDT1: u32 = 1 << 3;
+const STATUS_SOFT: u32 = 1 << 4;
+
+impl ResetReason {
+    /// 几个位同时置位时，看门狗优先于软件复位，软件复位优先于复位引脚
+    pub fn from_status(status: u32) -> Self {
+        if status & STATUS_WDT0 != 0 {
+            ResetReason::Watchdog(0)
+        } else if status & STATUS_WDT1 != 0 {
+            ResetReason::Watchdog(1)
+        } else if status & STATUS_SOFT != 0 {
+            ResetReason::Software
+        } else if status & STATUS_PIN != 0 {
+            ResetReason::Pin
+        } else {
+            ResetReason::PowerOn
+        }
+    }
+
+    /// 写入设备树`/chosen/rustsbi,reset-reason`的字符串
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ResetReason::PowerOn => "power-on",
+            ResetReason::Pin => "pin",
+            ResetReason::Watchdog(0) => "watchdog0",
+            ResetReason::Watchdog(_) => "watchdog1",
+            ResetReason::Software => "software",
+        }
+    }
+}
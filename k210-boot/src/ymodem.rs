@@ -0,0 +1,388 @@
+//! YMODEM协议（CRC16校验，1K数据块），RustSBI用它从串口接收内核，`xtask send`用它发送。
+//!
+//! 每次只传输一个文件。接收方在文件头后回应ACK和`C`，收到第一个EOT时回应NAK，第二个时回应ACK，
+//! 最后用空文件头结束这一批传输。任何一方连续出错太多次时发送两个CAN取消传输。
+
+pub const SOH: u8 = 0x01;
+pub const STX: u8 = 0x02;
+pub const EOT: u8 = 0x04;
+pub const ACK: u8 = 0x06;
+pub const NAK: u8 = 0x15;
+pub const CAN: u8 = 0x18;
+/// 接收方请求使用CRC16校验
+pub const CRC_REQUEST: u8 = b'C';
+// 最后一个数据块用它填充
+const PADDING: u8 = 0x1a;
+
+const BLOCK_SIZE: usize = 128;
+const BLOCK_SIZE_1K: usize = 1024;
+const MAX_ERRORS: u32 = 10;
+// 数据块中相邻字节之间的超时
+const BYTE_TIMEOUT_MS: u32 = 1000;
+// 发送方等待回应的超时，接收方可能正在处理上一个数据块
+const RESPONSE_TIMEOUT_MS: u32 = 10_000;
+const NAME_MAX: usize = 64;
+
+/// 传输使用的串口
+pub trait Port {
+    type Error;
+    /// 在timeout_ms毫秒内读取一个字节，超时时返回`Ok(None)`
+    fn read_byte(&mut self, timeout_ms: u32) -> core::result::Result<Option<u8>, Self::Error>;
+    fn write_all(&mut self, bytes: &[u8]) -> core::result::Result<(), Self::Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YmodemError<E> {
+    Port(E),
+    // 等待的时间内对方没有开始传输
+    NotStarted,
+    Cancelled,
+    TooManyErrors,
+    // 文件比接收缓冲区大
+    TooLarge { size: usize },
+    BadHeader,
+    // 数据块序号不连续
+    Sequence,
+    // 收到的数据比文件头中的长度少
+    Truncated { size: usize, received: usize },
+}
+
+impl<E> From<E> for YmodemError<E> {
+    fn from(e: E) -> Self {
+        YmodemError::Port(e)
+    }
+}
+
+pub type Result<T, E> = core::result::Result<T, YmodemError<E>>;
+
+/// 接收到的文件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileInfo {
+    name: [u8; NAME_MAX],
+    name_len: usize,
+    pub size: usize,
+}
+
+impl FileInfo {
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_len]).unwrap_or("?")
+    }
+}
+
+enum Packet {
+    Block { number: u8, len: usize },
+    Eot,
+    Cancel,
+    // 超时、校验错误或无法识别的数据
+    Error,
+}
+
+/// 接收一个文件并写入buf；wait_seconds秒内发送方没有开始传输时返回`NotStarted`
+pub fn receive<P: Port>(
+    port: &mut P,
+    buf: &mut [u8],
+    wait_seconds: u32,
+) -> Result<FileInfo, P::Error> {
+    let mut block = [0u8; BLOCK_SIZE_1K];
+    let mut waited = 0;
+    let (mut info, declared) = loop {
+        port.write_all(&[CRC_REQUEST])?;
+        match read_packet(port, &mut block, 1000)? {
+            Packet::Block { number: 0, len } => match parse_header(&block[..len]) {
+                Some(header) => break header,
+                None => return Err(cancel(port, YmodemError::BadHeader)?),
+            },
+            Packet::Cancel => return Err(YmodemError::Cancelled),
+            _ => {
+                purge(port)?;
+                waited += 1;
+                if waited >= wait_seconds {
+                    return Err(YmodemError::NotStarted);
+                }
+            }
+        }
+    };
+    if let Some(size) = declared {
+        if size > buf.len() {
+            return Err(cancel(port, YmodemError::TooLarge { size })?);
+        }
+    }
+    port.write_all(&[ACK, CRC_REQUEST])?;
+    let mut expected = 1u8;
+    let mut received = 0;
+    let mut errors = 0;
+    let mut eot = false;
+    loop {
+        match read_packet(port, &mut block, RESPONSE_TIMEOUT_MS)? {
+            Packet::Block { number, len } if number == expected => {
+                let len = match declared {
+                    Some(size) => len.min(size - received),
+                    None => len,
+                };
+                if received + len > buf.len() {
+                    return Err(cancel(
+                        port,
+                        YmodemError::TooLarge {
+                            size: received + len,
+                        },
+                    )?);
+                }
+                buf[received..received + len].copy_from_slice(&block[..len]);
+                received += len;
+                expected = expected.wrapping_add(1);
+                errors = 0;
+                port.write_all(&[ACK])?;
+            }
+            // 发送方没有收到上一个ACK，重发了数据块
+            Packet::Block { number, .. } if number == expected.wrapping_sub(1) => {
+                if received == 0 {
+                    port.write_all(&[ACK, CRC_REQUEST])?;
+                } else {
+                    port.write_all(&[ACK])?;
+                }
+            }
+            Packet::Block { .. } => return Err(cancel(port, YmodemError::Sequence)?),
+            Packet::Eot if !eot => {
+                eot = true;
+                port.write_all(&[NAK])?;
+            }
+            Packet::Eot => {
+                port.write_all(&[ACK])?;
+                break;
+            }
+            Packet::Cancel => return Err(YmodemError::Cancelled),
+            Packet::Error => {
+                errors += 1;
+                if errors >= MAX_ERRORS {
+                    return Err(cancel(port, YmodemError::TooManyErrors)?);
+                }
+                purge(port)?;
+                port.write_all(&[NAK])?;
+            }
+        }
+    }
+    // 接收结束这一批传输的空文件头；文件已经完整接收，这一步失败也不影响结果
+    for _ in 0..MAX_ERRORS {
+        port.write_all(&[CRC_REQUEST])?;
+        match read_packet(port, &mut block, BYTE_TIMEOUT_MS)? {
+            Packet::Block { number: 0, .. } => {
+                port.write_all(&[ACK])?;
+                break;
+            }
+            Packet::Cancel => break,
+            _ => purge(port)?,
+        }
+    }
+    info.size = declared.unwrap_or(received);
+    if received < info.size {
+        return Err(YmodemError::Truncated {
+            size: info.size,
+            received,
+        });
+    }
+    Ok(info)
+}
+
+/// 发送一个文件；wait_seconds秒内接收方没有请求传输时返回`NotStarted`
+pub fn send<P: Port>(
+    port: &mut P,
+    name: &str,
+    data: &[u8],
+    wait_seconds: u32,
+    mut progress: impl FnMut(usize),
+) -> Result<(), P::Error> {
+    let mut header = [0u8; BLOCK_SIZE];
+    let name = &name.as_bytes()[..name.len().min(NAME_MAX)];
+    header[..name.len()].copy_from_slice(name);
+    let mut digits = [0u8; 20];
+    let size = format_decimal(data.len(), &mut digits);
+    header[name.len() + 1..name.len() + 1 + size.len()].copy_from_slice(size);
+    wait_for_request(port, wait_seconds)?;
+    send_block(port, 0, &header)?;
+    wait_for_request(port, 1)?;
+    let mut block = [PADDING; BLOCK_SIZE_1K];
+    for (i, chunk) in data.chunks(BLOCK_SIZE_1K).enumerate() {
+        block[..chunk.len()].copy_from_slice(chunk);
+        block[chunk.len()..].fill(PADDING);
+        send_block(port, (i + 1) as u8, &block)?;
+        progress(i * BLOCK_SIZE_1K + chunk.len());
+    }
+    let mut errors = 0;
+    loop {
+        port.write_all(&[EOT])?;
+        match read_response(port)? {
+            Some(ACK) => break,
+            Some(NAK) => {}
+            _ => {
+                errors += 1;
+                if errors >= MAX_ERRORS {
+                    return Err(cancel(port, YmodemError::TooManyErrors)?);
+                }
+            }
+        }
+    }
+    wait_for_request(port, 1)?;
+    send_block(port, 0, &[0; BLOCK_SIZE])
+}
+
+fn send_block<P: Port>(port: &mut P, number: u8, data: &[u8]) -> Result<(), P::Error> {
+    let start = if data.len() == BLOCK_SIZE_1K {
+        STX
+    } else {
+        SOH
+    };
+    let crc = crc16(data).to_be_bytes();
+    for _ in 0..MAX_ERRORS {
+        port.write_all(&[start, number, !number])?;
+        port.write_all(data)?;
+        port.write_all(&crc)?;
+        match read_response(port)? {
+            Some(ACK) => return Ok(()),
+            Some(CAN) => return Err(YmodemError::Cancelled),
+            _ => {}
+        }
+    }
+    Err(cancel(port, YmodemError::TooManyErrors)?)
+}
+
+// 读取ACK、NAK或连续两个CAN，忽略接收方输出的其它字节
+fn read_response<P: Port>(port: &mut P) -> core::result::Result<Option<u8>, P::Error> {
+    loop {
+        match port.read_byte(RESPONSE_TIMEOUT_MS)? {
+            Some(byte @ (ACK | NAK)) => return Ok(Some(byte)),
+            Some(CAN) => {
+                if port.read_byte(BYTE_TIMEOUT_MS)? == Some(CAN) {
+                    return Ok(Some(CAN));
+                }
+            }
+            Some(_) => {}
+            None => return Ok(None),
+        }
+    }
+}
+
+fn wait_for_request<P: Port>(port: &mut P, wait_seconds: u32) -> Result<(), P::Error> {
+    for _ in 0..wait_seconds {
+        loop {
+            match port.read_byte(1000)? {
+                Some(CRC_REQUEST) => return Ok(()),
+                Some(CAN) => {
+                    if port.read_byte(BYTE_TIMEOUT_MS)? == Some(CAN) {
+                        return Err(YmodemError::Cancelled);
+                    }
+                }
+                Some(_) => {}
+                None => break,
+            }
+        }
+    }
+    Err(YmodemError::NotStarted)
+}
+
+fn read_packet<P: Port>(
+    port: &mut P,
+    block: &mut [u8; BLOCK_SIZE_1K],
+    timeout_ms: u32,
+) -> core::result::Result<Packet, P::Error> {
+    let len = match port.read_byte(timeout_ms)? {
+        Some(SOH) => BLOCK_SIZE,
+        Some(STX) => BLOCK_SIZE_1K,
+        Some(EOT) => return Ok(Packet::Eot),
+        Some(CAN) => {
+            return Ok(match port.read_byte(BYTE_TIMEOUT_MS)? {
+                Some(CAN) => Packet::Cancel,
+                _ => Packet::Error,
+            })
+        }
+        _ => return Ok(Packet::Error),
+    };
+    let mut number = [0u8; 2];
+    let mut crc = [0u8; 2];
+    for byte in number
+        .iter_mut()
+        .chain(block[..len].iter_mut())
+        .chain(crc.iter_mut())
+    {
+        match port.read_byte(BYTE_TIMEOUT_MS)? {
+            Some(b) => *byte = b,
+            None => return Ok(Packet::Error),
+        }
+    }
+    if number[0] != !number[1] || u16::from_be_bytes(crc) != crc16(&block[..len]) {
+        return Ok(Packet::Error);
+    }
+    Ok(Packet::Block {
+        number: number[0],
+        len,
+    })
+}
+
+// 丢弃出错数据块的剩余部分
+fn purge<P: Port>(port: &mut P) -> core::result::Result<(), P::Error> {
+    while port.read_byte(100)?.is_some() {}
+    Ok(())
+}
+
+fn cancel<P: Port>(
+    port: &mut P,
+    error: YmodemError<P::Error>,
+) -> Result<YmodemError<P::Error>, P::Error> {
+    port.write_all(&[CAN, CAN])?;
+    Ok(error)
+}
+
+// 文件头：以0结尾的文件名，后面是十进制的文件长度，可以带有以空格分隔的其它字段
+fn parse_header(block: &[u8]) -> Option<(FileInfo, Option<usize>)> {
+    let name_len = block.iter().position(|&b| b == 0)?;
+    if name_len == 0 {
+        // 空文件头表示发送方没有文件
+        return None;
+    }
+    let mut info = FileInfo {
+        name: [0; NAME_MAX],
+        name_len: name_len.min(NAME_MAX),
+        size: 0,
+    };
+    info.name[..info.name_len].copy_from_slice(&block[..info.name_len]);
+    let mut size: Option<usize> = None;
+    for &b in block[name_len + 1..]
+        .iter()
+        .take_while(|b| b.is_ascii_digit())
+    {
+        let value = size
+            .unwrap_or(0)
+            .checked_mul(10)?
+            .checked_add((b - b'0') as usize)?;
+        size = Some(value);
+    }
+    Some((info, size))
+}
+
+fn format_decimal(mut value: usize, digits: &mut [u8; 20]) -> &[u8] {
+    let mut start = digits.len();
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (value % 10) as u8;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+    &digits[start..]
+}
+
+/// CRC-16/XMODEM
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
@@ -0,0 +1,149 @@
+use k210_boot::bootctl::{self, BootControl, BootControlError, Decision, Slot, BOOT_CONTROL_SIZE};
+use k210_boot::config;
+
+// 模拟多次复位：每次启动都把更新后的记录写回闪存
+fn boot(flash: &mut [u8; BOOT_CONTROL_SIZE]) -> Decision {
+    let mut record = BootControl::parse(flash).unwrap();
+    let decision = record.select();
+    *flash = record.to_bytes();
+    decision
+}
+
+#[test]
+fn record_round_trip() {
+    for record in [
+        BootControl::new(Slot::A, 3),
+        BootControl::new(Slot::B, 0),
+        BootControl {
+            active: Slot::B,
+            tries: 255,
+            successful: true,
+            generation: 200,
+        },
+    ] {
+        assert_eq!(BootControl::parse(&record.to_bytes()), Ok(record));
+    }
+}
+
+#[test]
+fn bad_records_are_rejected() {
+    assert_eq!(
+        BootControl::parse(&[0xff; BOOT_CONTROL_SIZE]),
+        Err(BootControlError::BadMagic)
+    );
+    let mut bytes = BootControl::new(Slot::A, 3).to_bytes();
+    bytes[9] = 7;
+    assert!(matches!(
+        BootControl::parse(&bytes),
+        Err(BootControlError::BadCrc { .. })
+    ));
+    // 槽位编号不对，但CRC正确
+    let mut bytes = BootControl::new(Slot::A, 3).to_bytes();
+    bytes[8] = 2;
+    let crc = k210_boot::payload::crc32(&bytes[..12]);
+    bytes[12..].copy_from_slice(&crc.to_le_bytes());
+    assert_eq!(
+        BootControl::parse(&bytes),
+        Err(BootControlError::BadSlot(2))
+    );
+}
+
+#[test]
+fn trial_slot_falls_back_when_tries_run_out() {
+    let mut flash = BootControl::new(Slot::B, 2).to_bytes();
+    assert_eq!(
+        boot(&mut flash),
+        Decision::Trial {
+            slot: Slot::B,
+            tries_left: 1
+        }
+    );
+    assert_eq!(
+        boot(&mut flash),
+        Decision::Trial {
+            slot: Slot::B,
+            tries_left: 0
+        }
+    );
+    assert_eq!(
+        boot(&mut flash),
+        Decision::Fallback {
+            from: Slot::B,
+            to: Slot::A
+        }
+    );
+    // 回退之后一直启动另一个槽位
+    assert_eq!(boot(&mut flash), Decision::Confirmed(Slot::A));
+    assert_eq!(boot(&mut flash), Decision::Confirmed(Slot::A));
+}
+
+#[test]
+fn successful_boot_keeps_the_slot() {
+    let mut flash = BootControl::new(Slot::B, 3).to_bytes();
+    assert_eq!(boot(&mut flash).slot(), Slot::B);
+    let mut record = BootControl::parse(&flash).unwrap();
+    assert!(record.mark_successful());
+    assert!(!record.mark_successful());
+    flash = record.to_bytes();
+    for _ in 0..5 {
+        assert_eq!(boot(&mut flash), Decision::Confirmed(Slot::B));
+    }
+}
+
+#[test]
+fn zero_tries_falls_back_immediately() {
+    let mut record = BootControl::new(Slot::A, 0);
+    assert_eq!(
+        record.select(),
+        Decision::Fallback {
+            from: Slot::A,
+            to: Slot::B
+        }
+    );
+    assert_eq!(record.select(), Decision::Confirmed(Slot::B));
+}
+
+#[test]
+fn newer_copy_wins() {
+    let mut old = BootControl::new(Slot::A, 0);
+    old.successful = true;
+    let mut new = BootControl::new(Slot::B, 3);
+    new.generation = 1;
+    assert_eq!(bootctl::current([Some(old), Some(new)]), Some(1));
+    assert_eq!(bootctl::current([Some(new), Some(old)]), Some(0));
+    // 代数回绕之后0比255新
+    old.generation = 255;
+    new.generation = 0;
+    assert_eq!(bootctl::current([Some(old), Some(new)]), Some(1));
+    assert_eq!(bootctl::current([None, None]), None);
+}
+
+#[test]
+fn interrupted_write_keeps_the_old_copy() {
+    // 第二份副本擦除之后掉电，读出来全是0xff
+    let mut record = BootControl::new(Slot::B, 3);
+    record.generation = 4;
+    let erased = BootControl::parse(&[0xff; BOOT_CONTROL_SIZE]).ok();
+    assert_eq!(bootctl::current([Some(record), erased]), Some(0));
+    // 只写了一部分时CRC不对
+    let mut bytes = record.to_bytes();
+    bytes[12..].copy_from_slice(&[0xff; 4]);
+    let partial = BootControl::parse(&bytes).ok();
+    assert_eq!(bootctl::current([partial, Some(record)]), Some(1));
+    assert_eq!(bootctl::copy_offset(0), config::FLASH_BOOT_CONTROL_OFFSET);
+    assert_eq!(
+        bootctl::copy_offset(1) - bootctl::copy_offset(0),
+        bootctl::BOOT_CONTROL_SECTOR_SIZE
+    );
+}
+
+#[test]
+fn slots_do_not_overlap() {
+    assert_eq!(Slot::A.flash_offset(), config::FLASH_PAYLOAD_OFFSET);
+    assert_eq!(
+        Slot::B.flash_offset() - Slot::A.flash_offset(),
+        config::FLASH_SLOT_SIZE
+    );
+    assert_eq!(Slot::A.other(), Slot::B);
+    assert_eq!(Slot::B.other(), Slot::A);
+}
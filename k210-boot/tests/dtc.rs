@@ -0,0 +1,124 @@
+#![cfg(feature = "dtc")]
+
+use k210_boot::dtc::compile;
+use k210_boot::fdt::Fdt;
+
+fn cells(fdt: &Fdt, path: &str, name: &str) -> Vec<u32> {
+    let node = fdt.find_node(path).unwrap();
+    fdt.property(node, name)
+        .unwrap()
+        .chunks(4)
+        .map(|c| u32::from_be_bytes(c.try_into().unwrap()))
+        .collect()
+}
+
+fn phandle(fdt: &Fdt, path: &str) -> u32 {
+    let phandle = cells(fdt, path, "phandle");
+    assert_eq!(phandle.len(), 1, "{}", path);
+    phandle[0]
+}
+
+// 遍历所有节点，收集路径和phandle
+fn phandles(fdt: &Fdt, node: usize, path: &str, out: &mut Vec<(String, u32)>) {
+    if let Some(value) = fdt.property(node, "phandle") {
+        out.push((
+            path.to_string(),
+            u32::from_be_bytes(value.try_into().unwrap()),
+        ));
+    }
+    for child in fdt.children(node) {
+        let path = format!("{}/{}", path, fdt.node_name(child));
+        phandles(fdt, child, &path, out);
+    }
+}
+
+#[test]
+fn k210_device_tree() {
+    let blob = compile(include_str!("../../rustsbi-k210/kendryte-k210.dts")).unwrap();
+    let mut buf = vec![0; blob.len()];
+    let fdt = Fdt::open_into(&blob, &mut buf).unwrap();
+    let mut all = Vec::new();
+    phandles(&fdt, 0, "", &mut all);
+    // 只有被引用的节点才有phandle，编号不重复
+    let mut numbers: Vec<_> = all.iter().map(|(_, p)| *p).collect();
+    numbers.sort();
+    numbers.dedup();
+    assert_eq!(numbers.len(), all.len());
+    assert!(!numbers.contains(&0));
+    assert!(fdt
+        .property(fdt.find_node("/cpus/cpu@0").unwrap(), "phandle")
+        .is_none());
+
+    let plic = phandle(&fdt, "/soc/interrupt-controller@c000000");
+    let cpu0 = phandle(&fdt, "/cpus/cpu@0/interrupt-controller");
+    let cpu1 = phandle(&fdt, "/cpus/cpu@1/interrupt-controller");
+    let cpuclk = phandle(&fdt, "/clocks/cpu-clock");
+    let apb0 = phandle(&fdt, "/clocks/apb0-clock");
+    assert_eq!(cells(&fdt, "/soc", "interrupt-parent"), [plic]);
+    assert_eq!(
+        cells(&fdt, "/soc/timer@2000000", "interrupts-extended"),
+        [cpu0, 3, cpu0, 7, cpu1, 3, cpu1, 7]
+    );
+    assert_eq!(
+        cells(
+            &fdt,
+            "/soc/interrupt-controller@c000000",
+            "interrupts-extended"
+        ),
+        [cpu0, 11, cpu0, 9, cpu1, 11, cpu1, 9]
+    );
+    assert_eq!(cells(&fdt, "/soc/serial@38000000", "clocks"), [cpuclk]);
+    assert_eq!(
+        cells(&fdt, "/soc/gpio-controller@50200000", "clocks"),
+        [apb0, apb0]
+    );
+}
+
+#[test]
+fn explicit_phandles_are_kept() {
+    let source = "/dts-v1/;
+    / {
+        a: node-a { phandle = <1>; };
+        b: node-b { };
+        c: node-c { phandle = <5>; };
+        user { refs = <&a &b &c>; };
+    };";
+    let blob = compile(source).unwrap();
+    let mut buf = vec![0; blob.len()];
+    let fdt = Fdt::open_into(&blob, &mut buf).unwrap();
+    // 自动分配的编号从显式写出的最大值之后开始
+    assert_eq!(phandle(&fdt, "/node-a"), 1);
+    assert_eq!(phandle(&fdt, "/node-b"), 6);
+    assert_eq!(phandle(&fdt, "/node-c"), 5);
+    assert_eq!(cells(&fdt, "/user", "refs"), [1, 6, 5]);
+}
+
+#[test]
+fn labels_survive_merge() {
+    let source = "/dts-v1/;
+    / {
+        x: first { };
+        second { };
+    };
+    / {
+        first { value = <1>; };
+        y: z: second { };
+        user { refs = <&x &y &z>; };
+    };";
+    let blob = compile(source).unwrap();
+    let mut buf = vec![0; blob.len()];
+    let fdt = Fdt::open_into(&blob, &mut buf).unwrap();
+    let first = phandle(&fdt, "/first");
+    let second = phandle(&fdt, "/second");
+    assert_ne!(first, second);
+    assert_eq!(cells(&fdt, "/first", "value"), [1]);
+    assert_eq!(cells(&fdt, "/user", "refs"), [first, second, second]);
+}
+
+#[test]
+fn undefined_label() {
+    let source = "/dts-v1/;\n/ {\n    user { ref = <&missing>; };\n};";
+    let error = compile(source).unwrap_err();
+    assert_eq!(error.line, 3);
+    assert_eq!(error.message, "undefined label &missing");
+}
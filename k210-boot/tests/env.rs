@@ -0,0 +1,113 @@
+use k210_boot::env::{current, parse_number, BootMode, Env, EnvError, ENV_DATA_SIZE, ENV_SIZE};
+
+fn sealed(vars: &[(&str, &str)], generation: u8) -> Env {
+    let mut env = Env::new();
+    for (name, value) in vars {
+        env.set(name, value).unwrap();
+    }
+    env.seal(generation);
+    env
+}
+
+#[test]
+fn set_get_and_remove() {
+    let mut env = Env::new();
+    assert_eq!(env.get("bootargs"), None);
+    assert_eq!(env.set("bootargs", "console=hvc0"), Ok(true));
+    assert_eq!(env.set("baudrate", "115200"), Ok(true));
+    assert_eq!(env.get("bootargs"), Some("console=hvc0"));
+    // 相同的值不需要写入闪存
+    assert_eq!(env.set("baudrate", "115200"), Ok(false));
+    // 修改过的变量移到最后
+    assert_eq!(env.set("bootargs", "root=/dev/mmcblk0p2"), Ok(true));
+    let vars: Vec<_> = env.iter().collect();
+    assert_eq!(
+        vars,
+        [("baudrate", "115200"), ("bootargs", "root=/dev/mmcblk0p2")]
+    );
+    // 名字是另一个变量的前缀
+    assert_eq!(env.get("baud"), None);
+    assert!(!env.remove("baud"));
+    assert!(env.remove("baudrate"));
+    assert_eq!(env.iter().count(), 1);
+    assert_eq!(env.set("empty", ""), Ok(true));
+    assert_eq!(env.get("empty"), Some(""));
+}
+
+#[test]
+fn bad_names_and_values() {
+    let mut env = Env::new();
+    assert_eq!(env.set("", "x"), Err(EnvError::BadName));
+    assert_eq!(env.set("a=b", "x"), Err(EnvError::BadName));
+    assert_eq!(env.set("a", "x\0y"), Err(EnvError::BadValue));
+}
+
+#[test]
+fn store_is_bounded() {
+    let mut env = Env::new();
+    let value = "v".repeat(100);
+    let mut count = 0;
+    while env.set(&format!("var{}", count), &value).is_ok() {
+        count += 1;
+    }
+    assert_eq!(
+        env.set(&format!("var{}", count), &value),
+        Err(EnvError::Full)
+    );
+    assert!(count * 106 <= ENV_DATA_SIZE);
+    // 替换已有的变量时不计算它原来占用的空间
+    assert_eq!(env.set("var0", &"w".repeat(100)), Ok(true));
+    env.seal(1);
+    assert_eq!(Env::check(env.as_bytes()), Ok(1));
+    assert_eq!(env.iter().count(), count);
+}
+
+#[test]
+fn check_sealed_copies() {
+    let env = sealed(
+        &[("bootmode", "serial"), ("payload_address", "0x80040000")],
+        7,
+    );
+    assert_eq!(Env::check(env.as_bytes()), Ok(7));
+    assert_eq!(env.generation(), 7);
+    // 读取闪存时写入原始数据
+    let mut copy = Env::new();
+    *copy.raw_mut() = *env.as_bytes();
+    assert_eq!(copy.get("bootmode"), Some("serial"));
+
+    let mut bytes = *env.as_bytes();
+    bytes[20] ^= 1;
+    assert!(matches!(Env::check(&bytes), Err(EnvError::BadCrc { .. })));
+    // 擦除后的闪存
+    assert!(Env::check(&[0xff; ENV_SIZE]).is_err());
+    // 没有等号的变量
+    let mut env = Env::new();
+    env.raw_mut()[5..10].copy_from_slice(b"abc\0\0");
+    env.seal(0);
+    assert_eq!(Env::check(env.as_bytes()), Err(EnvError::Malformed));
+}
+
+#[test]
+fn newer_copy_wins() {
+    assert_eq!(current([Some(1), Some(2)]), Some(1));
+    assert_eq!(current([Some(3), Some(2)]), Some(0));
+    // 代数回绕
+    assert_eq!(current([Some(255), Some(0)]), Some(1));
+    assert_eq!(current([Some(0), Some(255)]), Some(0));
+    assert_eq!(current([Some(4), Some(4)]), Some(0));
+    assert_eq!(current([None, Some(9)]), Some(1));
+    assert_eq!(current([Some(9), None]), Some(0));
+    assert_eq!(current([None, None]), None);
+}
+
+#[test]
+fn numbers_and_boot_modes() {
+    assert_eq!(parse_number("115200"), Some(115_200));
+    assert_eq!(parse_number("0x80040000"), Some(0x8004_0000));
+    assert_eq!(parse_number("fast"), None);
+    assert_eq!(BootMode::parse("sd"), Some(BootMode::Sd));
+    assert_eq!(BootMode::parse("usb"), None);
+    assert!(BootMode::Auto.loads(BootMode::Flash));
+    assert!(BootMode::Flash.loads(BootMode::Flash));
+    assert!(!BootMode::Ram.loads(BootMode::Serial));
+}
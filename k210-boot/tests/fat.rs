@@ -0,0 +1,358 @@
+use k210_boot::fat::{BlockDevice, FatError, Volume, BLOCK_SIZE};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+// 从磁盘镜像文件按块读取，和SD卡驱动一样实现BlockDevice
+struct ImageFile(File);
+
+impl BlockDevice for ImageFile {
+    type Error = io::ErrorKind;
+
+    fn read_block(&mut self, lba: u32, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), io::ErrorKind> {
+        self.0
+            .seek(SeekFrom::Start(lba as u64 * BLOCK_SIZE as u64))
+            .map_err(|e| e.kind())?;
+        self.0.read_exact(buf).map_err(|e| e.kind())
+    }
+}
+
+// 测试结束时删除镜像文件
+struct Image(PathBuf);
+
+impl Image {
+    fn write(name: &str, bytes: &[u8]) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("k210-boot-{}-{}.img", name, std::process::id()));
+        fs::write(&path, bytes).unwrap();
+        Image(path)
+    }
+
+    fn open(&self) -> Result<Volume<ImageFile>, FatError<io::ErrorKind>> {
+        Volume::open(ImageFile(File::open(&self.0).unwrap()))
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+const ROOT: u32 = 2;
+const END_OF_CHAIN: u32 = 0x0fff_ffff;
+
+// 构造FAT32磁盘镜像：两份FAT表，根目录是从2号簇开始的两个簇，其它目录的簇数在创建时确定
+struct Builder {
+    image: Vec<u8>,
+    start: usize,
+    sectors_per_cluster: usize,
+    reserved: usize,
+    fat_size: usize,
+    next_cluster: u32,
+    dirs: BTreeMap<u32, (Vec<u32>, Vec<[u8; 32]>)>,
+    short_names: u32,
+}
+
+impl Builder {
+    fn new(partitioned: bool, sectors_per_cluster: usize) -> Self {
+        let start = if partitioned { 63 } else { 0 };
+        let total = 4096;
+        let reserved = 32;
+        let clusters = total / sectors_per_cluster;
+        let fat_size = ((clusters + 2) * 4).div_ceil(BLOCK_SIZE);
+        let mut builder = Builder {
+            image: vec![0; (start + total) * BLOCK_SIZE],
+            start,
+            sectors_per_cluster,
+            reserved,
+            fat_size,
+            next_cluster: ROOT,
+            dirs: BTreeMap::new(),
+            short_names: 0,
+        };
+        let b = &mut builder.image[start * BLOCK_SIZE..];
+        b[..3].copy_from_slice(&[0xeb, 0x58, 0x90]);
+        b[3..11].copy_from_slice(b"MSWIN4.1");
+        b[11..13].copy_from_slice(&(BLOCK_SIZE as u16).to_le_bytes());
+        b[13] = sectors_per_cluster as u8;
+        b[14..16].copy_from_slice(&(reserved as u16).to_le_bytes());
+        b[16] = 2;
+        b[21] = 0xf8;
+        b[32..36].copy_from_slice(&(total as u32).to_le_bytes());
+        b[36..40].copy_from_slice(&(fat_size as u32).to_le_bytes());
+        b[44..48].copy_from_slice(&ROOT.to_le_bytes());
+        b[82..90].copy_from_slice(b"FAT32   ");
+        b[510..512].copy_from_slice(&[0x55, 0xaa]);
+        if partitioned {
+            let entry = &mut builder.image[446..462];
+            entry[4] = 0x0c;
+            entry[8..12].copy_from_slice(&(start as u32).to_le_bytes());
+            entry[12..16].copy_from_slice(&(total as u32).to_le_bytes());
+            builder.image[510..512].copy_from_slice(&[0x55, 0xaa]);
+        }
+        builder.set_fat(0, 0x0fff_fff8);
+        builder.set_fat(1, END_OF_CHAIN);
+        let root = builder.alloc(2, false);
+        builder.dirs.insert(ROOT, (root, Vec::new()));
+        builder
+    }
+
+    fn set_fat(&mut self, cluster: u32, value: u32) {
+        for fat in 0..2 {
+            let offset = (self.start + self.reserved + fat * self.fat_size) * BLOCK_SIZE
+                + cluster as usize * 4;
+            self.image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    // 分配簇链；fragmented为真时簇之间留出空隙
+    fn alloc(&mut self, count: usize, fragmented: bool) -> Vec<u32> {
+        let mut chain = Vec::new();
+        for _ in 0..count {
+            chain.push(self.next_cluster);
+            self.next_cluster += if fragmented { 2 } else { 1 };
+        }
+        for pair in chain.windows(2) {
+            self.set_fat(pair[0], pair[1]);
+        }
+        if let Some(&last) = chain.last() {
+            self.set_fat(last, END_OF_CHAIN);
+        }
+        chain
+    }
+
+    fn cluster_bytes(&self) -> usize {
+        self.sectors_per_cluster * BLOCK_SIZE
+    }
+
+    fn write_chain(&mut self, chain: &[u32], data: &[u8]) {
+        let size = self.cluster_bytes();
+        let data_start = self.start + self.reserved + 2 * self.fat_size;
+        for (&cluster, chunk) in chain.iter().zip(data.chunks(size)) {
+            let offset =
+                (data_start + (cluster as usize - 2) * self.sectors_per_cluster) * BLOCK_SIZE;
+            self.image[offset..offset + chunk.len()].copy_from_slice(chunk);
+        }
+    }
+
+    fn add_file(&mut self, dir: u32, name: &str, data: &[u8], fragmented: bool) -> u32 {
+        let count = data.len().div_ceil(self.cluster_bytes());
+        let chain = self.alloc(count, fragmented);
+        self.write_chain(&chain, data);
+        let first = chain.first().copied().unwrap_or(0);
+        self.add_entry(dir, name, 0x20, first, data.len() as u32);
+        first
+    }
+
+    fn mkdir(&mut self, parent: u32, name: &str, clusters: usize) -> u32 {
+        let chain = self.alloc(clusters, false);
+        let first = chain[0];
+        self.add_entry(parent, name, 0x10, first, 0);
+        self.dirs.insert(first, (chain, Vec::new()));
+        let parent = if parent == ROOT { 0 } else { parent };
+        self.push_entry(first, short_entry(*b".          ", 0x10, first, 0));
+        self.push_entry(first, short_entry(*b"..         ", 0x10, parent, 0));
+        first
+    }
+
+    fn push_entry(&mut self, dir: u32, entry: [u8; 32]) {
+        self.dirs.get_mut(&dir).unwrap().1.push(entry);
+    }
+
+    // 名字不是大写的8.3格式时，写入长文件名目录项和“~n”形式的短文件名
+    fn add_entry(&mut self, dir: u32, name: &str, attr: u8, first: u32, size: u32) {
+        let short = match to_short_name(name) {
+            Some(short) => short,
+            None => {
+                self.short_names += 1;
+                let mut short = *b"LONG~      ";
+                short[5] = b'0' + self.short_names as u8;
+                let ext = name.rsplit_once('.').map_or("", |(_, ext)| ext);
+                for (c, e) in short[8..].iter_mut().zip(ext.bytes()) {
+                    *c = e.to_ascii_uppercase();
+                }
+                let checksum = checksum(&short);
+                let chars: Vec<u16> = name.encode_utf16().collect();
+                let parts: Vec<&[u16]> = chars.chunks(13).collect();
+                for (i, part) in parts.iter().enumerate().rev() {
+                    let mut order = i as u8 + 1;
+                    if i == parts.len() - 1 {
+                        order |= 0x40;
+                    }
+                    self.push_entry(dir, long_entry(order, checksum, part));
+                }
+                short
+            }
+        };
+        self.push_entry(dir, short_entry(short, attr, first, size));
+    }
+
+    fn push_deleted(&mut self, dir: u32, name: &str) {
+        let mut entry = short_entry(to_short_name(name).unwrap(), 0x20, 0, 0);
+        entry[0] = 0xe5;
+        self.push_entry(dir, entry);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let dirs = std::mem::take(&mut self.dirs);
+        for (chain, entries) in dirs.values() {
+            let bytes: Vec<u8> = entries.iter().flatten().copied().collect();
+            assert!(bytes.len() <= chain.len() * self.cluster_bytes());
+            self.write_chain(chain, &bytes);
+        }
+        self.image
+    }
+}
+
+fn to_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+    let valid = |s: &str, max| {
+        !s.is_empty()
+            && s.len() <= max
+            && s.bytes()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+    };
+    if !valid(base, 8) || !(ext.is_empty() || valid(ext, 3)) {
+        return None;
+    }
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base.as_bytes());
+    short[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    Some(short)
+}
+
+fn short_entry(name: [u8; 11], attr: u8, first: u32, size: u32) -> [u8; 32] {
+    let mut entry = [0; 32];
+    entry[..11].copy_from_slice(&name);
+    entry[11] = attr;
+    entry[20..22].copy_from_slice(&((first >> 16) as u16).to_le_bytes());
+    entry[26..28].copy_from_slice(&(first as u16).to_le_bytes());
+    entry[28..32].copy_from_slice(&size.to_le_bytes());
+    entry
+}
+
+fn long_entry(order: u8, checksum: u8, part: &[u16]) -> [u8; 32] {
+    let mut entry = [0; 32];
+    entry[0] = order;
+    entry[11] = 0x0f;
+    entry[13] = checksum;
+    let mut chars = [0xffffu16; 13];
+    chars[..part.len()].copy_from_slice(part);
+    if part.len() < 13 {
+        chars[part.len()] = 0;
+    }
+    let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+    for (c, offset) in chars.iter().zip(offsets) {
+        entry[offset..offset + 2].copy_from_slice(&c.to_le_bytes());
+    }
+    entry
+}
+
+fn checksum(short: &[u8; 11]) -> u8 {
+    short
+        .iter()
+        .fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c))
+}
+
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u8).wrapping_mul(31) ^ seed)
+        .collect()
+}
+
+#[test]
+fn read_kernel_from_unpartitioned_disk() {
+    let kernel = pattern(5 * 1024 + 123, 0x5a);
+    let mut builder = Builder::new(false, 2);
+    builder.add_file(ROOT, "KERNEL.BIN", &kernel, true);
+    let image = Image::write("superfloppy", &builder.finish());
+
+    let mut volume = image.open().unwrap();
+    for path in ["/kernel.bin", "/KERNEL.BIN", "kernel.bin", "//Kernel.Bin"] {
+        let file = volume.find(path).unwrap();
+        assert!(!file.is_dir);
+        assert_eq!(file.size as usize, kernel.len());
+        let mut buf = vec![0; kernel.len() + 100];
+        assert_eq!(volume.read_file(&file, &mut buf), Ok(kernel.len()));
+        assert_eq!(&buf[..kernel.len()], &kernel[..]);
+    }
+}
+
+#[test]
+fn long_names_and_subdirectories_on_partitioned_disk() {
+    let kernel = pattern(3 * BLOCK_SIZE, 0x11);
+    let dtb = pattern(1000, 0x22);
+    let stale = pattern(64, 0x33);
+    let mut builder = Builder::new(true, 1);
+    // 根目录占两个簇，要找的目录在第二个簇
+    for i in 0..20 {
+        builder.add_file(ROOT, &format!("FILE{}.TXT", i), b"filler", false);
+    }
+    let boot = builder.mkdir(ROOT, "boot", 2);
+    builder.push_deleted(boot, "K210.DTB");
+    builder.add_file(boot, "K210.DTB", &dtb, false);
+    builder.add_file(boot, "rustsbi-test-kernel.bin", &kernel, true);
+    builder.add_file(boot, "old-kernel.bin", &stale, false);
+    let image = Image::write("partitioned", &builder.finish());
+
+    let mut volume = image.open().unwrap();
+    let mut buf = vec![0; 4096];
+    let file = volume.find("/boot/rustsbi-test-kernel.bin").unwrap();
+    assert_eq!(volume.read_file(&file, &mut buf), Ok(kernel.len()));
+    assert_eq!(&buf[..kernel.len()], &kernel[..]);
+    // 长文件名也可以用生成的短文件名访问，“boot”是第一个长文件名
+    let by_short_name = volume.find("/long~1/LONG~2.BIN").unwrap();
+    assert_eq!(by_short_name, file);
+    let file = volume.find("/Boot/k210.dtb").unwrap();
+    assert_eq!(volume.read_file(&file, &mut buf), Ok(dtb.len()));
+    assert_eq!(&buf[..dtb.len()], &dtb[..]);
+    assert!(volume.find("/boot").unwrap().is_dir);
+    assert_eq!(volume.find("/boot/..").unwrap(), volume.find("/").unwrap());
+}
+
+#[test]
+fn lookup_errors() {
+    let kernel = pattern(2000, 0x44);
+    let mut builder = Builder::new(true, 1);
+    builder.add_file(ROOT, "KERNEL.BIN", &kernel, false);
+    builder.mkdir(ROOT, "BOOT", 1);
+    let image = Image::write("errors", &builder.finish());
+
+    let mut volume = image.open().unwrap();
+    assert_eq!(volume.find("/missing.bin"), Err(FatError::NotFound));
+    assert_eq!(volume.find("/kernel.bin/x"), Err(FatError::NotADirectory));
+    let dir = volume.find("/boot").unwrap();
+    assert_eq!(
+        volume.read_file(&dir, &mut [0; 16]),
+        Err(FatError::NotAFile)
+    );
+    let file = volume.find("/kernel.bin").unwrap();
+    assert_eq!(
+        volume.read_file(&file, &mut [0; 1024]),
+        Err(FatError::FileTooLarge { size: 2000 })
+    );
+}
+
+#[test]
+fn broken_images_are_rejected() {
+    let empty = Image::write("empty", &vec![0; 64 * BLOCK_SIZE]);
+    assert!(matches!(empty.open(), Err(FatError::NoFilesystem)));
+
+    // 文件长度超过簇链，以及簇链指向文件系统以外
+    let kernel = pattern(4 * BLOCK_SIZE, 0x55);
+    let mut builder = Builder::new(false, 1);
+    let first = builder.add_file(ROOT, "SHORT.BIN", &kernel, false);
+    builder.set_fat(first + 1, END_OF_CHAIN);
+    let second = builder.add_file(ROOT, "WILD.BIN", &kernel, false);
+    builder.set_fat(second, 0x0100_0000);
+    let image = Image::write("broken", &builder.finish());
+
+    let mut volume = image.open().unwrap();
+    let mut buf = vec![0; kernel.len()];
+    for path in ["/short.bin", "/wild.bin"] {
+        let file = volume.find(path).unwrap();
+        assert_eq!(volume.read_file(&file, &mut buf), Err(FatError::Corrupted));
+    }
+}
@@ -0,0 +1,325 @@
+#![allow(unknown_lints, clippy::manual_is_multiple_of)]
+
+use k210_boot::fdt::{Fdt, FdtError};
+
+const OFF_TOTALSIZE: usize = 4;
+const OFF_DT_STRUCT: usize = 8;
+const OFF_DT_STRINGS: usize = 12;
+const OFF_MEM_RSVMAP: usize = 16;
+const OFF_SIZE_DT_STRINGS: usize = 32;
+const OFF_SIZE_DT_STRUCT: usize = 36;
+
+// 按标记拼出一个设备树：头部、空的保留内存表、结构块、字符串块
+#[derive(Default)]
+struct Builder {
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+}
+
+impl Builder {
+    fn token(&mut self, token: u32) -> &mut Self {
+        self.structure.extend_from_slice(&token.to_be_bytes());
+        self
+    }
+
+    fn begin(&mut self, name: &str) -> &mut Self {
+        self.token(1);
+        self.structure.extend_from_slice(name.as_bytes());
+        self.structure.push(0);
+        self.pad();
+        self
+    }
+
+    fn end(&mut self) -> &mut Self {
+        self.token(2)
+    }
+
+    fn prop(&mut self, name: &str, value: &[u8]) -> &mut Self {
+        let name_offset = self.strings.len() as u32;
+        self.strings.extend_from_slice(name.as_bytes());
+        self.strings.push(0);
+        self.token(3).token(value.len() as u32).token(name_offset);
+        self.structure.extend_from_slice(value);
+        self.pad();
+        self
+    }
+
+    fn pad(&mut self) {
+        while self.structure.len() % 4 != 0 {
+            self.structure.push(0);
+        }
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        self.token(9);
+        let off_struct = 40 + 16;
+        let off_strings = off_struct + self.structure.len();
+        let total = off_strings + self.strings.len();
+        let mut blob = vec![0; 40 + 16];
+        blob.extend_from_slice(&self.structure);
+        blob.extend_from_slice(&self.strings);
+        for (offset, value) in [
+            (0, 0xd00d_feed),
+            (OFF_TOTALSIZE, total),
+            (OFF_DT_STRUCT, off_struct),
+            (OFF_DT_STRINGS, off_strings),
+            (OFF_MEM_RSVMAP, 40),
+            (20, 17),
+            (24, 16),
+            (OFF_SIZE_DT_STRINGS, self.strings.len()),
+            (OFF_SIZE_DT_STRUCT, self.structure.len()),
+        ] {
+            set(&mut blob, offset, value as u32);
+        }
+        blob
+    }
+}
+
+fn set(blob: &mut [u8], offset: usize, value: u32) {
+    blob[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+fn get(blob: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(blob[offset..offset + 4].try_into().unwrap())
+}
+
+fn sample() -> Vec<u8> {
+    Builder::default()
+        .begin("")
+        .prop("compatible", b"kendryte,k210\0")
+        .begin("cpus")
+        .prop("#address-cells", &1u32.to_be_bytes())
+        .begin("cpu@0")
+        .prop("reg", &0u32.to_be_bytes())
+        .prop("status", b"okay\0")
+        .end()
+        .begin("cpu@1")
+        .prop("reg", &1u32.to_be_bytes())
+        .end()
+        .end()
+        .begin("memory@80000000")
+        .prop("reg", &[0x80, 0, 0, 0, 0, 0x60, 0, 0])
+        .end()
+        .end()
+        .finish()
+}
+
+fn open(blob: &[u8], extra: usize) -> Result<Vec<u8>, FdtError> {
+    let mut buf = vec![0; blob.len() + extra];
+    let fdt = Fdt::open_into(blob, &mut buf)?;
+    Ok(fdt.as_bytes().to_vec())
+}
+
+#[test]
+fn find_nodes_and_properties() {
+    let blob = sample();
+    let mut buf = vec![0; blob.len()];
+    let fdt = Fdt::open_into(&blob, &mut buf).unwrap();
+    assert_eq!(fdt.as_bytes(), &blob[..]);
+    assert_eq!(fdt.property(0, "compatible"), Some(&b"kendryte,k210\0"[..]));
+    let cpus = fdt.find_node("/cpus").unwrap();
+    let names: Vec<_> = fdt.children(cpus).map(|c| fdt.node_name(c)).collect();
+    assert_eq!(names, ["cpu@0", "cpu@1"]);
+    let cpu1 = fdt.find_node("/cpus/cpu@1").unwrap();
+    assert_eq!(fdt.property(cpu1, "reg"), Some(&[0, 0, 0, 1][..]));
+    assert_eq!(fdt.find_node("/memory"), fdt.find_node("/memory@80000000"));
+    assert_eq!(fdt.find_node("/chosen"), None);
+    assert_eq!(fdt.property(cpu1, "status"), None);
+}
+
+#[test]
+fn truncated_header() {
+    let blob = sample();
+    for len in [0, 4, 39] {
+        assert_eq!(open(&blob[..len], 0), Err(FdtError::Truncated));
+    }
+    // totalsize比头部还小，或者超出了实际长度
+    let mut short = blob.clone();
+    set(&mut short, OFF_TOTALSIZE, 8);
+    assert_eq!(open(&short, 0), Err(FdtError::Truncated));
+    assert_eq!(open(&blob[..blob.len() - 1], 0), Err(FdtError::Truncated));
+    let mut magic = blob.clone();
+    magic[0] = 0;
+    assert_eq!(open(&magic, 0), Err(FdtError::BadMagic));
+    let mut version = blob.clone();
+    set(&mut version, 20, 16);
+    assert_eq!(open(&version, 0), Err(FdtError::BadVersion));
+}
+
+#[test]
+fn bad_block_offsets() {
+    let blob = sample();
+    let off_struct = get(&blob, OFF_DT_STRUCT);
+    let size_struct = get(&blob, OFF_SIZE_DT_STRUCT);
+    let cases: [(usize, u32, FdtError); 7] = [
+        // 结构块不对齐
+        (OFF_DT_STRUCT, off_struct + 2, FdtError::BadStructure),
+        (OFF_SIZE_DT_STRUCT, size_struct - 2, FdtError::BadStructure),
+        // 保留内存表不对齐或者越界
+        (OFF_MEM_RSVMAP, 44, FdtError::BadStructure),
+        (
+            OFF_MEM_RSVMAP,
+            (blob.len() as u32 + 8) & !7,
+            FdtError::Truncated,
+        ),
+        // 结构块和字符串块越界
+        (OFF_DT_STRUCT, blob.len() as u32, FdtError::Truncated),
+        (OFF_DT_STRINGS, u32::MAX, FdtError::Truncated),
+        (OFF_SIZE_DT_STRINGS, u32::MAX, FdtError::Truncated),
+    ];
+    for (offset, value, error) in cases {
+        let mut bad = blob.clone();
+        set(&mut bad, offset, value);
+        assert_eq!(open(&bad, 0), Err(error), "field {} = {:#x}", offset, value);
+    }
+    // 结构块少了FDT_END，或者最后一个节点没有结束
+    let mut bad = blob.clone();
+    set(&mut bad, OFF_SIZE_DT_STRUCT, size_struct - 4);
+    assert_eq!(open(&bad, 0), Err(FdtError::BadStructure));
+    let mut bad = blob.clone();
+    set(&mut bad, OFF_SIZE_DT_STRUCT, size_struct - 8);
+    assert_eq!(open(&bad, 0), Err(FdtError::BadStructure));
+}
+
+#[test]
+fn bad_property_offsets() {
+    // 属性名的偏移量超出字符串块
+    let mut blob = Builder::default()
+        .begin("")
+        .prop("model", b"k210\0")
+        .end()
+        .finish();
+    let name_offset = 56 + 8 + 8;
+    assert_eq!(get(&blob, name_offset), 0);
+    set(&mut blob, name_offset, 0x1000);
+    assert_eq!(open(&blob, 0), Err(FdtError::BadStructure));
+    // 属性长度超出结构块
+    let mut blob = Builder::default()
+        .begin("")
+        .prop("model", b"k210\0")
+        .end()
+        .finish();
+    set(&mut blob, name_offset - 4, 0xffff_fff0);
+    assert_eq!(open(&blob, 0), Err(FdtError::BadStructure));
+    // 不认识的标记
+    let mut blob = sample();
+    set(&mut blob, 56 + 8, 7);
+    assert_eq!(open(&blob, 0), Err(FdtError::BadStructure));
+}
+
+#[test]
+fn corrupted_blobs_never_panic() {
+    let blob = sample();
+    for len in 0..blob.len() {
+        assert!(open(&blob[..len], 64).is_err());
+    }
+    for i in 0..blob.len() {
+        for value in [0x00, 0x03, 0x80, 0xff] {
+            let mut bad = blob.clone();
+            bad[i] = value;
+            let mut buf = vec![0; bad.len() + 64];
+            if let Ok(mut fdt) = Fdt::open_into(&bad, &mut buf) {
+                let _ = fdt.find_node("/cpus/cpu@1");
+                let _ = fdt.set_property_str(0, "bootargs", "console=ttyS0");
+                let _ = fdt.add_subnode(0, "chosen");
+            }
+        }
+    }
+}
+
+#[test]
+fn insert_nested_nodes() {
+    let blob = sample();
+    let mut buf = vec![0; blob.len() + 256];
+    let mut fdt = Fdt::open_into(&blob, &mut buf).unwrap();
+    let chosen = fdt.add_subnode(0, "chosen").unwrap();
+    assert_eq!(fdt.add_subnode(0, "chosen"), Ok(chosen));
+    let a = fdt.add_subnode(chosen, "a").unwrap();
+    let b = fdt.add_subnode(a, "b@10").unwrap();
+    fdt.set_property_u32(b, "reg", 0x10).unwrap();
+    fdt.set_property_str(chosen, "bootargs", "earlycon")
+        .unwrap();
+    // 修改chosen的属性以后，它后面的节点需要重新查找
+    let b = fdt.find_node("/chosen/a/b").unwrap();
+    assert_eq!(fdt.property(b, "reg"), Some(&[0, 0, 0, 0x10][..]));
+    let chosen = fdt.find_node("/chosen").unwrap();
+    assert_eq!(fdt.property(chosen, "bootargs"), Some(&b"earlycon\0"[..]));
+    // 新节点位于根节点的属性之后，原来的节点不受影响
+    let names: Vec<_> = fdt.children(0).map(|c| fdt.node_name(c)).collect();
+    assert_eq!(names, ["chosen", "cpus", "memory@80000000"]);
+    let cpu1 = fdt.find_node("/cpus/cpu@1").unwrap();
+    assert_eq!(fdt.property(cpu1, "reg"), Some(&[0, 0, 0, 1][..]));
+    // 结果仍然是合法的设备树
+    let edited = fdt.as_bytes().to_vec();
+    assert_eq!(open(&edited, 0), Ok(edited.clone()));
+}
+
+#[test]
+fn replace_properties() {
+    let blob = sample();
+    let mut buf = vec![0; blob.len() + 256];
+    let mut fdt = Fdt::open_into(&blob, &mut buf).unwrap();
+    let size = fdt.totalsize();
+    // 变长：从4字节变成12字节
+    let cpu0 = fdt.find_node("/cpus/cpu@0").unwrap();
+    fdt.set_property_cells(cpu0, "reg", &[1, 2, 3]).unwrap();
+    assert_eq!(
+        fdt.property(cpu0, "reg"),
+        Some(&[0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3][..])
+    );
+    assert_eq!(fdt.totalsize(), size + 8);
+    // 变短：补齐到4字节后从8字节变成4字节
+    fdt.set_property_str(cpu0, "status", "ok").unwrap();
+    assert_eq!(fdt.property(cpu0, "status"), Some(&b"ok\0"[..]));
+    assert_eq!(fdt.totalsize(), size + 4);
+    fdt.set_property(cpu0, "status", &[]).unwrap();
+    assert_eq!(fdt.property(cpu0, "status"), Some(&[][..]));
+    assert_eq!(fdt.totalsize(), size);
+    // 其它节点的属性不变
+    let cpu1 = fdt.find_node("/cpus/cpu@1").unwrap();
+    assert_eq!(fdt.property(cpu1, "reg"), Some(&[0, 0, 0, 1][..]));
+    let memory = fdt.find_node("/memory").unwrap();
+    assert_eq!(
+        fdt.property(memory, "reg"),
+        Some(&[0x80, 0, 0, 0, 0, 0x60, 0, 0][..])
+    );
+    let edited = fdt.as_bytes().to_vec();
+    assert_eq!(open(&edited, 0), Ok(edited.clone()));
+}
+
+#[test]
+fn output_buffer_overflow() {
+    let blob = sample();
+    let mut small = vec![0; blob.len() - 1];
+    assert!(matches!(
+        Fdt::open_into(&blob, &mut small),
+        Err(FdtError::NoSpace)
+    ));
+    let mut buf = vec![0; blob.len() + 16];
+    let mut fdt = Fdt::open_into(&blob, &mut buf).unwrap();
+    let before = fdt.as_bytes().to_vec();
+    let cpu0 = fdt.find_node("/cpus/cpu@0").unwrap();
+    // 放不下时返回错误，设备树保持原样
+    assert_eq!(
+        fdt.set_property_str(cpu0, "status", "disabled, and a lot longer"),
+        Err(FdtError::NoSpace)
+    );
+    assert_eq!(
+        fdt.set_property_cells(cpu0, "interrupts", &[1, 2]),
+        Err(FdtError::NoSpace)
+    );
+    assert_eq!(
+        fdt.set_property(0, "serial-number", &[1, 2, 3, 4]),
+        Err(FdtError::NoSpace)
+    );
+    assert_eq!(
+        fdt.add_subnode(0, "a-rather-long-node-name"),
+        Err(FdtError::NoSpace)
+    );
+    assert_eq!(fdt.as_bytes(), &before[..]);
+    // 正好放得下
+    fdt.set_property_cells(cpu0, "reg", &[0, 1, 2, 3, 4])
+        .unwrap();
+    assert_eq!(fdt.totalsize(), fdt.as_bytes().len());
+    assert_eq!(fdt.totalsize(), blob.len() + 16);
+}
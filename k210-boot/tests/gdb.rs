@@ -0,0 +1,356 @@
+use k210_boot::gdb::{
+    next_pc, parse_request, Action, Event, PacketReader, Request, Response, Stub, Target, PC,
+    SIGILL, SIGTRAP,
+};
+
+const BASE: usize = 0x8020_0000;
+
+// 0x00: addi a0, a0, 1
+// 0x04: beq a0, a1, 0x10
+// 0x08: c.addi a0, 1
+// 0x0a: c.j 0x00
+// 0x0c: nop
+// 0x10: ret
+const PROGRAM: [u8; 20] = [
+    0x13, 0x05, 0x15, 0x00, 0x63, 0x06, 0xb5, 0x00, 0x05, 0x05, 0xdd, 0xbf, 0x13, 0x00, 0x00, 0x00,
+    0x67, 0x80, 0x00, 0x00,
+];
+
+struct Board {
+    registers: [usize; 33],
+    memory: Vec<u8>,
+    sepc: usize,
+    syncs: usize,
+}
+
+impl Board {
+    fn new() -> Self {
+        let mut registers = [0; 33];
+        registers[1] = 0x8020_0100; // ra
+        registers[2] = 0x8021_0000; // sp
+        registers[10] = 1; // a0
+        registers[11] = 2; // a1
+        registers[PC] = BASE;
+        let mut memory = vec![0; 0x200];
+        memory[..PROGRAM.len()].copy_from_slice(&PROGRAM);
+        Board {
+            registers,
+            memory,
+            sepc: 0,
+            syncs: 0,
+        }
+    }
+
+    fn bytes(&self, address: usize, len: usize) -> &[u8] {
+        &self.memory[address - BASE..address - BASE + len]
+    }
+}
+
+impl Target for Board {
+    fn register(&self, number: usize) -> usize {
+        self.registers[number]
+    }
+
+    fn set_register(&mut self, number: usize, value: usize) {
+        if number != 0 {
+            self.registers[number] = value;
+        }
+    }
+
+    fn read_byte(&mut self, address: usize) -> Option<u8> {
+        self.memory.get(address.checked_sub(BASE)?).copied()
+    }
+
+    fn write_byte(&mut self, address: usize, value: u8) -> bool {
+        match address
+            .checked_sub(BASE)
+            .and_then(|offset| self.memory.get_mut(offset))
+        {
+            Some(byte) => {
+                *byte = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn sync_instructions(&mut self) {
+        self.syncs += 1;
+    }
+
+    fn sepc(&self) -> usize {
+        self.sepc
+    }
+}
+
+// 模拟固件中的收发循环
+struct Session {
+    stub: Stub,
+    board: Board,
+    reader: PacketReader,
+    response: Response,
+}
+
+impl Session {
+    fn new() -> Self {
+        Session {
+            stub: Stub::new(),
+            board: Board::new(),
+            reader: PacketReader::new(),
+            response: Response::new(),
+        }
+    }
+
+    // 把GDB发来的字节交给桩，返回桩发出的字节；收到继续运行的请求时返回，第二个值为真
+    fn exchange(&mut self, input: &str) -> (String, bool) {
+        let mut output = Vec::new();
+        for &byte in input.as_bytes() {
+            match self.reader.feed(byte) {
+                Some(Event::Packet(packet)) => {
+                    output.push(b'+');
+                    let action = self
+                        .stub
+                        .handle(packet, &mut self.board, &mut self.response);
+                    if action == Action::Reply || !self.response.is_empty() {
+                        output.extend_from_slice(self.response.finish());
+                    }
+                    if action == Action::Resume {
+                        return (String::from_utf8(output).unwrap(), true);
+                    }
+                }
+                Some(Event::BadPacket) => output.push(b'-'),
+                Some(Event::Nack) => output.extend_from_slice(self.response.finish()),
+                Some(Event::Ack) | Some(Event::Interrupt) | None => {}
+            }
+        }
+        (String::from_utf8(output).unwrap(), false)
+    }
+
+    // 特权级执行到断点或者出错，桩报告停止原因
+    fn trap(&mut self, pc: usize, signal: u8) -> String {
+        self.board.registers[PC] = pc;
+        self.stub.stop(signal, &mut self.board, &mut self.response);
+        String::from_utf8(self.response.finish().to_vec()).unwrap()
+    }
+}
+
+// 一次`target remote`连接，GDB发送的包和桩的回复按原样记录
+#[test]
+fn recorded_connect_session() {
+    let mut session = Session::new();
+    assert_eq!(session.trap(BASE, SIGTRAP), "$S05#b8");
+    let recorded = [
+        (
+            "+$qSupported:multiprocess+;swbreak+;hwbreak+;qRelocInsn+;fork-events+;vfork-events+;exec-events+;vContSupported+;QThreadEvents+;no-resumed+;memory-tagging+#ec",
+            "+$PacketSize=0400#f4",
+        ),
+        ("+$vMustReplyEmpty#3a", "+$#00"),
+        ("+$QStartNoAckMode#b0", "+$#00"),
+        ("+$Hg0#df", "+$OK#9a"),
+        ("+$qTStatus#49", "+$#00"),
+        ("+$?#3f", "+$S05#b8"),
+        ("+$qfThreadInfo#bb", "+$#00"),
+        ("+$Hc-1#09", "+$OK#9a"),
+        ("+$qC#b4", "+$#00"),
+        ("+$qAttached#8f", "+$1#31"),
+        ("+$qOffsets#4b", "+$#00"),
+        (
+            "+$g#67",
+            "+$000000000000000000012080000000000000218000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000200000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000208000000000#23",
+        ),
+        ("+$m80200000,12#86", "+$130515006306b5000505ddbf130000006780#08"),
+        ("+$p0a#01", "+$0100000000000000#01"),
+        ("+$p41#d5", "+$xxxxxxxxxxxxxxxx#80"),
+        ("+$qSymbol::#5b", "+$#00"),
+        ("+$m0,4#fd", "+$E14#aa"),
+    ];
+    for (gdb, stub) in recorded {
+        assert_eq!(session.exchange(gdb), (stub.to_string(), false), "{}", gdb);
+    }
+    assert!(session.stub.attached());
+}
+
+// 设置断点、继续运行、停在断点上、移除断点、分离
+#[test]
+fn recorded_breakpoint_session() {
+    let mut session = Session::new();
+    session.trap(BASE, SIGTRAP);
+    assert_eq!(
+        session.exchange("+$Z0,80200008,2#a6"),
+        ("+$OK#9a".to_string(), false)
+    );
+    assert_eq!(session.board.bytes(BASE + 8, 2), [0x02, 0x90]);
+    assert!(session.stub.is_breakpoint(BASE + 8));
+    assert_eq!(
+        session.exchange("+$Z0,80200004,4#a4"),
+        ("+$OK#9a".to_string(), false)
+    );
+    assert_eq!(session.board.bytes(BASE + 4, 4), [0x73, 0x00, 0x10, 0x00]);
+    assert_eq!(session.exchange("+$c#63"), ("+".to_string(), true));
+    // 其它的ebreak交给特权级
+    assert!(!session.stub.is_breakpoint(BASE + 0x10));
+    assert_eq!(session.trap(BASE + 8, SIGTRAP), "$S05#b8");
+    assert_eq!(
+        session.exchange("+$z0,80200008,2#c6"),
+        ("+$OK#9a".to_string(), false)
+    );
+    assert_eq!(session.board.bytes(BASE + 8, 2), [0x05, 0x05]);
+    // 不支持硬件断点
+    assert_eq!(
+        session.exchange("+$Z1,80200000,4#a1"),
+        ("+$#00".to_string(), false)
+    );
+    assert_eq!(session.exchange("+$D#44"), ("+$OK#9a".to_string(), true));
+    assert_eq!(session.board.bytes(0x8020_0000, 8), &PROGRAM[..8]);
+    assert!(!session.stub.attached());
+}
+
+// 单步：分支不跳转、跳转、压缩跳转，每次都在下一条指令上临时插入断点
+#[test]
+fn recorded_step_session() {
+    let mut session = Session::new();
+    session.trap(BASE, SIGTRAP);
+    // addi
+    assert_eq!(session.exchange("+$s#73"), ("+".to_string(), true));
+    assert_eq!(session.board.bytes(BASE + 4, 4), [0x73, 0x00, 0x10, 0x00]);
+    assert!(session.stub.is_breakpoint(BASE + 4));
+    session.board.registers[10] = 2;
+    assert_eq!(session.trap(BASE + 4, SIGTRAP), "$S05#b8");
+    assert_eq!(session.board.bytes(BASE + 4, 4), &PROGRAM[4..8]);
+    // a0 == a1，beq跳到0x10
+    session.exchange("+$s#73");
+    assert!(session.stub.is_breakpoint(BASE + 0x10));
+    assert!(!session.stub.is_breakpoint(BASE + 8));
+    session.trap(BASE + 0x10, SIGTRAP);
+    // 不相等时顺序执行，下一条是压缩指令，插入c.ebreak
+    session.board.registers[11] = 3;
+    session.board.registers[PC] = BASE + 4;
+    session.exchange("+$s#73");
+    assert_eq!(session.board.bytes(BASE + 8, 4), [0x02, 0x90, 0xdd, 0xbf]);
+    // 在别处出错时同样移除临时断点
+    assert_eq!(session.trap(BASE + 0x20, SIGILL), "$S04#b7");
+    assert_eq!(session.board.bytes(BASE + 8, 2), [0x05, 0x05]);
+    // c.j跳回开头
+    session.board.registers[PC] = BASE + 0xa;
+    session.exchange("+$s#73");
+    assert!(session.stub.is_breakpoint(BASE));
+    session.trap(BASE, SIGTRAP);
+    assert_eq!(session.board.bytes(BASE, 20), PROGRAM);
+}
+
+#[test]
+fn registers_and_memory_writes() {
+    let mut session = Session::new();
+    session.trap(BASE, SIGTRAP);
+    assert_eq!(
+        session.exchange("+$P20=1000208000000000#fa"),
+        ("+$OK#9a".to_string(), false)
+    );
+    assert_eq!(session.board.registers[PC], BASE + 0x10);
+    assert_eq!(
+        session.exchange("+$M80200010,2:0000#30"),
+        ("+$OK#9a".to_string(), false)
+    );
+    assert_eq!(session.board.bytes(BASE + 0x10, 2), [0, 0]);
+    assert!(session.board.syncs > 0);
+    assert_eq!(
+        session.exchange("+$m80200010,2#56"),
+        ("+$0000#c0".to_string(), false)
+    );
+    // 长度和数据不符
+    assert_eq!(
+        session.exchange("+$M80200010,4:0000#32"),
+        ("+$E01#a6".to_string(), false)
+    );
+}
+
+#[test]
+fn packet_framing() {
+    let mut reader = PacketReader::new();
+    let mut events = Vec::new();
+    // 包以外的杂散字节、确认、Ctrl-C
+    for &byte in b"junk+-\x03" {
+        if let Some(event) = reader.feed(byte) {
+            events.push(format!("{:?}", event));
+        }
+    }
+    assert_eq!(events, ["Ack", "Nack", "Interrupt"]);
+    // 校验和错误
+    let mut bad = None;
+    for &byte in b"$g#68" {
+        bad = reader.feed(byte).map(|event| event == Event::BadPacket);
+    }
+    assert_eq!(bad, Some(true));
+    // 转义：}]表示}
+    let mut packet = Vec::new();
+    for &byte in b"$X0,1:}]#f9" {
+        if let Some(Event::Packet(data)) = reader.feed(byte) {
+            packet = data.to_vec();
+        }
+    }
+    assert_eq!(packet, b"X0,1:}");
+
+    let mut response = Response::new();
+    assert_eq!(response.finish(), b"$#00");
+    response.push(b"a#b");
+    assert_eq!(response.finish(), b"$a}\x03b#43");
+    response.clear();
+    response.push_hex(&[0xde, 0xad]);
+    assert_eq!(response.finish(), b"$dead#8e");
+}
+
+#[test]
+fn request_parsing() {
+    assert_eq!(
+        parse_request(b"m80200000,40"),
+        Some(Request::ReadMemory {
+            address: 0x8020_0000,
+            len: 0x40
+        })
+    );
+    assert_eq!(parse_request(b"c"), Some(Request::Continue(None)));
+    assert_eq!(
+        parse_request(b"C0b;80200000"),
+        Some(Request::Continue(Some(0x8020_0000)))
+    );
+    assert_eq!(parse_request(b"S05"), Some(Request::Step(None)));
+    assert_eq!(parse_request(b"m80200000"), None);
+    assert_eq!(parse_request(b"mzz,4"), None);
+    assert_eq!(parse_request(b"vCont?"), Some(Request::Unsupported));
+    assert_eq!(parse_request(b""), Some(Request::Unsupported));
+}
+
+#[test]
+fn next_pc_decoding() {
+    let regs = |r: usize| match r {
+        1 => 0x8020_0101,  // ra，最低位被清除
+        8 => 0,            // s0
+        10 => 5,           // a0
+        11 => 7,           // a1
+        15 => 0x8030_0000, // a5
+        _ => 0,
+    };
+    let pc = 0x8020_1000;
+    let cases: &[(u32, usize)] = &[
+        (0x1000_00ef, pc + 0x100),  // jal ra, 0x100
+        (0xff9f_f06f, pc - 8),      // j -8
+        (0x0107_80e7, 0x8030_0010), // jalr 16(a5)
+        (0x02b5_0063, pc + 4),      // beq a0, a1, 32
+        (0xfeb5_18e3, pc - 16),     // bne a0, a1, -16
+        (0x04b5_4063, pc + 64),     // blt a0, a1, 64
+        (0x80b5_70e3, pc + 4),      // bgeu a0, a1, -2048
+        (0xb7c1, pc - 64),          // c.j -64
+        (0xaffd, pc + 2046),        // c.j 2046
+        (0xc035, pc + 100),         // c.beqz s0, 100
+        (0xf3e5, pc - 32),          // c.bnez a5, -32
+        (0x8082, 0x8020_0100),      // ret
+        (0x9502, 4),                // c.jalr a0
+        (0x9002, pc + 2),           // c.ebreak
+        (0x1020_0073, 0x8040_0000), // sret
+        (0x0505, pc + 2),           // c.addi a0, 1
+        (0x0015_0513, pc + 4),      // addi a0, a0, 1
+    ];
+    for &(ins, expected) in cases {
+        assert_eq!(next_pc(ins, pc, regs, 0x8040_0000), expected, "{:#x}", ins);
+    }
+}
@@ -0,0 +1,125 @@
+use k210_boot::lz4::{decompress, frame_info, xxh32, Lz4Error};
+use lz4_flex::frame::{BlockMode, BlockSize, FrameEncoder, FrameInfo};
+use std::io::Write;
+
+// 和xtask一样用lz4_flex压缩
+fn compress(data: &[u8], info: FrameInfo) -> Vec<u8> {
+    let mut encoder = FrameEncoder::with_frame_info(info, Vec::new());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn full_info(len: usize) -> FrameInfo {
+    FrameInfo::new()
+        .content_size(Some(len as u64))
+        .content_checksum(true)
+        .block_checksums(true)
+}
+
+// 像内核一样既有重复的片段也有不好压缩的数据
+fn sample_kernel(len: usize) -> Vec<u8> {
+    let mut state = 0x1234_5678u32;
+    (0..len)
+        .map(|i| {
+            if i % 4096 < 3000 {
+                (i % 251) as u8
+            } else {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn xxh32_check_values() {
+    assert_eq!(xxh32(b"", 0), 0x02cc_5d05);
+    assert_eq!(xxh32(b"abc", 0), 0x32d1_53ff);
+    assert_eq!(
+        xxh32(b"Nobody inspects the spammish repetition", 0),
+        0xe229_3b2f
+    );
+}
+
+#[test]
+fn decompress_lz4_flex_frames() {
+    let kernel = sample_kernel(300_000);
+    for info in [
+        full_info(kernel.len()),
+        full_info(kernel.len()).block_mode(BlockMode::Linked),
+        full_info(kernel.len()).block_size(BlockSize::Max4MB),
+        FrameInfo::new(),
+    ] {
+        let frame = compress(&kernel, info);
+        assert!(frame.len() < kernel.len());
+        let mut out = vec![0; kernel.len() + 100];
+        assert_eq!(decompress(&frame, &mut out), Ok(kernel.len()));
+        assert_eq!(&out[..kernel.len()], &kernel[..]);
+    }
+    // 不好压缩的块原样存储
+    let noise: Vec<u8> = sample_kernel(4096)[3000..].to_vec();
+    let frame = compress(&noise, full_info(noise.len()));
+    let mut out = vec![0; noise.len()];
+    assert_eq!(decompress(&frame, &mut out), Ok(noise.len()));
+    assert_eq!(out, noise);
+}
+
+#[test]
+fn frame_info_reports_content_size() {
+    let kernel = sample_kernel(5000);
+    let frame = compress(&kernel, full_info(kernel.len()));
+    let info = frame_info(&frame).unwrap();
+    assert_eq!(info.content_size, Some(5000));
+    assert!(info.content_checksum && info.block_checksum);
+    assert_eq!(frame_info(&kernel), Err(Lz4Error::BadMagic));
+    let mut bad = frame.clone();
+    bad[6] ^= 1;
+    assert_eq!(frame_info(&bad), Err(Lz4Error::HeaderChecksum));
+}
+
+#[test]
+fn corrupted_frames_are_rejected() {
+    let kernel = sample_kernel(20_000);
+    let frame = compress(&kernel, full_info(kernel.len()));
+    let mut out = vec![0; kernel.len()];
+    let mut bad = frame.clone();
+    bad[40] ^= 0x10;
+    assert_eq!(decompress(&bad, &mut out), Err(Lz4Error::BlockChecksum));
+    assert_eq!(
+        decompress(&frame[..frame.len() - 6], &mut out),
+        Err(Lz4Error::Truncated)
+    );
+    // 没有数据块校验和时由内容校验和发现错误
+    let frame = compress(&kernel, full_info(kernel.len()).block_checksums(false));
+    let mut bad = frame.clone();
+    let last = bad.len() - 20;
+    bad[last] ^= 0x01;
+    assert!(decompress(&bad, &mut out).is_err());
+}
+
+#[test]
+fn output_must_be_large_enough() {
+    let kernel = sample_kernel(20_000);
+    let frame = compress(&kernel, full_info(kernel.len()));
+    let mut out = vec![0; kernel.len() - 1];
+    assert_eq!(decompress(&frame, &mut out), Err(Lz4Error::OutputTooSmall));
+}
+
+#[test]
+fn content_size_is_checked() {
+    let kernel = sample_kernel(10_000);
+    // 改写帧描述符中的长度，并重新计算描述符校验和
+    let mut frame = compress(&kernel, FrameInfo::new().content_size(Some(10_000)));
+    frame[6..14].copy_from_slice(&9_999u64.to_le_bytes());
+    frame[14] = (xxh32(&frame[4..14], 0) >> 8) as u8;
+    let mut out = vec![0; 20_000];
+    assert_eq!(
+        decompress(&frame, &mut out),
+        Err(Lz4Error::ContentSize {
+            expected: 9_999,
+            actual: 10_000
+        })
+    );
+}
@@ -0,0 +1,106 @@
+use k210_boot::monitor::{
+    csr_instruction, csr_number, parse, Command, CommandError, Edit, LineEditor, DEFAULT_DUMP_LEN,
+    LINE_MAX,
+};
+
+#[test]
+fn memory_commands() {
+    assert_eq!(
+        parse("md 0x80020000"),
+        Ok(Some(Command::Dump {
+            address: 0x8002_0000,
+            len: DEFAULT_DUMP_LEN
+        }))
+    );
+    assert_eq!(
+        parse("  md 0x80020000   256 "),
+        Ok(Some(Command::Dump {
+            address: 0x8002_0000,
+            len: 256
+        }))
+    );
+    assert_eq!(
+        parse("mw 0x80020000 0x00000013"),
+        Ok(Some(Command::Write {
+            address: 0x8002_0000,
+            value: 0x13
+        }))
+    );
+    assert_eq!(parse("mw 0x80020000"), Err(CommandError::MissingArgument));
+    assert_eq!(
+        parse("mw 0x80020000 0x100000000"),
+        Err(CommandError::BadNumber("0x100000000"))
+    );
+    assert_eq!(parse("md zero"), Err(CommandError::BadNumber("zero")));
+    assert_eq!(parse("md 0 1 2"), Err(CommandError::TooManyArguments));
+}
+
+#[test]
+fn other_commands() {
+    assert_eq!(parse(""), Ok(None));
+    assert_eq!(parse("   "), Ok(None));
+    assert_eq!(parse("?"), Ok(Some(Command::Help)));
+    assert_eq!(
+        parse("csr"),
+        Ok(Some(Command::Csr {
+            number: None,
+            value: None
+        }))
+    );
+    assert_eq!(
+        parse("csr mie 0x8"),
+        Ok(Some(Command::Csr {
+            number: Some(0x304),
+            value: Some(8)
+        }))
+    );
+    assert_eq!(parse("traps 1"), Ok(Some(Command::Traps { hart: Some(1) })));
+    assert_eq!(parse("tracemask 0x5"), Ok(Some(Command::TraceMask(5))));
+    assert_eq!(parse("tracemask"), Err(CommandError::MissingArgument));
+    assert_eq!(
+        parse("addr 0x80040000"),
+        Ok(Some(Command::Address(0x8004_0000)))
+    );
+    // 命令行中的空格保留原样
+    assert_eq!(
+        parse("bootargs console=hvc0  earlycon"),
+        Ok(Some(Command::Bootargs("console=hvc0  earlycon")))
+    );
+    assert_eq!(parse("bootargs"), Ok(Some(Command::Bootargs(""))));
+    assert_eq!(parse("boot"), Ok(Some(Command::Boot)));
+    assert_eq!(parse("go"), Err(CommandError::Unknown("go")));
+}
+
+#[test]
+fn csr_names_and_numbers() {
+    assert_eq!(csr_number("mstatus"), Ok(0x300));
+    assert_eq!(csr_number("0x7c0"), Ok(0x7c0));
+    assert_eq!(
+        csr_number("0x1000"),
+        Err(CommandError::UnknownCsr("0x1000"))
+    );
+    assert_eq!(csr_number("mtval"), Err(CommandError::UnknownCsr("mtval")));
+    // csrr a1, mstatus
+    assert_eq!(csr_instruction(0x300, false), 0x3000_25f3);
+    // csrrw a1, mscratch, a1
+    assert_eq!(csr_instruction(0x340, true), 0x3405_95f3);
+}
+
+#[test]
+fn line_editing() {
+    let mut editor = LineEditor::new();
+    for &byte in b"mdx" {
+        assert_eq!(editor.feed(byte), Edit::Echo(byte));
+    }
+    assert_eq!(editor.feed(0x7f), Edit::Erase);
+    assert_eq!(editor.feed(0x1b), Edit::Ignore);
+    assert_eq!(editor.feed(b'\r'), Edit::Enter);
+    assert_eq!(editor.line(), "md");
+    editor.clear();
+    assert_eq!(editor.feed(0x08), Edit::Ignore);
+    for _ in 0..LINE_MAX {
+        editor.feed(b'a');
+    }
+    assert_eq!(editor.feed(b'a'), Edit::Ignore);
+    assert_eq!(editor.line().len(), LINE_MAX);
+}
@@ -0,0 +1,117 @@
+use k210_boot::payload::{
+    crc32, HeaderError, PayloadHeader, VerifyError, FLAG_LZ4, FLAG_SHA256, PAYLOAD_HEADER_SIZE,
+};
+
+const LOAD: u64 = 0x8002_0000;
+
+// 模拟闪存中的镜像：描述头后面紧跟程序
+fn sample_image(payload: &[u8], with_sha256: bool) -> Vec<u8> {
+    let header = PayloadHeader::new(LOAD, LOAD + 0x10, payload, with_sha256);
+    let mut image = header.to_bytes().to_vec();
+    image.extend_from_slice(payload);
+    image
+}
+
+fn sample_payload() -> Vec<u8> {
+    (0..4096u32).map(|i| (i * 7 + 3) as u8).collect()
+}
+
+#[test]
+fn crc32_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    assert_eq!(crc32(b""), 0);
+}
+
+#[test]
+fn parse_and_verify_sample_image() {
+    let payload = sample_payload();
+    for with_sha256 in [false, true] {
+        let image = sample_image(&payload, with_sha256);
+        let header = PayloadHeader::parse(&image).unwrap();
+        assert_eq!(header.load_address, LOAD);
+        assert_eq!(header.entry, LOAD + 0x10);
+        assert_eq!(header.size, payload.len() as u64);
+        assert_eq!(header.flags & FLAG_SHA256 != 0, with_sha256);
+        assert_eq!(header.verify(&image[PAYLOAD_HEADER_SIZE..]), Ok(()));
+    }
+}
+
+#[test]
+fn corrupted_payload_is_rejected() {
+    let payload = sample_payload();
+    let mut image = sample_image(&payload, true);
+    image[PAYLOAD_HEADER_SIZE + 100] ^= 0x40;
+    let header = PayloadHeader::parse(&image).unwrap();
+    assert!(matches!(
+        header.verify(&image[PAYLOAD_HEADER_SIZE..]),
+        Err(VerifyError::Crc32 { .. })
+    ));
+    assert!(matches!(
+        header.verify(&image[PAYLOAD_HEADER_SIZE..PAYLOAD_HEADER_SIZE + 10]),
+        Err(VerifyError::Size { .. })
+    ));
+}
+
+#[test]
+fn sha256_mismatch_is_rejected() {
+    let payload = sample_payload();
+    let mut header = PayloadHeader::new(LOAD, LOAD, &payload, true);
+    header.sha256[0] ^= 1;
+    let header = PayloadHeader::parse(&header.to_bytes()).unwrap();
+    assert_eq!(header.verify(&payload), Err(VerifyError::Sha256));
+}
+
+#[test]
+fn bad_headers_are_rejected() {
+    let image = sample_image(&sample_payload(), false);
+    assert_eq!(
+        PayloadHeader::parse(&image[..PAYLOAD_HEADER_SIZE - 1]),
+        Err(HeaderError::TooShort)
+    );
+    // 擦除后的闪存读出全1
+    assert_eq!(
+        PayloadHeader::parse(&[0xff; PAYLOAD_HEADER_SIZE]),
+        Err(HeaderError::BadMagic)
+    );
+    let mut bad_version = image.clone();
+    bad_version[8] = 2;
+    assert_eq!(
+        PayloadHeader::parse(&bad_version),
+        Err(HeaderError::UnsupportedVersion(2))
+    );
+    let mut bad_field = image.clone();
+    bad_field[20] ^= 1;
+    assert!(matches!(
+        PayloadHeader::parse(&bad_field),
+        Err(HeaderError::HeaderCrc { .. })
+    ));
+    let outside = PayloadHeader::new(LOAD, LOAD + 0x10_0000, &sample_payload(), false);
+    assert_eq!(
+        PayloadHeader::parse(&outside.to_bytes()),
+        Err(HeaderError::EntryOutOfRange)
+    );
+}
+
+#[test]
+fn compressed_entry_checked_against_unpacked_size() {
+    // 压缩后只有4K，入口在解压后的第64K处
+    let mut header = PayloadHeader::new(LOAD, LOAD + 0x1_0000, &sample_payload(), false);
+    header.flags |= FLAG_LZ4;
+    let header = PayloadHeader::parse(&header.to_bytes()).unwrap();
+    assert_eq!(header.check_entry(0x2_0000), Ok(()));
+    assert_eq!(
+        header.check_entry(0x1_0000),
+        Err(HeaderError::EntryOutOfRange)
+    );
+    assert_eq!(
+        header.check_entry(u64::MAX),
+        Err(HeaderError::EntryOutOfRange)
+    );
+    // 入口在加载地址之前时不用解压就能发现
+    let mut before = PayloadHeader::new(LOAD, LOAD - 4, &sample_payload(), false);
+    before.flags |= FLAG_LZ4;
+    assert_eq!(
+        PayloadHeader::parse(&before.to_bytes()),
+        Err(HeaderError::EntryOutOfRange)
+    );
+}
@@ -0,0 +1,83 @@
+use k210_boot::signature::{
+    parse_key, public_key, split, verify, SignatureError, Trailer, TRAILER_SIZE,
+};
+
+const SECRET_KEY: [u8; 32] = [0x42; 32];
+
+// 内核后面附加签名尾部
+fn signed_image(payload: &[u8], secret_key: &[u8; 32]) -> Vec<u8> {
+    let mut image = payload.to_vec();
+    image.extend_from_slice(&Trailer::sign(payload, secret_key).to_bytes());
+    image
+}
+
+fn sample_payload() -> Vec<u8> {
+    (0..5000u32).map(|i| (i * 11 + 5) as u8).collect()
+}
+
+#[test]
+fn signed_image_is_accepted() {
+    let payload = sample_payload();
+    let image = signed_image(&payload, &SECRET_KEY);
+    assert_eq!(image.len(), payload.len() + TRAILER_SIZE);
+    assert_eq!(verify(&image, &public_key(&SECRET_KEY)), Ok(payload.len()));
+    let (body, trailer) = split(&image).unwrap();
+    assert_eq!(body, &payload[..]);
+    assert_eq!(trailer.payload_size, payload.len() as u64);
+    // 空内核也可以签名
+    let image = signed_image(&[], &SECRET_KEY);
+    assert_eq!(verify(&image, &public_key(&SECRET_KEY)), Ok(0));
+}
+
+#[test]
+fn tampered_image_is_rejected() {
+    let image = signed_image(&sample_payload(), &SECRET_KEY);
+    let key = public_key(&SECRET_KEY);
+    // 改动内核
+    let mut bad = image.clone();
+    bad[100] ^= 1;
+    assert_eq!(verify(&bad, &key), Err(SignatureError::BadSignature));
+    // 改动签名
+    let mut bad = image.clone();
+    let at = image.len() - TRAILER_SIZE + 10;
+    bad[at] ^= 1;
+    assert_eq!(verify(&bad, &key), Err(SignatureError::BadSignature));
+}
+
+#[test]
+fn wrong_key_is_rejected() {
+    let image = signed_image(&sample_payload(), &SECRET_KEY);
+    let other = public_key(&[0x17; 32]);
+    assert_eq!(verify(&image, &other), Err(SignatureError::BadSignature));
+}
+
+#[test]
+fn missing_or_mismatched_trailer_is_rejected() {
+    let key = public_key(&SECRET_KEY);
+    let payload = sample_payload();
+    assert_eq!(verify(&payload, &key), Err(SignatureError::NoTrailer));
+    assert_eq!(verify(&[0; 10], &key), Err(SignatureError::NoTrailer));
+    // 尾部之前多了一个字节
+    let trailer = Trailer::sign(&payload, &SECRET_KEY).to_bytes();
+    let mut image = payload.clone();
+    image.push(0);
+    image.extend_from_slice(&trailer);
+    assert_eq!(
+        verify(&image, &key),
+        Err(SignatureError::BadLength {
+            expected: payload.len() as u64,
+            actual: payload.len() as u64 + 1,
+        })
+    );
+}
+
+#[test]
+fn parse_hex_key() {
+    let text = "000102030405060708090a0b0c0d0e0f101112131415161718191A1B1C1D1E1F\n";
+    let key = parse_key(text).unwrap();
+    assert_eq!(key[0], 0);
+    assert_eq!(key[26], 0x1a);
+    assert_eq!(key[31], 0x1f);
+    assert_eq!(parse_key("abcd"), None);
+    assert_eq!(parse_key(&"zz".repeat(32)), None);
+}
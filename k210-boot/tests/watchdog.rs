@@ -0,0 +1,41 @@
+use k210_boot::watchdog::{timeout_top, top_timeout_ms, ResetReason, MAX_TIMEOUT_MS, MAX_TOP};
+
+#[test]
+fn timeout_rounds_up_to_power_of_two() {
+    // 2^16个周期大约5毫秒
+    assert_eq!(top_timeout_ms(0), 5);
+    assert_eq!(timeout_top(1), Some(0));
+    assert_eq!(timeout_top(5), Some(0));
+    assert_eq!(timeout_top(6), Some(1));
+    // 10秒需要2^27个周期，实际约10.3秒
+    assert_eq!(timeout_top(10_000), Some(11));
+    assert_eq!(top_timeout_ms(11), 10_324);
+    assert_eq!(MAX_TIMEOUT_MS, 165_191);
+    assert_eq!(timeout_top(MAX_TIMEOUT_MS), Some(MAX_TOP));
+    for top in 0..=MAX_TOP {
+        assert_eq!(timeout_top(top_timeout_ms(top)), Some(top));
+    }
+}
+
+#[test]
+fn timeout_out_of_range() {
+    assert_eq!(timeout_top(0), None);
+    assert_eq!(timeout_top(MAX_TIMEOUT_MS + 1), None);
+    assert_eq!(timeout_top(usize::MAX), None);
+}
+
+#[test]
+fn reset_reason_from_status() {
+    assert_eq!(ResetReason::from_status(0), ResetReason::PowerOn);
+    assert_eq!(ResetReason::from_status(1 << 1), ResetReason::Pin);
+    assert_eq!(ResetReason::from_status(1 << 2), ResetReason::Watchdog(0));
+    assert_eq!(ResetReason::from_status(1 << 3), ResetReason::Watchdog(1));
+    assert_eq!(ResetReason::from_status(1 << 4), ResetReason::Software);
+    // 几个位同时置位时看门狗优先
+    assert_eq!(
+        ResetReason::from_status((1 << 1) | (1 << 2)),
+        ResetReason::Watchdog(0)
+    );
+    assert_eq!(ResetReason::Watchdog(1).as_str(), "watchdog1");
+    assert_eq!(ResetReason::PowerOn.as_str(), "power-on");
+}
@@ -0,0 +1,190 @@
+// 在伪终端的两端分别运行发送方和接收方，和通过USB串口传输时一样经过终端驱动
+#![cfg(unix)]
+
+use k210_boot::ymodem::{self, crc16, Port, YmodemError};
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::raw::{c_char, c_int, c_short, c_ulong, c_void};
+use std::os::unix::io::AsRawFd;
+use std::thread;
+
+extern "C" {
+    fn grantpt(fd: c_int) -> c_int;
+    fn unlockpt(fd: c_int) -> c_int;
+    fn ptsname(fd: c_int) -> *const c_char;
+    fn tcgetattr(fd: c_int, termios: *mut c_void) -> c_int;
+    fn tcsetattr(fd: c_int, action: c_int, termios: *const c_void) -> c_int;
+    fn cfmakeraw(termios: *mut c_void);
+    fn poll(fds: *mut PollFd, nfds: c_ulong, timeout: c_int) -> c_int;
+}
+
+#[repr(C)]
+struct PollFd {
+    fd: c_int,
+    events: c_short,
+    revents: c_short,
+}
+
+const POLLIN: c_short = 1;
+const TCSANOW: c_int = 0;
+
+// 返回伪终端的主设备和从设备，从设备设置为原始模式
+fn open_pty() -> (File, File) {
+    let master = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/ptmx")
+        .unwrap();
+    let fd = master.as_raw_fd();
+    let path = unsafe {
+        assert_eq!(grantpt(fd), 0);
+        assert_eq!(unlockpt(fd), 0);
+        CStr::from_ptr(ptsname(fd)).to_str().unwrap().to_owned()
+    };
+    let slave = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .unwrap();
+    // 只通过指针传递termios结构体，不需要知道它在各个系统上的布局
+    let mut termios = [0u64; 64];
+    unsafe {
+        let termios = termios.as_mut_ptr() as *mut c_void;
+        assert_eq!(tcgetattr(slave.as_raw_fd(), termios), 0);
+        cfmakeraw(termios);
+        assert_eq!(tcsetattr(slave.as_raw_fd(), TCSANOW, termios), 0);
+    }
+    (master, slave)
+}
+
+struct PtyPort(File);
+
+impl Port for PtyPort {
+    type Error = io::ErrorKind;
+
+    fn read_byte(&mut self, timeout_ms: u32) -> Result<Option<u8>, io::ErrorKind> {
+        let mut fd = PollFd {
+            fd: self.0.as_raw_fd(),
+            events: POLLIN,
+            revents: 0,
+        };
+        if unsafe { poll(&mut fd, 1, timeout_ms as c_int) } <= 0 {
+            return Ok(None);
+        }
+        let mut byte = [0];
+        self.0.read_exact(&mut byte).map_err(|e| e.kind())?;
+        Ok(Some(byte[0]))
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), io::ErrorKind> {
+        self.0.write_all(bytes).map_err(|e| e.kind())
+    }
+}
+
+// 把第n次写入的数据改掉一个字节，模拟线路上的干扰
+struct Noisy {
+    port: PtyPort,
+    writes: usize,
+    corrupt: usize,
+}
+
+impl Port for Noisy {
+    type Error = io::ErrorKind;
+
+    fn read_byte(&mut self, timeout_ms: u32) -> Result<Option<u8>, io::ErrorKind> {
+        self.port.read_byte(timeout_ms)
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), io::ErrorKind> {
+        self.writes += 1;
+        if self.writes == self.corrupt {
+            let mut bytes = bytes.to_vec();
+            let middle = bytes.len() / 2;
+            bytes[middle] ^= 0x55;
+            return self.port.write_all(&bytes);
+        }
+        self.port.write_all(bytes)
+    }
+}
+
+fn kernel(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 13 + i / 256) as u8).collect()
+}
+
+#[test]
+fn crc16_check_value() {
+    assert_eq!(crc16(b"123456789"), 0x31c3);
+}
+
+#[test]
+fn transfer_over_pty() {
+    for len in [0, 1, 1024, 5000] {
+        let (master, slave) = open_pty();
+        let data = kernel(len);
+        let sent = data.clone();
+        let sender = thread::spawn(move || {
+            let mut last = 0;
+            let result = ymodem::send(&mut PtyPort(master), "kernel.bin", &sent, 5, |done| {
+                assert!(done > last);
+                last = done;
+            });
+            (result, last)
+        });
+        let mut buf = vec![0xee; 8192];
+        let info = ymodem::receive(&mut PtyPort(slave), &mut buf, 5).unwrap();
+        let (result, last) = sender.join().unwrap();
+        assert_eq!(result, Ok(()));
+        assert_eq!(last, len);
+        assert_eq!(info.name(), "kernel.bin");
+        assert_eq!(info.size, len);
+        assert_eq!(&buf[..len], &data[..]);
+        // 最后一个数据块的填充不会写入缓冲区
+        assert_eq!(buf[len], 0xee);
+    }
+}
+
+#[test]
+fn corrupted_block_is_resent() {
+    let (master, slave) = open_pty();
+    let data = kernel(3000);
+    let sent = data.clone();
+    let sender = thread::spawn(move || {
+        // 第5次写入是1号数据块的内容
+        let mut port = Noisy {
+            port: PtyPort(master),
+            writes: 0,
+            corrupt: 5,
+        };
+        ymodem::send(&mut port, "kernel.bin", &sent, 5, |_| {})
+    });
+    let mut buf = vec![0; 4096];
+    let info = ymodem::receive(&mut PtyPort(slave), &mut buf, 5).unwrap();
+    assert_eq!(sender.join().unwrap(), Ok(()));
+    assert_eq!(info.size, data.len());
+    assert_eq!(&buf[..data.len()], &data[..]);
+}
+
+#[test]
+fn file_larger_than_buffer_is_cancelled() {
+    let (master, slave) = open_pty();
+    let sender = thread::spawn(move || {
+        ymodem::send(&mut PtyPort(master), "big.bin", &kernel(4096), 5, |_| {})
+    });
+    let mut buf = vec![0; 1024];
+    assert_eq!(
+        ymodem::receive(&mut PtyPort(slave), &mut buf, 5),
+        Err(YmodemError::TooLarge { size: 4096 })
+    );
+    assert_eq!(sender.join().unwrap(), Err(YmodemError::Cancelled));
+}
+
+#[test]
+fn receiver_gives_up_without_sender() {
+    let (_master, slave) = open_pty();
+    let mut buf = vec![0; 1024];
+    assert_eq!(
+        ymodem::receive(&mut PtyPort(slave), &mut buf, 2),
+        Err(YmodemError::NotStarted)
+    );
+}
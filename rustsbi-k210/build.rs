@@ -1,18 +1,56 @@
+use k210_boot::{config, dtc, signature};
 use std::env;
 use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 fn main() {
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
 
-    // Put the linker script somewhere the linker can find it
-    fs::File::create(out_dir.join("link-k210.ld"))
-        .unwrap()
-        .write_all(include_bytes!("link-k210.ld"))
-        .unwrap();
+    // Fill in the boot configuration, then put the linker script
+    // somewhere the linker can find it
+    let script = include_str!("link-k210.ld")
+        .replace("${SBI_START}", &format!("{:#x}", config::SBI_START))
+        .replace(
+            "${SBI_SIZE_LIMIT}",
+            &format!("{:#x}", config::SBI_SIZE_LIMIT),
+        );
+    fs::write(out_dir.join("link-k210.ld"), script).unwrap();
     println!("cargo:rustc-link-search={}", out_dir.display());
 
+    // Compile the device tree source, so the blob always matches it
+    let dtb = dtc::compile(include_str!("kendryte-k210.dts"))
+        .unwrap_or_else(|e| panic!("kendryte-k210.dts: {}", e));
+    fs::write(out_dir.join("kendryte-k210.dtb"), dtb).unwrap();
+
+    // Embed the public key that verified boot checks the kernel against
+    if env::var_os("CARGO_FEATURE_VERIFIED_BOOT").is_some() {
+        let key = verify_key();
+        fs::write(out_dir.join("verify-key.bin"), key).unwrap();
+    }
+
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=link-k210.ld");
+    println!("cargo:rerun-if-changed=kendryte-k210.dts");
+    for var in config::ENV_VARS {
+        println!("cargo:rerun-if-env-changed={}", var);
+    }
+}
+
+// Relative paths in K210_VERIFY_KEY start from the workspace root
+fn verify_key() -> [u8; signature::PUBLIC_KEY_SIZE] {
+    let Some(path) = config::VERIFY_KEY else {
+        panic!("the verified-boot feature needs K210_VERIFY_KEY, see `cargo xtask keygen`");
+    };
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let path = manifest_dir.parent().unwrap().join(Path::new(path));
+    println!("cargo:rerun-if-changed={}", path.display());
+    let text = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("cannot read {}: {}", path.display(), e));
+    signature::parse_key(&text).unwrap_or_else(|| {
+        panic!(
+            "{}: expected {} hex digits",
+            path.display(),
+            signature::PUBLIC_KEY_SIZE * 2
+        )
+    })
 }
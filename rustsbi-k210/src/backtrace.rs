@@ -0,0 +1,43 @@
+// 沿着帧指针打印机器态的返回地址链，打印的地址可以用addr2line等工具还原为源码位置。
+// 只在当前核的SBI栈范围内查找，帧指针损坏时不会访问到栈以外的内存
+use crate::stack::hart_stack_bounds;
+use core::arch::asm;
+use rustsbi::println;
+
+const MAX_DEPTH: usize = 32;
+
+pub fn print_backtrace(hart_id: usize, pc: usize, ra: usize, fp: usize) {
+    println!("[rustsbi] backtrace (hart {}):", hart_id);
+    println!("[rustsbi]   #0 {:#018x}", pc);
+    println!("[rustsbi]   #1 {:#018x}", ra);
+    walk_frames(hart_id, fp, 2);
+}
+
+// 从调用者开始打印返回地址链；需要用帧指针编译（见.cargo/config.toml）
+#[inline(never)]
+pub fn print_current_backtrace(hart_id: usize) {
+    let fp: usize;
+    unsafe { asm!("mv {}, s0", out(reg) fp) };
+    println!("[rustsbi] backtrace (hart {}):", hart_id);
+    walk_frames(hart_id, fp, 0);
+}
+
+fn walk_frames(hart_id: usize, fp: usize, first_depth: usize) {
+    let (bottom, top) = hart_stack_bounds(hart_id);
+    // 帧指针指向调用者的栈顶，fp-8处保存返回地址，fp-16处保存上一级帧指针
+    let mut fp = fp;
+    for depth in first_depth..MAX_DEPTH {
+        if fp % 8 != 0 || fp < bottom + 16 || fp > top {
+            break;
+        }
+        let (ra, prev_fp) = unsafe { (*((fp - 8) as *const usize), *((fp - 16) as *const usize)) };
+        if ra == 0 {
+            break;
+        }
+        println!("[rustsbi]   #{} {:#018x}", depth, ra);
+        if prev_fp <= fp {
+            break;
+        }
+        fp = prev_fp;
+    }
+}
@@ -0,0 +1,149 @@
+// 启动时把内嵌的设备树（或从SD卡读取的设备树）复制到内存中，按这个兼容层实际提供的功能修改后再交给内核。
+//
+// 原始设备树描述的是1.9.1版本的芯片：没有Sv39，没有timebase-frequency，也没有为固件保留内存。
+// 修改的内容包括：每个核的mmu-type、ISA字符串和状态（交接后停下来的核标记为disabled），/cpus的timebase-frequency，
+// 固件所在内存的/reserved-memory节点，启动配置中的bootargs，交接区中描述的初始内存盘，以及上次复位的原因。
+use crate::handoff;
+use crate::hart::NUM_HARTS;
+use crate::peripheral;
+use alloc::format;
+use k210_boot::config;
+use k210_boot::fdt::{self, Fdt};
+use rustsbi::println;
+
+// 由build.rs从kendryte-k210.dts编译得到
+static DEVICE_TREE_BINARY: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/kendryte-k210.dtb"));
+const _: () = assert!(
+    include_bytes!(concat!(env!("OUT_DIR"), "/kendryte-k210.dtb")).len() <= config::DTB_SIZE_LIMIT
+);
+
+// 模拟rdtime和sfence.vma以后，内核看到的是带Sv39的1.12版本特权级架构
+const MMU_TYPE: &str = "riscv,sv39";
+const ISA: &str = "rv64imafdc_zicntr_zicsr_zifencei";
+
+#[repr(C, align(8))]
+struct DeviceTreeBuffer([u8; config::DTB_SIZE_LIMIT]);
+
+// 没有配置设备树地址时，修改后的设备树放在固件的.bss段中，位于保留内存里
+static mut DEVICE_TREE_BUFFER: DeviceTreeBuffer = DeviceTreeBuffer([0; config::DTB_SIZE_LIMIT]);
+
+// 传给内核的设备树地址，由启动核在进入特权级之前写入
+static mut DEVICE_TREE_ADDRESS: usize = 0;
+
+pub fn init() {
+    let buf = match config::DTB_ADDRESS {
+        Some(address) => unsafe {
+            core::slice::from_raw_parts_mut(address as *mut u8, config::DTB_SIZE_LIMIT)
+        },
+        None => unsafe { &mut (*core::ptr::addr_of_mut!(DEVICE_TREE_BUFFER)).0 },
+    };
+    let address = buf.as_ptr() as usize;
+    #[cfg(feature = "sd-boot")]
+    let source = crate::sdcard::device_tree().unwrap_or(DEVICE_TREE_BINARY);
+    #[cfg(not(feature = "sd-boot"))]
+    let source = DEVICE_TREE_BINARY;
+    let mut fdt = match Fdt::open_into(source, buf) {
+        Ok(fdt) => fdt,
+        Err(e) => {
+            println!(
+                "[rustsbi] cannot open device tree: {:?}, passing it unmodified",
+                e
+            );
+            unsafe { DEVICE_TREE_ADDRESS = source.as_ptr() as usize };
+            return;
+        }
+    };
+    if let Err(e) = patch(&mut fdt) {
+        // 每一步修改完成后设备树都是完整的，出错时仍然使用已经修改的部分
+        println!("[rustsbi] failed to patch device tree: {:?}", e);
+    }
+    unsafe { DEVICE_TREE_ADDRESS = address };
+}
+
+pub fn address() -> usize {
+    unsafe { DEVICE_TREE_ADDRESS }
+}
+
+fn patch(fdt: &mut Fdt) -> fdt::Result<()> {
+    patch_cpus(fdt)?;
+    patch_reserved_memory(fdt)?;
+    let chosen = fdt.add_subnode(0, "chosen")?;
+    // 监视器设置的bootargs优先，其次是环境变量中的
+    #[cfg(feature = "env")]
+    let mut buf = [0; 1024];
+    #[cfg(feature = "env")]
+    let bootargs = crate::env::get_str(k210_boot::env::ENV_BOOTARGS, &mut buf).or(config::BOOTARGS);
+    #[cfg(not(feature = "env"))]
+    let bootargs = config::BOOTARGS;
+    #[cfg(feature = "monitor")]
+    let bootargs = crate::monitor::bootargs().or(bootargs);
+    if let Some(bootargs) = bootargs {
+        fdt.set_property_str(chosen, "bootargs", bootargs)?;
+    }
+    #[cfg(feature = "watchdog")]
+    fdt.set_property_str(
+        chosen,
+        "rustsbi,reset-reason",
+        crate::watchdog::reset_reason().as_str(),
+    )?;
+    if let Some((start, end)) = handoff::initrd() {
+        println!("[rustsbi] initrd at {:#x}..{:#x}", start, end);
+        fdt.set_property_cells(chosen, "linux,initrd-start", &u64_cells(start as u64))?;
+        fdt.set_property_cells(chosen, "linux,initrd-end", &u64_cells(end as u64))?;
+    }
+    Ok(())
+}
+
+fn patch_cpus(fdt: &mut Fdt) -> fdt::Result<()> {
+    let cpus = fdt.find_node("/cpus").ok_or(fdt::FdtError::NotFound)?;
+    fdt.set_property_u32(cpus, "timebase-frequency", peripheral::timebase_frequency())?;
+    // 修改会让后面节点的偏移量失效，每个核都重新查找
+    let mut index = 0;
+    while let Some(cpu) = nth_cpu(fdt, index) {
+        let hart_id = fdt
+            .property(cpu, "reg")
+            .and_then(|reg| reg.try_into().ok())
+            .map(|reg| u32::from_be_bytes(reg) as usize);
+        let status = if hart_id.is_some_and(hart_enters_kernel) {
+            "okay"
+        } else {
+            "disabled"
+        };
+        fdt.set_property_str(cpu, "mmu-type", MMU_TYPE)?;
+        fdt.set_property_str(cpu, "riscv,isa", ISA)?;
+        fdt.set_property_str(cpu, "status", status)?;
+        index += 1;
+    }
+    Ok(())
+}
+
+// 交接信息指定其它核启动时，0号核一直停在pause_hart中，内核不能把它当作可用的核
+fn hart_enters_kernel(hart_id: usize) -> bool {
+    hart_id < NUM_HARTS && !(hart_id == 0 && handoff::next_stage().boot_hart != 0)
+}
+
+fn nth_cpu(fdt: &Fdt, index: usize) -> Option<usize> {
+    let cpus = fdt.find_node("/cpus")?;
+    fdt.children(cpus)
+        .filter(|&node| fdt.node_name(node).starts_with("cpu@"))
+        .nth(index)
+}
+
+// 固件和交接区一直保留到下一阶段程序的入口
+fn patch_reserved_memory(fdt: &mut Fdt) -> fdt::Result<()> {
+    let size = (config::PAYLOAD_ADDRESS - config::SBI_START) as u64;
+    let start = config::SBI_START as u64;
+    let node = fdt.add_subnode(0, "reserved-memory")?;
+    fdt.set_property_u32(node, "#address-cells", 2)?;
+    fdt.set_property_u32(node, "#size-cells", 2)?;
+    fdt.set_property(node, "ranges", &[])?;
+    let resv = fdt.add_subnode(node, &format!("mmode_resv0@{:x}", start))?;
+    let [start_hi, start_lo] = u64_cells(start);
+    let [size_hi, size_lo] = u64_cells(size);
+    fdt.set_property_cells(resv, "reg", &[start_hi, start_lo, size_hi, size_lo])?;
+    fdt.set_property(resv, "no-map", &[])
+}
+
+fn u64_cells(value: u64) -> [u32; 2] {
+    [(value >> 32) as u32, value as u32]
+}
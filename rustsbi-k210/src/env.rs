@@ -0,0 +1,160 @@
+// 保存在SPI闪存中的启动环境变量，用`env`特性编译时才会包含。
+//
+// 0号核启动时读取两份副本中较新的一份（格式见k210_boot::env），按其中的变量修改控制台波特率、
+// 下一阶段程序的入口地址、内核命令行、跟踪掩码和启动方式。内核可以通过厂商SBI调用读取和修改变量，
+// 修改后立即写回闪存。
+use crate::{handoff, spi_flash};
+use core::sync::atomic::{AtomicBool, Ordering};
+use k210_boot::config;
+use k210_boot::env::{self, BootMode, Env, EnvError, ENV_SIZE};
+use rustsbi::println;
+
+static mut ENV: Env = Env::new();
+// 当前副本的序号，闪存中没有有效的副本时为None
+static mut CURRENT: Option<usize> = None;
+static mut BOOT_MODE: BootMode = BootMode::Auto;
+// 多个核可能同时通过SBI调用修改变量
+static ENV_LOCK: AtomicBool = AtomicBool::new(false);
+
+// 0号核在初始化外设之前调用，这时还不能打印
+pub fn init() {
+    with_env(|env, current| {
+        let mut generations = [None; 2];
+        for (index, generation) in generations.iter_mut().enumerate() {
+            spi_flash::read(copy_offset(index), env.raw_mut());
+            *generation = Env::check(env.as_bytes()).ok();
+        }
+        *current = env::current(generations);
+        match *current {
+            // 缓冲区中已经是第二份副本
+            Some(1) => {}
+            Some(index) => spi_flash::read(copy_offset(index), env.raw_mut()),
+            None => *env = Env::new(),
+        }
+    })
+}
+
+// 0号核读取交接区之后调用，打印读取的结果并应用启动时使用的变量；波特率在初始化外设时已经使用
+pub fn apply() {
+    with_env(|env, current| match *current {
+        Some(index) => println!(
+            "[rustsbi] environment copy {} of generation {}, {} variables",
+            index,
+            env.generation(),
+            env.iter().count()
+        ),
+        None => println!(
+            "[rustsbi] no valid environment at flash {:#x}",
+            config::FLASH_ENV_OFFSET
+        ),
+    });
+    match number(env::ENV_PAYLOAD_ADDRESS) {
+        Some(address) if handoff::load_range_valid(address, 0) => {
+            println!("[rustsbi] payload address {:#x} from environment", address);
+            handoff::set_next_address(address);
+        }
+        Some(address) => println!("[rustsbi] ignored payload address {:#x}", address),
+        None => {}
+    }
+    #[cfg(feature = "trace")]
+    if let Some(mask) = number(env::ENV_TRACE_MASK) {
+        if crate::trace::set_mask(mask, crate::trace::OUTPUT_CONSOLE).is_none() {
+            println!("[rustsbi] ignored invalid trace mask {:#x}", mask);
+        }
+    }
+    let mode = with_env(|env, _| match env.get(env::ENV_BOOT_MODE) {
+        None => BootMode::Auto,
+        Some(value) => BootMode::parse(value).unwrap_or_else(|| {
+            println!("[rustsbi] ignored unknown boot mode {}", value);
+            BootMode::Auto
+        }),
+    });
+    if mode != BootMode::Auto {
+        println!("[rustsbi] boot mode {:?} from environment", mode);
+    }
+    unsafe { BOOT_MODE = mode };
+}
+
+// 控制台的波特率，没有设置时为None
+pub fn baudrate() -> Option<u32> {
+    number(env::ENV_BAUDRATE).map(|baudrate| baudrate as u32)
+}
+
+pub fn boot_mode() -> BootMode {
+    unsafe { BOOT_MODE }
+}
+
+// 把变量的值复制到buf中，返回值的长度；变量不存在时返回None
+pub fn get(name: &str, buf: &mut [u8]) -> Option<usize> {
+    with_env(|env, _| {
+        let value = env.get(name)?.as_bytes();
+        let len = value.len().min(buf.len());
+        buf[..len].copy_from_slice(&value[..len]);
+        Some(value.len())
+    })
+}
+
+// 写入设备树时使用；buf放不下时忽略这个变量
+pub fn get_str<'a>(name: &str, buf: &'a mut [u8]) -> Option<&'a str> {
+    let len = get(name, buf)?;
+    if len > buf.len() {
+        println!("[rustsbi] ignored {} longer than {} bytes", name, buf.len());
+        return None;
+    }
+    core::str::from_utf8(&buf[..len]).ok()
+}
+
+// 设置变量，值为None时删除；内容改变时写回闪存
+pub fn set(name: &str, value: Option<&str>) -> Result<(), EnvError> {
+    with_env(|env, current| {
+        let changed = match value {
+            Some(value) => env.set(name, value)?,
+            None => env.remove(name),
+        };
+        if changed {
+            // 写到另一份副本上，写入时掉电不会破坏当前的副本
+            let next = current.map_or(0, |index| 1 - index);
+            let generation = match *current {
+                Some(_) => env.generation().wrapping_add(1),
+                None => 0,
+            };
+            env.seal(generation);
+            spi_flash::erase_sector(copy_offset(next));
+            spi_flash::program(copy_offset(next), env.as_bytes());
+            *current = Some(next);
+        }
+        Ok(())
+    })
+}
+
+fn number(name: &str) -> Option<usize> {
+    with_env(|env, _| {
+        let value = env.get(name)?;
+        let number = env::parse_number(value);
+        if number.is_none() {
+            println!("[rustsbi] ignored invalid {}={}", name, value);
+        }
+        number
+    })
+}
+
+fn copy_offset(index: usize) -> usize {
+    config::FLASH_ENV_OFFSET + index * ENV_SIZE
+}
+
+fn with_env<T>(f: impl FnOnce(&mut Env, &mut Option<usize>) -> T) -> T {
+    while ENV_LOCK
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        core::hint::spin_loop();
+    }
+    let result = unsafe {
+        f(
+            &mut *core::ptr::addr_of_mut!(ENV),
+            &mut *core::ptr::addr_of_mut!(CURRENT),
+        )
+    };
+    ENV_LOCK.store(false, Ordering::Release);
+    result
+}
@@ -1,84 +1,139 @@
-use core::arch::asm;
-use riscv::register::scause::{Exception, Trap};
+use riscv::register::{
+    mstatus::MPP,
+    scause::{self, Exception, Trap},
+    sepc, stval,
+};
 
 use crate::feature;
+#[cfg(feature = "gdb-stub")]
+use crate::gdb_stub;
+use crate::hart;
+use crate::machine_trap;
 use crate::runtime::{MachineTrap, Runtime, SupervisorContext};
+use crate::stats::{self, Counter};
+#[cfg(feature = "trace")]
+use crate::trace;
+use crate::trap_history::{TrapOutcome, TrapRecord};
+use crate::vendor;
 
-pub fn execute_supervisor(supervisor_mepc: usize, a0: usize, a1: usize) -> ! {
-    let mut rt = Runtime::new_sbi_supervisor(supervisor_mepc, a0, a1);
+pub fn execute_supervisor(supervisor_mepc: usize, mode: MPP, args: [usize; 3]) -> ! {
+    let mut rt = Runtime::new_sbi_supervisor(supervisor_mepc, mode, args);
+    #[cfg(feature = "gdb-stub")]
+    if riscv::register::mhartid::read() == 0 {
+        gdb_stub::attach(rt.context_mut());
+    }
     loop {
         match rt.next() {
             Some(MachineTrap::SbiCall()) => {
                 let ctx = rt.context_mut();
-                if emulate_sbi_call(ctx) {
-                    continue;
-                }
-                feature::preprocess_supervisor_external(ctx); // specific for 1.9.1; see document for details
+                let (extension, function) = (ctx.a7, ctx.a6);
                 let param = [ctx.a0, ctx.a1, ctx.a2, ctx.a3, ctx.a4, ctx.a5];
-                let ans = rustsbi::ecall(ctx.a7, ctx.a6, param);
-                ctx.a0 = ans.error;
-                ctx.a1 = ans.value;
-                ctx.mepc = ctx.mepc.wrapping_add(4);
+                stats::count(Counter::sbi_extension(extension));
+                with_trap_record(|record| record.sbi_call = Some((extension, function)));
+                if !emulate_sbi_call(ctx) {
+                    feature::preprocess_supervisor_external(ctx); // specific for 1.9.1; see document for details
+                    let ans = rustsbi::ecall(extension, function, param);
+                    ctx.a0 = ans.error;
+                    ctx.a1 = ans.value;
+                    ctx.mepc = ctx.mepc.wrapping_add(4);
+                }
+                record_sbi_ret(ctx);
+                trace!(
+                    trace::TRACE_SBI_CALL,
+                    "[trace] sbi eid {:#x} fid {:#x} args {:x?} -> error {} value {:#x}",
+                    extension,
+                    function,
+                    param,
+                    ctx.a0 as isize,
+                    ctx.a1
+                );
             }
             Some(MachineTrap::IllegalInstruction()) => {
                 let ctx = rt.context_mut();
-                // FIXME: get_vaddr_u32这个过程可能出错。
-                let ins = unsafe { get_vaddr_u32(ctx.mepc) } as usize;
-                if !emulate_illegal_instruction(ctx, ins) {
+                // 读取指令失败时按未知指令处理，转交给特权级
+                let ins = unsafe { get_vaddr_u32(ctx.mepc) }.unwrap_or(0) as usize;
+                with_trap_record(|record| record.instruction = Some(ins as u32));
+                let mepc = ctx.mepc;
+                if emulate_illegal_instruction(ctx, ins) {
+                    with_trap_record(|record| record.outcome = TrapOutcome::Emulated);
+                    trace!(
+                        trace::TRACE_EMULATE,
+                        "[trace] emulate {:#010x} at {:#x}",
+                        ins,
+                        mepc
+                    );
+                } else {
+                    stats::count(Counter::IllegalInstruction);
                     unsafe {
                         if feature::should_transfer_trap(ctx) {
-                            feature::do_transfer_trap(
-                                ctx,
-                                Trap::Exception(Exception::IllegalInstruction),
-                            )
+                            #[cfg(feature = "gdb-stub")]
+                            gdb_stub::catch(ctx, 2, gdb_stub::SIGILL);
+                            transfer_trap(ctx, Exception::IllegalInstruction)
                         } else {
                             fail_illegal_instruction(ctx, ins)
                         }
                     }
                 }
             }
-            Some(MachineTrap::ExternalInterrupt()) => unsafe {
+            Some(MachineTrap::Breakpoint()) => {
+                let ctx = rt.context_mut();
+                #[cfg(feature = "gdb-stub")]
+                if gdb_stub::breakpoint(ctx) {
+                    continue;
+                }
+                unsafe { transfer_trap(ctx, Exception::Breakpoint) }
+            }
+            Some(MachineTrap::ExternalInterrupt()) => {
                 let ctx = rt.context_mut();
-                feature::call_supervisor_interrupt(ctx)
-            },
-            Some(MachineTrap::MachineTimer()) => feature::forward_supervisor_timer(),
-            Some(MachineTrap::MachineSoft()) => feature::forward_supervisor_soft(),
+                stats::count(Counter::ExternalInterrupt);
+                unsafe { feature::call_supervisor_interrupt(ctx) };
+                with_trap_record(|record| record.outcome = TrapOutcome::Forwarded);
+            }
+            Some(MachineTrap::MachineTimer()) => {
+                stats::count(Counter::TimerInterrupt);
+                feature::forward_supervisor_timer();
+                with_trap_record(|record| record.outcome = TrapOutcome::Forwarded);
+            }
+            Some(MachineTrap::MachineSoft()) => {
+                stats::count(Counter::SoftInterrupt);
+                feature::forward_supervisor_soft();
+                with_trap_record(|record| record.outcome = TrapOutcome::Forwarded);
+            }
             // todo：编写样例，验证store page fault和instruction page fault
             Some(MachineTrap::InstructionFault(addr)) => {
                 let ctx = rt.context_mut();
                 if feature::is_page_fault(addr) {
-                    unsafe {
-                        feature::do_transfer_trap(
-                            ctx,
-                            Trap::Exception(Exception::InstructionPageFault),
-                        )
-                    }
+                    stats::count(Counter::InstructionPageFault);
+                    unsafe { transfer_trap(ctx, Exception::InstructionPageFault) }
                 } else {
-                    unsafe {
-                        feature::do_transfer_trap(ctx, Trap::Exception(Exception::InstructionFault))
-                    }
+                    stats::count(Counter::InstructionAccessFault);
+                    #[cfg(feature = "gdb-stub")]
+                    gdb_stub::catch(ctx, 1, gdb_stub::SIGSEGV);
+                    unsafe { transfer_trap(ctx, Exception::InstructionFault) }
                 }
             }
             Some(MachineTrap::LoadFault(addr)) => {
                 let ctx = rt.context_mut();
                 if feature::is_page_fault(addr) {
-                    unsafe {
-                        feature::do_transfer_trap(ctx, Trap::Exception(Exception::LoadPageFault))
-                    }
+                    stats::count(Counter::LoadPageFault);
+                    unsafe { transfer_trap(ctx, Exception::LoadPageFault) }
                 } else {
-                    unsafe { feature::do_transfer_trap(ctx, Trap::Exception(Exception::LoadFault)) }
+                    stats::count(Counter::LoadAccessFault);
+                    #[cfg(feature = "gdb-stub")]
+                    gdb_stub::catch(ctx, 5, gdb_stub::SIGSEGV);
+                    unsafe { transfer_trap(ctx, Exception::LoadFault) }
                 }
             }
             Some(MachineTrap::StoreFault(addr)) => {
                 let ctx = rt.context_mut();
                 if feature::is_page_fault(addr) {
-                    unsafe {
-                        feature::do_transfer_trap(ctx, Trap::Exception(Exception::StorePageFault))
-                    }
+                    stats::count(Counter::StorePageFault);
+                    unsafe { transfer_trap(ctx, Exception::StorePageFault) }
                 } else {
-                    unsafe {
-                        feature::do_transfer_trap(ctx, Trap::Exception(Exception::StoreFault))
-                    }
+                    stats::count(Counter::StoreAccessFault);
+                    #[cfg(feature = "gdb-stub")]
+                    gdb_stub::catch(ctx, 7, gdb_stub::SIGSEGV);
+                    unsafe { transfer_trap(ctx, Exception::StoreFault) }
                 }
             }
             None => unreachable!(),
@@ -86,35 +141,68 @@ pub fn execute_supervisor(supervisor_mepc: usize, a0: usize, a1: usize) -> ! {
     }
 }
 
+unsafe fn transfer_trap(ctx: &mut SupervisorContext, exception: Exception) {
+    feature::do_transfer_trap(ctx, Trap::Exception(exception));
+    let scause = scause::read().bits();
+    with_trap_record(|record| record.outcome = TrapOutcome::Transferred(scause));
+    trace!(
+        trace::TRACE_TRANSFER,
+        "[trace] transfer scause {:#x} sepc {:#x} stval {:#x} -> stvec {:#x}",
+        scause,
+        sepc::read(),
+        stval::read(),
+        ctx.mepc
+    );
+}
+
+// 修改当前核最近一条陷入记录，也就是正在处理的陷入
 #[inline]
-unsafe fn get_vaddr_u32(vaddr: usize) -> u32 {
-    get_vaddr_u16(vaddr) as u32 | ((get_vaddr_u16(vaddr.wrapping_add(2)) as u32) << 16)
+fn with_trap_record(f: impl FnOnce(&mut TrapRecord)) {
+    if let Some(record) = hart::this_hart().history.last_mut() {
+        f(record)
+    }
 }
 
 #[inline]
-unsafe fn get_vaddr_u16(vaddr: usize) -> u16 {
-    let mut ans: u16;
-    asm!("
-        li      {2}, (1 << 17)
-        csrrs   {2}, mstatus, {2}
-        lhu     {0}, 0({1})
-        csrw    mstatus, {2}
-    ", out(reg) ans, in(reg) vaddr, out(reg) _);
-    ans
+fn record_sbi_ret(ctx: &SupervisorContext) {
+    let (error, value) = (ctx.a0, ctx.a1);
+    with_trap_record(|record| record.outcome = TrapOutcome::SbiRet { error, value });
+}
+
+#[inline]
+unsafe fn get_vaddr_u32(vaddr: usize) -> Option<u32> {
+    let low = get_vaddr_u16(vaddr)?;
+    let high = get_vaddr_u16(vaddr.wrapping_add(2))?;
+    Some(low as u32 | ((high as u32) << 16))
+}
+
+#[inline]
+unsafe fn get_vaddr_u16(vaddr: usize) -> Option<u16> {
+    let ans = machine_trap::load_supervisor_u16(vaddr);
+    if ans == machine_trap::ACCESS_FAULT {
+        None
+    } else {
+        Some(ans as u16)
+    }
 }
 
 fn emulate_sbi_call(ctx: &mut SupervisorContext) -> bool {
     if feature::emulate_sbi_rustsbi_k210_sext(ctx) {
         return true;
     }
+    if vendor::emulate_sbi_rustsbi_k210_vendor(ctx) {
+        return true;
+    }
     false
 }
 
 fn emulate_illegal_instruction(ctx: &mut SupervisorContext, ins: usize) -> bool {
     if feature::emulate_rdtime(ctx, ins) {
+        stats::count(Counter::EmulateRdtime);
         return true;
     }
     if feature::emulate_sfence_vma(ctx, ins) {
+        stats::count(Counter::EmulateSfenceVma);
         return true;
     }
     false
@@ -1,9 +1,9 @@
+use crate::hart::{self, PENDING_FENCE_I, PENDING_SFENCE_VMA, PENDING_SUPERVISOR_SOFT};
 use crate::runtime::SupervisorContext;
 use core::arch::asm;
+use k210_hal::clint::msip;
 use riscv::register::{mie, mip, mstatus};
 
-static mut DEVINTRENTRY: usize = 0;
-
 pub unsafe fn call_supervisor_interrupt(ctx: &mut SupervisorContext) {
     let mut mstatus: usize;
     asm!("csrr {}, mstatus", out(reg) mstatus);
@@ -33,9 +33,7 @@ pub unsafe fn call_supervisor_interrupt(ctx: &mut SupervisorContext) {
 #[inline]
 pub fn emulate_sbi_rustsbi_k210_sext(ctx: &mut SupervisorContext) -> bool {
     if ctx.a7 == 0x0A000004 && ctx.a6 == 0x210 {
-        unsafe {
-            DEVINTRENTRY = ctx.a0;
-        }
+        hart::this_hart().sext_entry = ctx.a0;
         // enable mext
         unsafe {
             mie::set_mext();
@@ -55,7 +53,7 @@ fn devintr() {
     unsafe {
         // call devintr defined in application
         // we have to ask compiler save ra explicitly
-        asm!("jalr 0({})", in(reg) DEVINTRENTRY, lateout("ra") _);
+        asm!("jalr 0({})", in(reg) hart::this_hart().sext_entry, lateout("ra") _);
     }
 }
 
@@ -70,7 +68,7 @@ pub fn preprocess_supervisor_external(ctx: &mut SupervisorContext) {
         unsafe {
             let mtip = mip::read().mtimer();
             if mtip {
-                if DEVINTRENTRY != 0 {
+                if hart::this_hart().sext_entry != 0 {
                     mie::set_mext();
                 }
             }
@@ -87,10 +85,20 @@ pub fn forward_supervisor_timer() {
     }
 }
 
+// M-level software interrupts carry requests from other harts (see peripheral.rs);
+// IPIs are forwarded to S-level, fences are performed here on behalf of the sender.
 pub fn forward_supervisor_soft() {
-    // Forward to S-level software interrupt
-    unsafe {
-        mip::set_ssoft(); // set S-soft interrupt flag
-        mie::clear_msoft(); // mask M-soft interrupt
+    let hart = hart::this_hart();
+    msip::clear_ipi(hart.hart_id);
+    let pending = hart.take_pending();
+    if pending & PENDING_FENCE_I != 0 {
+        unsafe { asm!("fence.i") };
+    }
+    if pending & PENDING_SFENCE_VMA != 0 {
+        unsafe { asm!(".word 0x10400073") }; // sfence.vm x0
+    }
+    if pending & PENDING_SUPERVISOR_SOFT != 0 {
+        // Forward to S-level software interrupt
+        unsafe { mip::set_ssoft() }; // set S-soft interrupt flag
     }
 }
@@ -0,0 +1,192 @@
+// 从SPI闪存加载下一阶段程序，用`flash-boot`特性编译时才会包含。
+//
+// 在启动配置的闪存偏移量处查找程序镜像（格式见k210_boot::payload）。没有镜像时照常启动内存中的程序；
+// 有镜像时把程序复制到描述头指定的加载地址，检查通过后从入口地址启动，检查失败时报告错误并停机。
+// 描述头带有`FLAG_LZ4`标志时程序是压缩过的，解压后再启动。
+//
+// 闪存中有启动控制记录时按A/B启动（见k210_boot::bootctl）：由较新的一份副本决定启动哪个槽位，
+// 并把消耗的尝试次数写到另一份副本上。正在尝试的槽位没有可用的镜像时立即回退到另一个槽位。
+use crate::spi_flash::{erase_sector, program, read};
+use crate::{handoff, peripheral, unpack};
+use k210_boot::bootctl::{self, BootControl, BootControlError, Decision, Slot, BOOT_CONTROL_SIZE};
+use k210_boot::payload::{HeaderError, PayloadHeader, FLAG_LZ4, PAYLOAD_HEADER_SIZE};
+use rustsbi::println;
+
+// 本次启动使用的启动控制记录，由0号核写入；没有记录时为None
+static mut BOOT_CONTROL: Option<BootControl> = None;
+// 这个记录来自哪一份副本，下次写到另一份上
+static mut BOOT_CONTROL_COPY: usize = 0;
+
+// 在0号核进入下一阶段程序之前调用，返回闪存中程序的入口地址
+pub fn load_payload() -> Option<usize> {
+    let Some(mut record) = read_boot_control() else {
+        return load_or_fail(Slot::A);
+    };
+    let decision = record.select();
+    match decision {
+        Decision::Confirmed(_) => {}
+        Decision::Trial { slot, tries_left } => println!(
+            "[rustsbi] trying flash slot {:?}, {} tries left",
+            slot, tries_left
+        ),
+        Decision::Fallback { from, to } => println!(
+            "[rustsbi] flash slot {:?} did not boot successfully, falling back to slot {:?}",
+            from, to
+        ),
+    }
+    let slot = decision.slot();
+    let entry = match (load_slot(slot), decision) {
+        (Ok(Some(entry)), _) => Some(entry),
+        // 正在尝试的槽位不能启动，直接回退，不必等到尝试次数用完
+        (result, Decision::Trial { .. }) => {
+            if result.is_ok() {
+                println!("[rustsbi] no payload in flash slot {:?}", slot);
+            }
+            record.fall_back();
+            println!("[rustsbi] falling back to flash slot {:?}", record.active);
+            write_boot_control(&mut record);
+            unsafe { BOOT_CONTROL = Some(record) };
+            return load_or_fail(record.active);
+        }
+        (Ok(None), _) => None,
+        (Err(()), _) => fail(slot),
+    };
+    if !matches!(decision, Decision::Confirmed(_)) {
+        write_boot_control(&mut record);
+    }
+    unsafe { BOOT_CONTROL = Some(record) };
+    entry
+}
+
+// 内核通过厂商SBI调用确认启动成功，返回当前槽位；没有启动控制记录时返回None
+pub fn mark_boot_successful() -> Option<Slot> {
+    let mut record = unsafe { BOOT_CONTROL }?;
+    if record.mark_successful() {
+        write_boot_control(&mut record);
+        unsafe { BOOT_CONTROL = Some(record) };
+    }
+    Some(record.active)
+}
+
+fn read_boot_control() -> Option<BootControl> {
+    let mut copies = [None; 2];
+    for (index, copy) in copies.iter_mut().enumerate() {
+        let offset = bootctl::copy_offset(index);
+        let mut bytes = [0u8; BOOT_CONTROL_SIZE];
+        read(offset, &mut bytes);
+        *copy = match BootControl::parse(&bytes) {
+            Ok(record) => Some(record),
+            Err(BootControlError::BadMagic) => None,
+            Err(e) => {
+                println!(
+                    "[rustsbi] ignored boot control record at flash {:#x}: {:?}",
+                    offset, e
+                );
+                None
+            }
+        };
+    }
+    let index = bootctl::current(copies)?;
+    unsafe { BOOT_CONTROL_COPY = index };
+    copies[index]
+}
+
+// 写到另一份副本上，写入时掉电不会破坏当前的副本
+fn write_boot_control(record: &mut BootControl) {
+    let next = 1 - unsafe { BOOT_CONTROL_COPY };
+    record.generation = record.generation.wrapping_add(1);
+    let offset = bootctl::copy_offset(next);
+    erase_sector(offset);
+    program(offset, &record.to_bytes());
+    unsafe { BOOT_CONTROL_COPY = next };
+}
+
+fn load_or_fail(slot: Slot) -> Option<usize> {
+    load_slot(slot).unwrap_or_else(|()| fail(slot))
+}
+
+// 槽位中没有镜像时返回Ok(None)；出错时打印原因，这时内存中的程序可能已经被覆盖
+fn load_slot(slot: Slot) -> Result<Option<usize>, ()> {
+    let offset = slot.flash_offset();
+    let mut bytes = [0u8; PAYLOAD_HEADER_SIZE];
+    read(offset, &mut bytes);
+    let header = match PayloadHeader::parse(&bytes) {
+        Ok(header) => header,
+        Err(HeaderError::BadMagic) => return Ok(None),
+        Err(e) => {
+            println!(
+                "[rustsbi] bad payload header at flash {:#x}: {:?}",
+                offset, e
+            );
+            return Err(());
+        }
+    };
+    let (start, size) = (header.load_address as usize, header.size as usize);
+    // 压缩的程序先读到暂存区，检查通过后再解压到加载地址
+    let (buffer, plan) = if header.flags & FLAG_LZ4 != 0 {
+        let mut frame = [0u8; unpack::FRAME_HEADER_SIZE];
+        let frame = &mut frame[..size.min(unpack::FRAME_HEADER_SIZE)];
+        read(offset + PAYLOAD_HEADER_SIZE, frame);
+        let Some(plan) = unpack::plan(start, frame, size) else {
+            return Err(());
+        };
+        if let Err(e) = header.check_entry(plan.size as u64) {
+            println!(
+                "[rustsbi] bad payload header at flash {:#x}: {:?}",
+                offset, e
+            );
+            return Err(());
+        }
+        (plan.staging, Some(plan))
+    } else {
+        if !handoff::load_range_valid(start, size) {
+            println!(
+                "[rustsbi] payload {:#x}..{:#x} overlaps the firmware or lies outside of SRAM",
+                start,
+                start.wrapping_add(size)
+            );
+            return Err(());
+        }
+        (start, None)
+    };
+    println!(
+        "[rustsbi] loading {:#x} bytes from flash {:#x} to {:#x}",
+        size, offset, buffer
+    );
+    let payload = unsafe { core::slice::from_raw_parts_mut(buffer as *mut u8, size) };
+    read(offset + PAYLOAD_HEADER_SIZE, payload);
+    if let Err(e) = header.verify(payload) {
+        println!("[rustsbi] payload verification failed: {:?}", e);
+        return Err(());
+    }
+    let loaded = match plan {
+        Some(plan) => {
+            if let Err(e) = unpack::unpack(start, plan, size) {
+                println!("[rustsbi] cannot unpack the payload: {:?}", e);
+                return Err(());
+            }
+            plan.size
+        }
+        None => {
+            unsafe { core::arch::asm!("fence.i") };
+            size
+        }
+    };
+    if plan.is_some() {
+        println!("[rustsbi] unpacked {:#x} bytes to {:#x}", loaded, start);
+    }
+    #[cfg(feature = "verified-boot")]
+    handoff::set_signed_image(start, loaded);
+    Ok(Some(header.entry as usize))
+}
+
+fn fail(slot: Slot) -> ! {
+    println!("[rustsbi] cannot boot flash slot {:?}", slot);
+    println!("[rustsbi] system shutdown scheduled due to flash boot failure");
+    use rustsbi::Reset;
+    peripheral::Reset.system_reset(
+        rustsbi::reset::RESET_TYPE_SHUTDOWN,
+        rustsbi::reset::RESET_REASON_SYSTEM_FAILURE,
+    );
+    loop {}
+}
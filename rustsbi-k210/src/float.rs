@@ -0,0 +1,109 @@
+// 特权级浮点寄存器的延迟保存，用`float-context`特性编译时才会包含。
+//
+// 机器态平时不使用浮点寄存器。需要读写它们的指令模拟器通过HartState::float_context或
+// float_context_mut访问，第一次访问时才把寄存器保存到当前核的状态块中；返回特权级之前恢复，
+// 修改过时把mstatus.FS设置为Dirty。
+use crate::hart::HartState;
+use core::arch::asm;
+use core::mem::offset_of;
+use riscv::register::mstatus::{self, FS};
+
+// 浮点寄存器只在模拟器需要时才保存，返回特权级时再恢复
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatState {
+    NotSaved,
+    Saved,
+    Modified,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct FloatContext {
+    pub f: [u64; 32],
+    pub fcsr: usize,
+}
+
+// rustsbi-k210按riscv64imac编译，汇编器不认识浮点指令，这里直接写出fsd/fld的编码。
+// fsd f{i}, 8*i(a0): | imm[11:5] | rs2=i | rs1=a0 | 011 | imm[4:0] | 0100111 |
+// fld f{i}, 8*i(a0): | imm[11:0] | rs1=a0 | 011 | rd=i | 0000111 |
+// 调用时mstatus.FS不能为Off，否则会产生非法指令异常
+unsafe fn save_float_registers(float: *mut FloatContext) {
+    asm!(
+        r"
+        .irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31
+        .word (((8*\i) >> 5) << 25) | (\i << 20) | (10 << 15) | (3 << 12) | (((8*\i) & 0x1f) << 7) | 0x27
+        .endr
+        ",
+        "csrr   {fcsr}, 0x003", // fcsr
+        "sd     {fcsr}, {fcsr_offset}(a0)",
+        in("a0") float,
+        fcsr = out(reg) _,
+        fcsr_offset = const offset_of!(FloatContext, fcsr),
+    )
+}
+
+unsafe fn restore_float_registers(float: *const FloatContext) {
+    asm!(
+        r"
+        .irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31
+        .word ((8*\i) << 20) | (10 << 15) | (3 << 12) | (\i << 7) | 0x07
+        .endr
+        ",
+        "ld     {fcsr}, {fcsr_offset}(a0)",
+        "csrw   0x003, {fcsr}", // fcsr
+        in("a0") float,
+        fcsr = out(reg) _,
+        fcsr_offset = const offset_of!(FloatContext, fcsr),
+    )
+}
+
+const _: () = assert!(offset_of!(FloatContext, f) == 0);
+
+impl HartState {
+    // 读取特权级的浮点寄存器。特权级关闭了浮点单元（mstatus.FS为Off）时返回None，
+    // 这时模拟器应当把异常转交给特权级处理
+    #[allow(unused)] // 供打开这个特性的指令模拟器使用
+    pub fn float_context(&mut self) -> Option<&FloatContext> {
+        if !self.save_float() {
+            return None;
+        }
+        Some(&self.float)
+    }
+
+    // 修改特权级的浮点寄存器；返回特权级时会恢复寄存器，并把mstatus.FS设置为Dirty
+    #[allow(unused)] // 供打开这个特性的指令模拟器使用
+    pub fn float_context_mut(&mut self) -> Option<&mut FloatContext> {
+        if !self.save_float() {
+            return None;
+        }
+        self.float_state = FloatState::Modified;
+        Some(&mut self.float)
+    }
+
+    // 第一次访问时才保存浮点寄存器。即使FS为Clean或Initial，寄存器里也是特权级的有效值，
+    // 机器态使用浮点单元前必须先保存
+    fn save_float(&mut self) -> bool {
+        if self.context.mstatus.fs() == FS::Off {
+            return false;
+        }
+        if self.float_state == FloatState::NotSaved {
+            unsafe { save_float_registers(&mut self.float) };
+            self.float_state = FloatState::Saved;
+        }
+        true
+    }
+
+    // 返回特权级之前调用
+    pub fn restore_float(&mut self) {
+        match self.float_state {
+            FloatState::NotSaved => return,
+            FloatState::Saved => unsafe { restore_float_registers(&self.float) },
+            FloatState::Modified => unsafe {
+                restore_float_registers(&self.float);
+                mstatus::set_fs(FS::Dirty);
+                self.context.mstatus = mstatus::read();
+            },
+        }
+        self.float_state = FloatState::NotSaved;
+    }
+}
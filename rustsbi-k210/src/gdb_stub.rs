@@ -0,0 +1,172 @@
+// GDB远程串行协议的桩，用`gdb-stub`特性编译时才会包含。
+//
+// 0号核第一次进入特权级之前停下来，在UARTHS上等待GDB连接（`target remote`）。连接以后，
+// GDB插入的断点、单步，以及`K210_GDB_CATCH`选中的异常都会让当前核停下来交给GDB；继续运行时
+// 异常仍然按原来的方式转交给特权级。协议本身在k210_boot::gdb中，这里只负责收发字节，
+// 以及通过MPRV访问特权级的寄存器和内存。
+use crate::machine_trap::{self, ACCESS_FAULT};
+use crate::runtime::SupervisorContext;
+use crate::uarths::Uarths;
+use core::arch::asm;
+use core::mem::{offset_of, size_of};
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicBool, Ordering};
+use k210_boot::config;
+use k210_boot::gdb::{Action, Event, PacketReader, Response, Stub, Target, PC, SIGTRAP};
+use k210_boot::ymodem::Port;
+use riscv::register::{mtval, sepc};
+use rustsbi::println;
+
+pub use k210_boot::gdb::{SIGILL, SIGSEGV};
+
+struct Gdb {
+    stub: Stub,
+    reader: PacketReader,
+    response: Response,
+}
+
+static mut GDB: Gdb = Gdb {
+    stub: Stub::new(),
+    reader: PacketReader::new(),
+    response: Response::new(),
+};
+// 等待GDB时每隔这么久检查一次是否需要喂狗
+const POLL_MS: u32 = 100;
+
+// 同一时间只有一个核和GDB通信，其它停下来的核在这里等待
+static GDB_LOCK: AtomicBool = AtomicBool::new(false);
+
+// x1到x31按编号排列在上下文的开头
+const _: () = assert!(offset_of!(SupervisorContext, ra) == 0);
+const _: () = assert!(offset_of!(SupervisorContext, t6) == 30 * size_of::<usize>());
+
+// 0号核进入特权级之前调用，等待GDB连接；连接以后GDB发送继续运行的请求时返回
+pub fn attach(ctx: &mut SupervisorContext) {
+    println!("[rustsbi] waiting for gdb on the console serial port");
+    with_gdb(|gdb| {
+        let mut target = Supervisor(ctx);
+        gdb.stub.stop(SIGTRAP, &mut target, &mut gdb.response);
+        // GDB连接时会先询问停止原因，这里不主动发送
+        gdb.response.clear();
+        serve(gdb, &mut target);
+    })
+}
+
+// 特权级的断点异常；是GDB插入的断点时停下来并返回true，否则应当转交给特权级
+pub fn breakpoint(ctx: &mut SupervisorContext) -> bool {
+    with_gdb(|gdb| {
+        if !(gdb.stub.attached() && gdb.stub.is_breakpoint(ctx.mepc)) {
+            return false;
+        }
+        stop(gdb, ctx, SIGTRAP);
+        true
+    })
+}
+
+// 按mcause的编号判断是否需要停下来；继续运行后调用者仍然转交这个异常
+pub fn catch(ctx: &mut SupervisorContext, cause: usize, signal: u8) {
+    if config::GDB_CATCH & (1 << cause) == 0 {
+        return;
+    }
+    with_gdb(|gdb| {
+        if gdb.stub.attached() {
+            stop(gdb, ctx, signal);
+        }
+    })
+}
+
+fn stop(gdb: &mut Gdb, ctx: &mut SupervisorContext, signal: u8) {
+    // 访问特权级内存出错时会改写mtval，转交异常时还要用到它
+    let mtval = mtval::read();
+    let mut target = Supervisor(ctx);
+    gdb.stub.stop(signal, &mut target, &mut gdb.response);
+    Uarths.write_all(gdb.response.finish()).ok();
+    serve(gdb, &mut target);
+    unsafe { asm!("csrw mtval, {}", in(reg) mtval) };
+}
+
+// 处理GDB的请求，直到继续运行、单步或者分离
+fn serve(gdb: &mut Gdb, target: &mut Supervisor) {
+    loop {
+        // 停在GDB中时看门狗不能复位芯片
+        #[cfg(feature = "watchdog")]
+        crate::watchdog::kick();
+        let Ok(Some(byte)) = Uarths.read_byte(POLL_MS) else {
+            continue;
+        };
+        match gdb.reader.feed(byte) {
+            Some(Event::Packet(packet)) => {
+                Uarths.write_all(b"+").ok();
+                let action = gdb.stub.handle(packet, target, &mut gdb.response);
+                if action == Action::Reply || !gdb.response.is_empty() {
+                    Uarths.write_all(gdb.response.finish()).ok();
+                }
+                if action == Action::Resume {
+                    return;
+                }
+            }
+            Some(Event::BadPacket) => {
+                Uarths.write_all(b"-").ok();
+            }
+            Some(Event::Nack) => {
+                Uarths.write_all(gdb.response.finish()).ok();
+            }
+            // 核已经停下来了，不需要处理Ctrl-C
+            Some(Event::Ack) | Some(Event::Interrupt) | None => {}
+        }
+    }
+}
+
+fn with_gdb<T>(f: impl FnOnce(&mut Gdb) -> T) -> T {
+    while GDB_LOCK
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        core::hint::spin_loop();
+    }
+    let result = f(unsafe { &mut *addr_of_mut!(GDB) });
+    GDB_LOCK.store(false, Ordering::Release);
+    result
+}
+
+// 停下来的核；pc就是返回特权级时的mepc
+struct Supervisor<'a>(&'a mut SupervisorContext);
+
+impl Target for Supervisor<'_> {
+    fn register(&self, number: usize) -> usize {
+        match number {
+            1..=31 => unsafe {
+                *(&*self.0 as *const SupervisorContext as *const usize).add(number - 1)
+            },
+            PC => self.0.mepc,
+            _ => 0,
+        }
+    }
+
+    fn set_register(&mut self, number: usize, value: usize) {
+        match number {
+            1..=31 => unsafe {
+                *(&mut *self.0 as *mut SupervisorContext as *mut usize).add(number - 1) = value
+            },
+            PC => self.0.mepc = value,
+            _ => {}
+        }
+    }
+
+    fn read_byte(&mut self, address: usize) -> Option<u8> {
+        let byte = unsafe { machine_trap::load_supervisor_u8(address) };
+        (byte != ACCESS_FAULT).then_some(byte as u8)
+    }
+
+    fn write_byte(&mut self, address: usize, value: u8) -> bool {
+        unsafe { machine_trap::store_supervisor_u8(address, value) != ACCESS_FAULT }
+    }
+
+    fn sync_instructions(&mut self) {
+        unsafe { asm!("fence.i") };
+    }
+
+    fn sepc(&self) -> usize {
+        sepc::read()
+    }
+}
@@ -0,0 +1,141 @@
+// 决定下一阶段程序的入口、特权级和启动核。
+//
+// 按以下顺序查找OpenSBI的fw_dynamic_info结构：前一级引导程序在a2中传入的地址，
+// 合并镜像中位于下一阶段程序之前的交接区。都没有找到时使用编译时的启动配置。
+// 找到的结构体地址在a2中原样传给下一阶段程序，没有时传0。
+//
+// 交接区中还可以有初始内存盘的描述头，设备树模块据此告诉内核内存盘的位置；
+// 以及带签名尾部的内核镜像长度，打开验证启动时据此检查内核的签名。
+use crate::hart::NUM_HARTS;
+use k210_boot::config;
+use k210_boot::fw_dynamic::{
+    FwDynamicInfo, FW_DYNAMIC_INFO_MAGIC, FW_DYNAMIC_INFO_SIZE, NEXT_MODE_U,
+};
+use k210_boot::initrd::{InitrdHeader, INITRD_HEADER_SIZE, INITRD_MAGIC};
+use riscv::register::mstatus::MPP;
+use rustsbi::println;
+
+#[derive(Clone, Copy)]
+pub struct NextStage {
+    pub address: usize,
+    pub mode: MPP,
+    pub boot_hart: usize,
+    // 传给下一阶段程序的a2
+    pub info: usize,
+    pub quiet: bool,
+}
+
+// 由0号核在唤醒其它核之前写入
+static mut NEXT_STAGE: NextStage = NextStage {
+    address: config::PAYLOAD_ADDRESS,
+    mode: MPP::Supervisor,
+    boot_hart: 0,
+    info: 0,
+    quiet: false,
+};
+
+// 带签名尾部的内核镜像的起始地址和长度，由0号核写入
+#[cfg(feature = "verified-boot")]
+static mut SIGNED_IMAGE: Option<(usize, usize)> = None;
+
+// 0号核清零.bss段之后调用；prev_info是进入固件时的a2
+pub fn init(prev_info: usize) {
+    #[cfg(feature = "verified-boot")]
+    read_image_size();
+    let found = [prev_info, config::HANDOFF_ADDRESS]
+        .into_iter()
+        .find_map(|address| read_info(address).map(|info| (address, info)));
+    let Some((address, info)) = found else {
+        return;
+    };
+    if let Err(e) = info.validate(NUM_HARTS) {
+        println!(
+            "[rustsbi] ignored fw_dynamic_info at {:#x}: {:?}",
+            address, e
+        );
+        return;
+    }
+    let next = NextStage {
+        address: info.next_addr as usize,
+        mode: if info.next_mode == NEXT_MODE_U {
+            MPP::User
+        } else {
+            MPP::Supervisor
+        },
+        boot_hart: info.boot_hart().unwrap_or(0) as usize,
+        info: address,
+        quiet: info.no_boot_prints(),
+    };
+    unsafe { NEXT_STAGE = next };
+}
+
+pub fn next_stage() -> NextStage {
+    unsafe { NEXT_STAGE }
+}
+
+// 从其它位置加载了下一阶段程序时，由0号核改写入口地址
+#[cfg(any(feature = "flash-boot", feature = "env", feature = "monitor"))]
+pub fn set_next_address(address: usize) {
+    unsafe { NEXT_STAGE.address = address };
+}
+
+// 加载或解压的程序只能放在交接区以后的SRAM中，也不能覆盖设备树
+pub fn load_range_valid(start: usize, size: usize) -> bool {
+    let Some(end) = start.checked_add(size) else {
+        return false;
+    };
+    let overlaps_dtb =
+        config::DTB_ADDRESS.is_some_and(|dtb| start < dtb + config::DTB_SIZE_LIMIT && dtb < end);
+    start >= config::HANDOFF_ADDRESS + config::HANDOFF_SIZE
+        && end <= config::RAM_END
+        && !overlaps_dtb
+}
+
+#[cfg(feature = "verified-boot")]
+pub fn signed_image() -> Option<(usize, usize)> {
+    unsafe { SIGNED_IMAGE }
+}
+
+// 从其它位置加载了内核后，由0号核改为加载的范围
+#[cfg(feature = "verified-boot")]
+pub fn set_signed_image(start: usize, size: usize) {
+    unsafe { SIGNED_IMAGE = Some((start, size)) };
+}
+
+// 合并镜像中的内核紧跟在交接区之后；长度为0或超出SRAM时认为没有签名
+#[cfg(feature = "verified-boot")]
+fn read_image_size() {
+    let address = config::HANDOFF_ADDRESS + config::HANDOFF_IMAGE_SIZE_OFFSET;
+    let size = unsafe { core::ptr::read_volatile(address as *const u64) } as usize;
+    if size != 0 && size <= config::RAM_END - config::PAYLOAD_ADDRESS {
+        set_signed_image(config::PAYLOAD_ADDRESS, size);
+    }
+}
+
+// 只读取SRAM中、RustSBI镜像以外的地址；复位后a2是随机值，不能直接访问
+fn read_info(address: usize) -> Option<FwDynamicInfo> {
+    let sbi_end = config::SBI_START + config::SBI_SIZE_LIMIT;
+    if address % 8 != 0 || address < sbi_end || address > config::RAM_END - FW_DYNAMIC_INFO_SIZE {
+        return None;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(address as *const u8, FW_DYNAMIC_INFO_SIZE) };
+    FwDynamicInfo::from_bytes(bytes).filter(|info| info.magic == FW_DYNAMIC_INFO_MAGIC)
+}
+
+// 交接区中描述的初始内存盘，返回起止地址；内存盘不能和固件重叠
+pub fn initrd() -> Option<(usize, usize)> {
+    let address = config::HANDOFF_ADDRESS + config::HANDOFF_INITRD_OFFSET;
+    let bytes = unsafe { core::slice::from_raw_parts(address as *const u8, INITRD_HEADER_SIZE) };
+    let header = InitrdHeader::from_bytes(bytes)?;
+    if header.magic != INITRD_MAGIC {
+        return None;
+    }
+    let sbi_end = (config::SBI_START + config::SBI_SIZE_LIMIT) as u64;
+    match header.validate(sbi_end, config::RAM_END as u64) {
+        Ok((start, end)) => Some((start as usize, end as usize)),
+        Err(e) => {
+            println!("[rustsbi] ignored initrd header: {:?}", e);
+            None
+        }
+    }
+}
@@ -0,0 +1,83 @@
+// 每个核的机器态状态块。
+//
+// 特权级运行时mscratch指向当前核的状态块（它的第一个字段就是特权级上下文），
+// 陷入机器态后，运行时和各个扩展都通过mscratch找到当前核的状态，不再使用全局变量。
+#[cfg(feature = "float-context")]
+use crate::float::{FloatContext, FloatState};
+use crate::runtime::SupervisorContext;
+use crate::stats::HartStats;
+use crate::trap_history::TrapHistory;
+use core::mem::{offset_of, MaybeUninit};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use riscv::register::mscratch;
+
+pub const NUM_HARTS: usize = 2;
+
+// 其它核请求当前核完成的工作，保存在HartState::pending中
+pub const PENDING_SUPERVISOR_SOFT: usize = 1 << 0;
+pub const PENDING_FENCE_I: usize = 1 << 1;
+pub const PENDING_SFENCE_VMA: usize = 1 << 2;
+
+#[repr(C)]
+pub struct HartState {
+    // 必须放在第一个，陷入时汇编代码把mscratch当作特权级上下文使用
+    pub context: SupervisorContext,
+    #[cfg(feature = "float-context")]
+    pub float: FloatContext,
+    #[cfg(feature = "float-context")]
+    pub float_state: FloatState,
+    pub hart_id: usize,
+    // 正在处理特权级的陷入，此时context保存着陷入时的特权级上下文
+    pub handling_trap: bool,
+    // 陷入入口判断异常来源时临时保存t0
+    pub trap_scratch: usize,
+    // 其它核发来的核间中断和内存屏障请求
+    pub pending: AtomicUsize,
+    // 虚拟CSR：1.9.1版本没有S态外部中断，由sbi_rustsbi_k210_sext注册的处理函数入口
+    pub sext_entry: usize,
+    pub stats: HartStats,
+    pub history: TrapHistory,
+}
+
+const _: () = assert!(offset_of!(HartState, context) == 0);
+
+// 位于.bss段，启动核清零后才会唤醒其它核
+static mut HART_STATES: MaybeUninit<[HartState; NUM_HARTS]> = MaybeUninit::uninit();
+
+pub fn init(hartid: usize) -> &'static mut HartState {
+    let hart = unsafe { &mut (*HART_STATES.as_mut_ptr())[hartid] };
+    hart.hart_id = hartid;
+    #[cfg(feature = "float-context")]
+    hart.float_state = FloatState::NotSaved;
+    hart.handling_trap = false;
+    hart.pending = AtomicUsize::new(0);
+    hart.sext_entry = 0;
+    hart.stats = HartStats::new();
+    hart.history = TrapHistory::new();
+    hart
+}
+
+// 当前核的状态块；只能在runtime::init之后调用
+#[inline]
+pub fn this_hart() -> &'static mut HartState {
+    unsafe { &mut *(mscratch::read() as *mut HartState) }
+}
+
+// 其它核的状态块，只应当访问其中的原子变量
+#[inline]
+pub fn hart(hartid: usize) -> Option<&'static HartState> {
+    if hartid >= NUM_HARTS {
+        return None;
+    }
+    Some(unsafe { &(*HART_STATES.as_ptr())[hartid] })
+}
+
+impl HartState {
+    pub fn request(&self, pending: usize) {
+        self.pending.fetch_or(pending, Ordering::AcqRel);
+    }
+
+    pub fn take_pending(&self) -> usize {
+        self.pending.swap(0, Ordering::AcqRel)
+    }
+}
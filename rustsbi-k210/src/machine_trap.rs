@@ -0,0 +1,335 @@
+// 机器态自身产生的异常。
+//
+// mtvec总是指向from_supervisor_save，它发现mstatus.MPP为Machine时跳转到这里。这类异常不能写入
+// 特权级上下文，否则会悄悄破坏内核的状态。已知可能出错的访存（例如通过MPRV读取特权级内存）
+// 登记在RECOVERABLE中，出错时跳到对应的恢复位置继续执行；其它异常打印机器态寄存器和返回地址链，
+// 然后按照RustSBI出错时的策略关机。
+use crate::backtrace;
+use crate::hart::HartState;
+use core::arch::asm;
+use core::mem::{offset_of, size_of};
+use riscv::register::{mcause, mepc, mstatus, mtval};
+use rustsbi::{print, println};
+
+// 按寄存器编号保存的机器态寄存器，x[0]不使用
+#[repr(C, align(16))]
+pub struct MachineTrapFrame {
+    pub x: [usize; 32],
+}
+
+const _: () = assert!(size_of::<MachineTrapFrame>() % 16 == 0);
+
+// 汇编代码中x[i]的偏移量从结构体定义得到，修改结构体时不需要再手动修改汇编
+macro_rules! frame_offset {
+    ($i:expr) => {
+        offset_of!(MachineTrapFrame, x) + $i * size_of::<usize>()
+    };
+}
+
+const REGISTER_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+// 访问失败时的返回值
+pub const ACCESS_FAULT: usize = usize::MAX;
+
+extern "C" {
+    static rustsbi_k210_load_u16_insn: u8;
+    static rustsbi_k210_load_u16_fixup: u8;
+    #[cfg(feature = "monitor")]
+    static rustsbi_k210_load_u8_insn: u8;
+    #[cfg(feature = "monitor")]
+    static rustsbi_k210_load_u8_fixup: u8;
+    #[cfg(feature = "monitor")]
+    static rustsbi_k210_store_u32_insn: u8;
+    #[cfg(feature = "monitor")]
+    static rustsbi_k210_store_u32_fixup: u8;
+    #[cfg(feature = "gdb-stub")]
+    static rustsbi_k210_load_supervisor_u8_insn: u8;
+    #[cfg(feature = "gdb-stub")]
+    static rustsbi_k210_load_supervisor_u8_fixup: u8;
+    #[cfg(feature = "gdb-stub")]
+    static rustsbi_k210_store_supervisor_u8_insn: u8;
+    #[cfg(feature = "gdb-stub")]
+    static rustsbi_k210_store_supervisor_u8_fixup: u8;
+}
+
+// 可恢复的访存指令地址，和出错后继续执行的位置；出错时a0被设置为ACCESS_FAULT
+fn recoverable_fixup(mepc: usize) -> Option<usize> {
+    let recoverable = unsafe {
+        [
+            (
+                &rustsbi_k210_load_u16_insn as *const u8 as usize,
+                &rustsbi_k210_load_u16_fixup as *const u8 as usize,
+            ),
+            #[cfg(feature = "monitor")]
+            (
+                &rustsbi_k210_load_u8_insn as *const u8 as usize,
+                &rustsbi_k210_load_u8_fixup as *const u8 as usize,
+            ),
+            #[cfg(feature = "monitor")]
+            (
+                &rustsbi_k210_store_u32_insn as *const u8 as usize,
+                &rustsbi_k210_store_u32_fixup as *const u8 as usize,
+            ),
+            #[cfg(feature = "monitor")]
+            crate::monitor::csr_recoverable(),
+            #[cfg(feature = "gdb-stub")]
+            (
+                &rustsbi_k210_load_supervisor_u8_insn as *const u8 as usize,
+                &rustsbi_k210_load_supervisor_u8_fixup as *const u8 as usize,
+            ),
+            #[cfg(feature = "gdb-stub")]
+            (
+                &rustsbi_k210_store_supervisor_u8_insn as *const u8 as usize,
+                &rustsbi_k210_store_supervisor_u8_fixup as *const u8 as usize,
+            ),
+        ]
+    };
+    recoverable
+        .iter()
+        .find(|(insn, _)| *insn == mepc)
+        .map(|(_, fixup)| *fixup)
+}
+
+// 通过MPRV，以特权级的地址空间读取16位数据；读取失败时返回ACCESS_FAULT
+#[naked]
+#[link_section = ".text"]
+pub unsafe extern "C" fn load_supervisor_u16(_vaddr: usize) -> usize {
+    asm!(
+        "li     t0, (1 << 17)
+        csrrs   t0, mstatus, t0",
+        ".global rustsbi_k210_load_u16_insn
+rustsbi_k210_load_u16_insn:
+        lhu     a0, 0(a0)",
+        ".global rustsbi_k210_load_u16_fixup
+rustsbi_k210_load_u16_fixup:
+        csrw    mstatus, t0
+        ret",
+        options(noreturn)
+    )
+}
+
+// GDB通过MPRV读取特权级的一个字节；读取失败时返回ACCESS_FAULT
+#[cfg(feature = "gdb-stub")]
+#[naked]
+#[link_section = ".text"]
+pub unsafe extern "C" fn load_supervisor_u8(_vaddr: usize) -> usize {
+    asm!(
+        "li     t0, (1 << 17)
+        csrrs   t0, mstatus, t0",
+        ".global rustsbi_k210_load_supervisor_u8_insn
+rustsbi_k210_load_supervisor_u8_insn:
+        lbu     a0, 0(a0)",
+        ".global rustsbi_k210_load_supervisor_u8_fixup
+rustsbi_k210_load_supervisor_u8_fixup:
+        csrw    mstatus, t0
+        ret",
+        options(noreturn)
+    )
+}
+
+// GDB通过MPRV写入特权级的一个字节，用于修改内存和插入断点；成功时返回0，失败时返回ACCESS_FAULT
+#[cfg(feature = "gdb-stub")]
+#[naked]
+#[link_section = ".text"]
+pub unsafe extern "C" fn store_supervisor_u8(_vaddr: usize, _value: u8) -> usize {
+    asm!(
+        "li     t0, (1 << 17)
+        csrrs   t0, mstatus, t0",
+        ".global rustsbi_k210_store_supervisor_u8_insn
+rustsbi_k210_store_supervisor_u8_insn:
+        sb      a1, 0(a0)
+        li      a0, 0",
+        ".global rustsbi_k210_store_supervisor_u8_fixup
+rustsbi_k210_store_supervisor_u8_fixup:
+        csrw    mstatus, t0
+        ret",
+        options(noreturn)
+    )
+}
+
+// 启动监视器读取任意地址的一个字节；读取失败时返回ACCESS_FAULT
+#[cfg(feature = "monitor")]
+#[naked]
+#[link_section = ".text"]
+pub unsafe extern "C" fn load_u8(_address: usize) -> usize {
+    asm!(
+        ".global rustsbi_k210_load_u8_insn
+rustsbi_k210_load_u8_insn:
+        lbu     a0, 0(a0)",
+        ".global rustsbi_k210_load_u8_fixup
+rustsbi_k210_load_u8_fixup:
+        ret",
+        options(noreturn)
+    )
+}
+
+// 启动监视器写入任意地址的一个字；成功时返回0，失败时返回ACCESS_FAULT
+#[cfg(feature = "monitor")]
+#[naked]
+#[link_section = ".text"]
+pub unsafe extern "C" fn store_u32(_address: usize, _value: u32) -> usize {
+    asm!(
+        ".global rustsbi_k210_store_u32_insn
+rustsbi_k210_store_u32_insn:
+        sw      a1, 0(a0)
+        li      a0, 0",
+        ".global rustsbi_k210_store_u32_fixup
+rustsbi_k210_store_u32_fixup:
+        ret",
+        options(noreturn)
+    )
+}
+
+extern "C" fn machine_trap_handler(frame: &mut MachineTrapFrame) {
+    let mepc = mepc::read();
+    if let Some(fixup) = recoverable_fixup(mepc) {
+        frame.x[10] = ACCESS_FAULT; // a0
+        mepc::write(fixup);
+        return;
+    }
+    let hart_id = riscv::register::mhartid::read();
+    println!(
+        "[rustsbi-fault] hart {} unexpected trap from machine level",
+        hart_id
+    );
+    println!(
+        "[rustsbi-fault] mcause: {:?} ({:#x}), mepc: {:#018x}, mbadaddr: {:#018x}, mstatus: {:#018x}",
+        mcause::read().cause(),
+        mcause::read().bits(),
+        mepc,
+        mtval::read(),
+        mstatus::read().bits()
+    );
+    for (i, name) in REGISTER_NAMES.iter().enumerate().skip(1) {
+        print!("{:>4}: {:#018x}", name, frame.x[i]);
+        if i % 4 == 3 {
+            println!("");
+        } else {
+            print!("  ");
+        }
+    }
+    backtrace::print_backtrace(hart_id, mepc, frame.x[1], frame.x[8]);
+    println!("[rustsbi-fault] system shutdown scheduled due to machine level trap");
+    use rustsbi::Reset;
+    crate::peripheral::Reset.system_reset(
+        rustsbi::reset::RESET_TYPE_SHUTDOWN,
+        rustsbi::reset::RESET_REASON_SYSTEM_FAILURE,
+    );
+}
+
+// 进入时sp:当前核状态块, mscratch:机器栈, t0保存在状态块的trap_scratch中
+#[naked]
+#[link_section = ".text"]
+pub unsafe extern "C" fn from_machine_trap() -> ! {
+    asm!(
+        "csrrw  sp, mscratch, sp", // 新sp:机器栈, 新mscratch:当前核状态块
+        "addi   sp, sp, -{frame_size}",
+        "sd      ra, {ra}(sp)
+        sd      gp, {gp}(sp)
+        sd      tp, {tp}(sp)
+        sd      t1, {t1}(sp)
+        sd      t2, {t2}(sp)
+        sd      s0, {s0}(sp)
+        sd      s1, {s1}(sp)
+        sd      a0, {a0}(sp)
+        sd      a1, {a1}(sp)
+        sd      a2, {a2}(sp)
+        sd      a3, {a3}(sp)
+        sd      a4, {a4}(sp)
+        sd      a5, {a5}(sp)
+        sd      a6, {a6}(sp)
+        sd      a7, {a7}(sp)
+        sd      s2, {s2}(sp)
+        sd      s3, {s3}(sp)
+        sd      s4, {s4}(sp)
+        sd      s5, {s5}(sp)
+        sd      s6, {s6}(sp)
+        sd      s7, {s7}(sp)
+        sd      s8, {s8}(sp)
+        sd      s9, {s9}(sp)
+        sd      s10, {s10}(sp)
+        sd      s11, {s11}(sp)
+        sd      t3, {t3}(sp)
+        sd      t4, {t4}(sp)
+        sd      t5, {t5}(sp)
+        sd      t6, {t6}(sp)",
+        "csrr   t0, mscratch
+        ld      t0, {trap_scratch}(t0)
+        sd      t0, {t0}(sp)", // 原来的t0
+        "addi   t0, sp, {frame_size}
+        sd      t0, {sp}(sp)", // 原来的sp
+        "mv     a0, sp",
+        "call   {machine_trap_handler}",
+        "ld      ra, {ra}(sp)
+        ld      gp, {gp}(sp)
+        ld      tp, {tp}(sp)
+        ld      t1, {t1}(sp)
+        ld      t2, {t2}(sp)
+        ld      s0, {s0}(sp)
+        ld      s1, {s1}(sp)
+        ld      a0, {a0}(sp)
+        ld      a1, {a1}(sp)
+        ld      a2, {a2}(sp)
+        ld      a3, {a3}(sp)
+        ld      a4, {a4}(sp)
+        ld      a5, {a5}(sp)
+        ld      a6, {a6}(sp)
+        ld      a7, {a7}(sp)
+        ld      s2, {s2}(sp)
+        ld      s3, {s3}(sp)
+        ld      s4, {s4}(sp)
+        ld      s5, {s5}(sp)
+        ld      s6, {s6}(sp)
+        ld      s7, {s7}(sp)
+        ld      s8, {s8}(sp)
+        ld      s9, {s9}(sp)
+        ld      s10, {s10}(sp)
+        ld      s11, {s11}(sp)
+        ld      t3, {t3}(sp)
+        ld      t4, {t4}(sp)
+        ld      t5, {t5}(sp)
+        ld      t6, {t6}(sp)
+        ld      t0, {t0}(sp)",
+        "addi   sp, sp, {frame_size}", // sp:机器栈, mscratch:当前核状态块
+        "mret",
+        frame_size = const size_of::<MachineTrapFrame>(),
+        ra = const frame_offset!(1),
+        sp = const frame_offset!(2),
+        gp = const frame_offset!(3),
+        tp = const frame_offset!(4),
+        t0 = const frame_offset!(5),
+        t1 = const frame_offset!(6),
+        t2 = const frame_offset!(7),
+        s0 = const frame_offset!(8),
+        s1 = const frame_offset!(9),
+        a0 = const frame_offset!(10),
+        a1 = const frame_offset!(11),
+        a2 = const frame_offset!(12),
+        a3 = const frame_offset!(13),
+        a4 = const frame_offset!(14),
+        a5 = const frame_offset!(15),
+        a6 = const frame_offset!(16),
+        a7 = const frame_offset!(17),
+        s2 = const frame_offset!(18),
+        s3 = const frame_offset!(19),
+        s4 = const frame_offset!(20),
+        s5 = const frame_offset!(21),
+        s6 = const frame_offset!(22),
+        s7 = const frame_offset!(23),
+        s8 = const frame_offset!(24),
+        s9 = const frame_offset!(25),
+        s10 = const frame_offset!(26),
+        s11 = const frame_offset!(27),
+        t3 = const frame_offset!(28),
+        t4 = const frame_offset!(29),
+        t5 = const frame_offset!(30),
+        t6 = const frame_offset!(31),
+        trap_scratch = const offset_of!(HartState, trap_scratch),
+        machine_trap_handler = sym machine_trap_handler,
+        options(noreturn)
+    )
+}
@@ -4,24 +4,80 @@
 #![feature(asm_const)]
 #![feature(riscv_ext_intrinsics)]
 
+// 跟踪输出宏trace!需要在其它模块之前定义
+#[cfg(feature = "trace")]
+#[macro_use]
+mod trace;
+#[cfg(not(feature = "trace"))]
+macro_rules! trace {
+    ($mask:expr, $fmt:literal $(, $arg:expr)* $(,)?) => {{
+        $(let _ = &$arg;)*
+    }};
+}
+
+mod backtrace;
+mod device_tree;
+#[cfg(feature = "env")]
+mod env;
 mod execute;
 mod feature;
+#[cfg(feature = "flash-boot")]
+mod flash;
+#[cfg(feature = "float-context")]
+mod float;
+#[cfg(feature = "gdb-stub")]
+mod gdb_stub;
+mod handoff;
+mod hart;
 mod hart_csr_utils;
+mod machine_trap;
+#[cfg(feature = "monitor")]
+mod monitor;
 mod peripheral;
 mod runtime;
+#[cfg(feature = "sd-boot")]
+mod sdcard;
+#[cfg(feature = "serial-boot")]
+mod serial_boot;
+#[cfg(any(feature = "flash-boot", feature = "env"))]
+mod spi_flash;
+mod stack;
+mod stats;
+mod trap_history;
+#[cfg(any(feature = "serial-boot", feature = "monitor", feature = "gdb-stub"))]
+mod uarths;
+mod unpack;
+mod vendor;
+#[cfg(feature = "verified-boot")]
+mod verified_boot;
+#[cfg(feature = "watchdog")]
+mod watchdog;
+
+// 监视器可以在检查签名之前改写内存和CSR，包括公钥和已经加载的内核
+#[cfg(all(feature = "monitor", feature = "verified-boot"))]
+compile_error!("the `monitor` feature cannot be enabled together with `verified-boot`");
+// GDB在签名检查之后仍然可以改写内核的内存和寄存器
+#[cfg(all(feature = "gdb-stub", feature = "verified-boot"))]
+compile_error!("the `gdb-stub` feature cannot be enabled together with `verified-boot`");
 
 extern crate alloc;
 
 use buddy_system_allocator::LockedHeap;
 use core::arch::asm;
 use core::panic::PanicInfo;
+#[cfg(any(feature = "flash-boot", feature = "sd-boot", feature = "serial-boot"))]
+use k210_boot::env::BootMode;
 
 use rustsbi::println;
 
 const PER_HART_STACK_SIZE: usize = 8 * 1024; // 8KiB
 const SBI_STACK_SIZE: usize = 2 * PER_HART_STACK_SIZE;
+// 栈指针要16字节对齐，栈底的金丝雀按usize读写，链接脚本只保证4字节对齐
+#[repr(C, align(16))]
+struct SbiStack([u8; SBI_STACK_SIZE]);
+
 #[link_section = ".bss.uninit"]
-static mut SBI_STACK: [u8; SBI_STACK_SIZE] = [0; SBI_STACK_SIZE];
+static mut SBI_STACK: SbiStack = SbiStack([0; SBI_STACK_SIZE]);
 
 const SBI_HEAP_SIZE: usize = 8 * 1024; // 8KiB
 #[link_section = ".bss.uninit"]
@@ -29,14 +85,22 @@ static mut HEAP_SPACE: [u8; SBI_HEAP_SIZE] = [0; SBI_HEAP_SIZE];
 #[global_allocator]
 static SBI_HEAP: LockedHeap<32> = LockedHeap::empty();
 
-static DEVICE_TREE_BINARY: &[u8] = include_bytes!("../kendryte-k210.dtb");
-
 #[cfg_attr(not(test), panic_handler)]
 #[allow(unused)]
 fn panic(info: &PanicInfo) -> ! {
     let hart_id = riscv::register::mhartid::read();
     // 输出的信息大概是“[rustsbi-panic] hart 0 panicked at ...”
     println!("[rustsbi-panic] hart {} {}", hart_id, info);
+    backtrace::print_current_backtrace(hart_id);
+    if let Some(hart) = hart::hart(hart_id) {
+        if hart.handling_trap {
+            println!(
+                "[rustsbi-panic] while handling supervisor trap, context: {:#x?}",
+                hart.context
+            );
+        }
+        hart.history.dump(hart_id);
+    }
     println!("[rustsbi-panic] system shutdown scheduled due to RustSBI panic");
     use rustsbi::Reset;
     peripheral::Reset.system_reset(
@@ -46,56 +110,128 @@ fn panic(info: &PanicInfo) -> ! {
     loop {}
 }
 
-extern "C" fn rust_main() -> ! {
+// a2是前一级引导程序传入的fw_dynamic_info地址，见handoff模块
+extern "C" fn rust_main(_hartid: usize, _opaque: usize, prev_info: usize) -> ! {
     let hartid = riscv::register::mhartid::read();
     if hartid == 0 {
         init_bss();
     }
     pause_if_not_start_hart();
-    runtime::init();
+    stack::paint(hartid);
+    runtime::init(hartid);
     if hartid == 0 {
+        #[cfg(feature = "trace")]
+        trace::init();
         init_heap();
+        #[cfg(feature = "env")]
+        env::init();
         peripheral::init_peripheral();
-        println!("[rustsbi] RustSBI version {}", rustsbi::VERSION);
-        println!("{}", rustsbi::LOGO);
-        println!(
-            "[rustsbi] Implementation: RustSBI-K210 Version {}",
-            env!("CARGO_PKG_VERSION")
-        );
+        #[cfg(feature = "watchdog")]
+        watchdog::init();
+        handoff::init(prev_info);
+        unpack::unpack_fused_payload();
+        #[cfg(feature = "env")]
+        env::apply();
+        #[cfg(feature = "flash-boot")]
+        if boot_mode().loads(BootMode::Flash) {
+            if let Some(entry) = flash::load_payload() {
+                handoff::set_next_address(entry);
+            }
+        }
+        #[cfg(feature = "sd-boot")]
+        if boot_mode().loads(BootMode::Sd) {
+            sdcard::load_payload();
+        }
+        #[cfg(feature = "serial-boot")]
+        if boot_mode().loads(BootMode::Serial) {
+            serial_boot::load_payload(boot_mode() == BootMode::Serial);
+        }
+        #[cfg(feature = "monitor")]
+        monitor::run();
+        #[cfg(feature = "verified-boot")]
+        verified_boot::check();
+        device_tree::init();
+        if !handoff::next_stage().quiet {
+            println!("[rustsbi] RustSBI version {}", rustsbi::VERSION);
+            println!("{}", rustsbi::LOGO);
+            println!(
+                "[rustsbi] Implementation: RustSBI-K210 Version {}",
+                env!("CARGO_PKG_VERSION")
+            );
+        }
+    }
+    let next = handoff::next_stage();
+    if hartid == 0 && next.boot_hart != 0 {
+        // 由其它核启动下一阶段程序，0号核和其它核一样等待唤醒
+        unsafe { k210_hal::clint::msip::set_ipi(next.boot_hart) };
+        pause_hart();
     }
     delegate_interrupt_exception();
-    if hartid == 0 {
+    if hartid == next.boot_hart && !next.quiet {
         hart_csr_utils::print_hart_csrs();
-        println!("[rustsbi] enter supervisor 0x80020000");
+        println!(
+            "[rustsbi] enter {:?} {:#x} on hart {}, device tree at {:#x}",
+            next.mode,
+            next.address,
+            hartid,
+            device_tree::address()
+        );
     }
-    execute::execute_supervisor(0x80020000, hartid, DEVICE_TREE_BINARY.as_ptr() as usize)
+    // 之前加载内核和等待监视器的时间不计入看门狗
+    #[cfg(feature = "watchdog")]
+    if hartid == next.boot_hart {
+        watchdog::arm_at_boot();
+    }
+    execute::execute_supervisor(
+        next.address,
+        next.mode,
+        [hartid, device_tree::address(), next.info],
+    )
 }
 
 fn pause_if_not_start_hart() {
+    if riscv::register::mhartid::read() != 0 {
+        pause_hart();
+    }
+}
+
+// 等待核间中断唤醒当前核
+fn pause_hart() {
     use k210_hal::clint::msip;
     use riscv::asm::wfi;
     use riscv::register::{mhartid, mie, mip};
 
     let hartid = mhartid::read();
-    if hartid != 0 {
-        unsafe {
-            // Clear IPI
-            msip::clear_ipi(hartid);
-            // Start listening for software interrupts
-            mie::set_msoft();
-
-            loop {
-                wfi();
-                if mip::read().msoft() {
-                    break;
-                }
-            }
+    unsafe {
+        // Clear IPI
+        msip::clear_ipi(hartid);
+        // Start listening for software interrupts
+        mie::set_msoft();
 
-            // Stop listening for software interrupts
-            mie::clear_msoft();
-            // Clear IPI
-            msip::clear_ipi(hartid);
+        loop {
+            wfi();
+            if mip::read().msoft() {
+                break;
+            }
         }
+
+        // Stop listening for software interrupts
+        mie::clear_msoft();
+        // Clear IPI
+        msip::clear_ipi(hartid);
+    }
+}
+
+// 环境变量bootmode选择的启动方式，决定尝试哪些加载方式
+#[cfg(any(feature = "flash-boot", feature = "sd-boot", feature = "serial-boot"))]
+fn boot_mode() -> BootMode {
+    #[cfg(feature = "env")]
+    {
+        env::boot_mode()
+    }
+    #[cfg(not(feature = "env"))]
+    {
+        BootMode::Auto
     }
 }
 
@@ -129,6 +265,8 @@ fn delegate_interrupt_exception() {
         mideleg::set_stimer();
         mideleg::set_ssoft();
         medeleg::set_instruction_misaligned();
+        // gdb-stub需要先检查断点是不是GDB插入的
+        #[cfg(not(feature = "gdb-stub"))]
         medeleg::set_breakpoint();
         medeleg::set_user_env_call();
         /* MMU Exception Delegation
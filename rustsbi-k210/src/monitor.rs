@@ -0,0 +1,191 @@
+// 启动监视器，用`monitor`特性编译时才会包含。
+//
+// 0号核进入下一阶段程序之前在控制台上等待`K210_MONITOR_WAIT_MS`毫秒，期间按任意键进入监视器。
+// 在监视器中可以查看和修改内存和CSR、查看陷入记录、修改本次启动的入口地址、内核命令行和跟踪掩码，
+// 或者通过串口下载内核，输入`boot`后继续启动。访问不存在的地址或CSR时只报告错误，不会关机。
+use crate::machine_trap::{self, ACCESS_FAULT};
+use crate::uarths::Uarths;
+use crate::{handoff, hart, hart_csr_utils};
+use core::ptr::{addr_of, addr_of_mut};
+use k210_boot::config;
+use k210_boot::monitor::{self, Command, Edit, LineEditor, CSR_NAMES, LINE_MAX};
+use k210_boot::ymodem::Port;
+use rustsbi::{print, println};
+
+// addi a0, zero, 0
+const LI_A0_ZERO: u32 = 0x0000_0513;
+// jalr zero, 0(ra)
+const RET: u32 = 0x0000_8067;
+
+// 按编号访问CSR的跳板，第一条指令在运行时写入；它出错时由machine_trap恢复到最后的ret
+static mut CSR_TRAMPOLINE: [u32; 3] = [0, LI_A0_ZERO, RET];
+
+// 本次启动的内核命令行，优先于环境变量和启动配置
+static mut BOOTARGS: [u8; LINE_MAX] = [0; LINE_MAX];
+static mut BOOTARGS_LEN: Option<usize> = None;
+
+// 跳板按照调用约定在a0、a1中返回
+#[repr(C)]
+struct CsrResult {
+    error: usize,
+    value: usize,
+}
+
+// 0号核在加载下一阶段程序之后、生成设备树之前调用
+pub fn run() {
+    if config::MONITOR_WAIT_MS == 0 {
+        return;
+    }
+    println!(
+        "[rustsbi] press any key in {} ms to enter the boot monitor",
+        config::MONITOR_WAIT_MS
+    );
+    if !matches!(
+        Uarths.read_byte(config::MONITOR_WAIT_MS as u32),
+        Ok(Some(_))
+    ) {
+        return;
+    }
+    println!("[rustsbi] boot monitor, type `help` for commands");
+    let mut editor = LineEditor::new();
+    loop {
+        print!("monitor> ");
+        read_line(&mut editor);
+        match monitor::parse(editor.line()) {
+            Ok(Some(Command::Boot)) => break,
+            Ok(Some(command)) => execute(command),
+            Ok(None) => {}
+            Err(e) => println!("error: {:?}", e),
+        }
+        editor.clear();
+    }
+}
+
+// 监视器设置的内核命令行
+pub fn bootargs() -> Option<&'static str> {
+    let len = unsafe { BOOTARGS_LEN }?;
+    let bootargs = unsafe { &(*addr_of!(BOOTARGS))[..len] };
+    core::str::from_utf8(bootargs).ok()
+}
+
+// 跳板的第一条指令和出错后继续执行的位置，登记在machine_trap中
+pub fn csr_recoverable() -> (usize, usize) {
+    let start = addr_of!(CSR_TRAMPOLINE) as usize;
+    (start, start + 8)
+}
+
+fn read_line(editor: &mut LineEditor) {
+    loop {
+        let Ok(Some(byte)) = Uarths.read_byte(u32::MAX) else {
+            continue;
+        };
+        match editor.feed(byte) {
+            Edit::Echo(byte) => print!("{}", byte as char),
+            Edit::Erase => print!("\x08 \x08"),
+            Edit::Enter => {
+                println!("");
+                return;
+            }
+            Edit::Ignore => {}
+        }
+    }
+}
+
+fn execute(command: Command) {
+    match command {
+        Command::Help => println!("{}", monitor::HELP),
+        Command::Dump { address, len } => dump(address, len),
+        Command::Write { address, value } => {
+            if address % 4 != 0 {
+                println!("error: {:#x} is not 4-byte aligned", address);
+            } else if unsafe { machine_trap::store_u32(address, value) } == ACCESS_FAULT {
+                println!("error: cannot write {:#x}", address);
+            }
+        }
+        Command::Csr { number: None, .. } => {
+            hart_csr_utils::print_hart_csrs();
+            for &(name, number) in CSR_NAMES {
+                match access_csr(number, None) {
+                    Some(value) => println!("{:>10} ({:#05x}) = {:#018x}", name, number, value),
+                    None => println!("{:>10} ({:#05x}) not available", name, number),
+                }
+            }
+        }
+        Command::Csr {
+            number: Some(number),
+            value,
+        } => match access_csr(number, value) {
+            Some(old) => println!("csr {:#05x} = {:#018x}", number, old),
+            None => println!("error: cannot access csr {:#05x}", number),
+        },
+        Command::Traps { hart: Some(id) } => match hart::hart(id) {
+            Some(state) => state.history.dump(id),
+            None => println!("error: no hart {}", id),
+        },
+        Command::Traps { hart: None } => {
+            for id in 0..hart::NUM_HARTS {
+                hart::hart(id).unwrap().history.dump(id);
+            }
+        }
+        Command::Address(address) => {
+            if handoff::load_range_valid(address, 0) {
+                handoff::set_next_address(address);
+            } else {
+                println!("error: {:#x} is outside of the payload memory", address);
+            }
+        }
+        #[cfg(feature = "trace")]
+        Command::TraceMask(mask) => {
+            if crate::trace::set_mask(mask, crate::trace::OUTPUT_CONSOLE).is_none() {
+                println!("error: invalid trace mask {:#x}", mask);
+            }
+        }
+        #[cfg(not(feature = "trace"))]
+        Command::TraceMask(_) => println!("error: tracing needs the `trace` feature"),
+        Command::Bootargs(bootargs) => unsafe {
+            (*addr_of_mut!(BOOTARGS))[..bootargs.len()].copy_from_slice(bootargs.as_bytes());
+            BOOTARGS_LEN = (!bootargs.is_empty()).then_some(bootargs.len());
+        },
+        #[cfg(feature = "serial-boot")]
+        Command::Serial => crate::serial_boot::load_payload(true),
+        #[cfg(not(feature = "serial-boot"))]
+        Command::Serial => println!("error: serial download needs the `serial-boot` feature"),
+        Command::Boot => {}
+    }
+}
+
+// 每行16字节，后面是可打印的字符
+fn dump(address: usize, len: usize) {
+    for line in (0..len).step_by(16) {
+        let start = address.wrapping_add(line);
+        print!("{:#010x}:", start);
+        let mut text = [b' '; 16];
+        for (i, c) in text.iter_mut().enumerate().take(len - line) {
+            let byte = unsafe { machine_trap::load_u8(start.wrapping_add(i)) };
+            if byte == ACCESS_FAULT {
+                println!("");
+                println!("error: cannot read {:#x}", start.wrapping_add(i));
+                return;
+            }
+            print!(" {:02x}", byte);
+            *c = match byte as u8 {
+                c @ 0x20..=0x7e => c,
+                _ => b'.',
+            };
+        }
+        println!("  {}", core::str::from_utf8(&text).unwrap());
+    }
+}
+
+// 读取CSR，或者写入value并返回原来的值；CSR不存在时返回None
+fn access_csr(number: u16, value: Option<usize>) -> Option<usize> {
+    let instruction = monitor::csr_instruction(number, value.is_some());
+    let result = unsafe {
+        let trampoline = addr_of_mut!(CSR_TRAMPOLINE);
+        (*trampoline)[0] = instruction;
+        core::arch::asm!("fence.i");
+        let f: extern "C" fn(usize, usize) -> CsrResult = core::mem::transmute(trampoline);
+        f(0, value.unwrap_or(0))
+    };
+    (result.error != ACCESS_FAULT).then_some(result.value)
+}
@@ -1,7 +1,11 @@
+use crate::hart::{self, PENDING_FENCE_I, PENDING_SFENCE_VMA, PENDING_SUPERVISOR_SOFT};
 use k210_hal::{clint::msip, clock::Clocks, fpioa, pac, prelude::*};
 use riscv::register::{mhartid, mip};
 use rustsbi::println;
 
+// 环境变量中没有设置时的波特率，`cargo xtask send`也使用这个波特率
+const DEFAULT_BAUDRATE: u32 = 115_200;
+
 pub fn init_peripheral() {
     let p = pac::Peripherals::take().unwrap();
 
@@ -11,13 +15,35 @@ pub fn init_peripheral() {
     let _uarths_tx = fpioa.io5.into_function(fpioa::UARTHS_TX);
     let _uarths_rx = fpioa.io4.into_function(fpioa::UARTHS_RX);
     // Configure UART
-    let serial = p.UARTHS.configure(115_200.bps(), &clocks);
+    #[cfg(feature = "env")]
+    let baudrate = crate::env::baudrate().unwrap_or(DEFAULT_BAUDRATE);
+    #[cfg(not(feature = "env"))]
+    let baudrate = DEFAULT_BAUDRATE;
+    let serial = p.UARTHS.configure(baudrate.bps(), &clocks);
     let (tx, rx) = serial.split();
 
     rustsbi::legacy_stdio::init_legacy_stdio_embedded_hal_fuse(tx, rx);
     rustsbi::init_timer(Timer);
     rustsbi::init_reset(Reset);
     rustsbi::init_ipi(Ipi);
+    rustsbi::init_rfence(Rfence);
+}
+
+// CLINT的mtime以CPU频率的1/50计数
+pub fn timebase_frequency() -> u32 {
+    Clocks::new().cpu().0 / 50
+}
+
+// 把请求记录在目标核的状态块中，再用核间中断通知它；目标核在机器态软件中断中完成请求
+fn send_request(hart_mask: rustsbi::HartMask, pending: usize) {
+    for i in 0..hart::NUM_HARTS {
+        if hart_mask.has_bit(i) {
+            if let Some(target) = hart::hart(i) {
+                target.request(pending);
+                msip::set_ipi(i);
+            }
+        }
+    }
 }
 
 struct Ipi;
@@ -27,12 +53,36 @@ impl rustsbi::Ipi for Ipi {
         1
     }
     fn send_ipi_many(&self, hart_mask: rustsbi::HartMask) -> rustsbi::SbiRet {
-        for i in 0..=1 {
-            if hart_mask.has_bit(i) {
-                msip::set_ipi(i);
-                msip::clear_ipi(i);
-            }
-        }
+        send_request(hart_mask, PENDING_SUPERVISOR_SOFT);
+        rustsbi::SbiRet::ok(0)
+    }
+}
+
+struct Rfence;
+
+// K210不支持ASID，也没有按地址刷新的sfence.vm，所有请求都刷新整个地址翻译缓存
+impl rustsbi::Rfence for Rfence {
+    fn remote_fence_i(&self, hart_mask: rustsbi::HartMask) -> rustsbi::SbiRet {
+        send_request(hart_mask, PENDING_FENCE_I);
+        rustsbi::SbiRet::ok(0)
+    }
+    fn remote_sfence_vma(
+        &self,
+        hart_mask: rustsbi::HartMask,
+        _start_addr: usize,
+        _size: usize,
+    ) -> rustsbi::SbiRet {
+        send_request(hart_mask, PENDING_SFENCE_VMA);
+        rustsbi::SbiRet::ok(0)
+    }
+    fn remote_sfence_vma_asid(
+        &self,
+        hart_mask: rustsbi::HartMask,
+        _start_addr: usize,
+        _size: usize,
+        _asid: usize,
+    ) -> rustsbi::SbiRet {
+        send_request(hart_mask, PENDING_SFENCE_VMA);
         rustsbi::SbiRet::ok(0)
     }
 }
@@ -53,6 +103,24 @@ pub struct Reset;
 impl rustsbi::Reset for Reset {
     fn system_reset(&self, reset_type: usize, reset_reason: usize) -> rustsbi::SbiRet {
         println!("[rustsbi] reset triggered! todo: shutdown all harts on k210; program halt. Type: {}, reason: {}", reset_type, reset_reason);
+        for hart_id in 0..hart::NUM_HARTS {
+            if let Some(hart) = hart::hart(hart_id) {
+                hart.history.dump(hart_id);
+                hart.stats.dump(hart_id);
+            }
+        }
+        // 正常关机时停止看门狗，否则它会把芯片复位；重启由看门狗完成。
+        // 因为系统故障关机时改为重启，让A/B启动有机会回退到另一个槽位
+        #[cfg(feature = "watchdog")]
+        match reset_type {
+            rustsbi::reset::RESET_TYPE_COLD_REBOOT | rustsbi::reset::RESET_TYPE_WARM_REBOOT => {
+                crate::watchdog::reboot()
+            }
+            _ if reset_reason == rustsbi::reset::RESET_REASON_SYSTEM_FAILURE => {
+                crate::watchdog::reboot()
+            }
+            _ => crate::watchdog::disable(),
+        }
         loop {}
     }
 }
@@ -1,47 +1,71 @@
+use crate::hart::{self, HartState};
+use crate::machine_trap;
+use crate::stack;
+use crate::stats::{Counter, HartStats};
+use crate::trap_history::TrapRecord;
 use core::arch::asm;
+use core::mem::{offset_of, size_of};
 use riscv::register::{
     mcause::{self, Exception, Interrupt, Trap},
+    mscratch,
     mstatus::{self, Mstatus, MPP},
     mtval,
     mtvec::{self, TrapMode},
 };
 
-pub fn init() {
+pub fn init(hartid: usize) {
     let mut addr = from_supervisor_save as usize;
     if addr & 0x2 != 0 {
         addr += 0x2; // 必须对齐到4个字节
     }
     unsafe { mtvec::write(addr, TrapMode::Direct) };
+    // 在第一次进入特权级之前，mscratch就指向当前核的状态块
+    let hart = hart::init(hartid);
+    mscratch::write(hart as *mut HartState as usize);
 }
 
+// 运行时本身不保存状态，所有状态都放在当前核的状态块中
 pub struct Runtime {
-    context: SupervisorContext,
+    hart: *mut HartState,
 }
 
 impl Runtime {
-    pub fn new_sbi_supervisor(supervisor_mepc: usize, a0: usize, a1: usize) -> Self {
-        let context: SupervisorContext = unsafe { core::mem::MaybeUninit::zeroed().assume_init() };
-        let mut ans = Runtime { context };
+    // mode是下一阶段程序的特权级，args依次放入a0到a2
+    pub fn new_sbi_supervisor(supervisor_mepc: usize, mode: MPP, args: [usize; 3]) -> Self {
+        let mut ans = Runtime {
+            hart: hart::this_hart(),
+        };
+        let hart = ans.hart();
+        hart.context = unsafe { core::mem::MaybeUninit::zeroed().assume_init() };
+        #[cfg(feature = "float-context")]
+        hart.float_state = crate::float::FloatState::NotSaved;
         ans.prepare_supervisor(supervisor_mepc);
-        ans.context.a0 = a0;
-        ans.context.a1 = a1;
+        unsafe { mstatus::set_mpp(mode) };
+        let ctx = ans.context_mut();
+        ctx.mstatus = mstatus::read();
+        [ctx.a0, ctx.a1, ctx.a2] = args;
         ans
     }
 
+    fn hart(&mut self) -> &mut HartState {
+        unsafe { &mut *self.hart }
+    }
+
     fn reset(&mut self) {
         unsafe { mstatus::set_mpp(MPP::Supervisor) };
-        self.context.mstatus = mstatus::read();
-        self.context.machine_stack = 0x2333333366666666; // 将会被resume函数覆盖
+        let context = self.context_mut();
+        context.mstatus = mstatus::read();
+        context.machine_stack = 0x2333333366666666; // 将会被resume函数覆盖
     }
 
     // 在处理异常的时候，使用context_mut得到运行时当前用户的上下文，可以改变上下文的内容
     pub fn context_mut(&mut self) -> &mut SupervisorContext {
-        &mut self.context
+        &mut self.hart().context
     }
 
     pub fn prepare_supervisor(&mut self, new_mepc: usize) {
         self.reset();
-        self.context.mepc = new_mepc;
+        self.context_mut().mepc = new_mepc;
     }
 }
 
@@ -49,11 +73,26 @@ impl Iterator for Runtime {
     type Item = MachineTrap;
 
     fn next(&mut self) -> Option<Self::Item> {
-        unsafe { do_resume(&mut self.context as *mut _) };
+        let hart = self.hart();
+        #[cfg(feature = "float-context")]
+        hart.restore_float();
+        stack::check_canary(hart.hart_id);
+        hart.stats.trap_exit();
+        hart.handling_trap = false;
+        unsafe { do_resume(&mut hart.context as *mut _) };
+        hart.handling_trap = true;
+        hart.stats.trap_enter();
         let mtval = mtval::read();
+        hart.history.push(TrapRecord::new(
+            mcause::read().bits(),
+            hart.context.mepc,
+            mtval,
+        ));
         let trap = match mcause::read().cause() {
             Trap::Exception(Exception::SupervisorEnvCall) => MachineTrap::SbiCall(),
             Trap::Exception(Exception::IllegalInstruction) => MachineTrap::IllegalInstruction(),
+            // 只有不委托断点异常时才会出现，见gdb_stub
+            Trap::Exception(Exception::Breakpoint) => MachineTrap::Breakpoint(),
             Trap::Exception(Exception::InstructionFault) => MachineTrap::InstructionFault(mtval),
             Trap::Exception(Exception::LoadFault) => MachineTrap::LoadFault(mtval),
             Trap::Exception(Exception::StoreFault) => MachineTrap::StoreFault(mtval),
@@ -62,7 +101,9 @@ impl Iterator for Runtime {
             Trap::Interrupt(Interrupt::MachineSoft) => MachineTrap::MachineSoft(),
             e => panic!(
                 "unhandled exception: {:?}! mtval: {:#x?}, ctx: {:#x?}",
-                e, mtval, self.context
+                e,
+                mtval,
+                self.context_mut()
             ),
         };
         Some(trap)
@@ -74,6 +115,7 @@ impl Iterator for Runtime {
 pub enum MachineTrap {
     SbiCall(),
     IllegalInstruction(),
+    Breakpoint(),
     ExternalInterrupt(),
     MachineTimer(),
     MachineSoft(),
@@ -121,6 +163,89 @@ pub struct SupervisorContext {
     pub machine_stack: usize, // 33
 }
 
+// 进入特权级之前保存在机器栈上的被调用者寄存器
+#[allow(dead_code)] // 只在汇编中通过偏移量访问
+#[repr(C, align(16))]
+struct MachineFrame {
+    ra: usize,
+    gp: usize,
+    tp: usize,
+    s0: usize,
+    s1: usize,
+    s2: usize,
+    s3: usize,
+    s4: usize,
+    s5: usize,
+    s6: usize,
+    s7: usize,
+    s8: usize,
+    s9: usize,
+    s10: usize,
+    s11: usize,
+}
+
+// 汇编代码中使用的偏移量都从结构体定义得到，修改结构体时不需要再手动修改汇编
+macro_rules! ctx_offset {
+    ($field:ident) => {
+        offset_of!(SupervisorContext, $field)
+    };
+}
+
+macro_rules! frame_offset {
+    ($field:ident) => {
+        offset_of!(MachineFrame, $field)
+    };
+}
+
+const MACHINE_FRAME_SIZE: usize = size_of::<MachineFrame>();
+
+// x1到x31必须按寄存器编号排在上下文的开头，指令模拟器（例如emulate_rdtime）把它们当作数组访问
+const _: () = {
+    let order = [
+        ctx_offset!(ra),
+        ctx_offset!(sp),
+        ctx_offset!(gp),
+        ctx_offset!(tp),
+        ctx_offset!(t0),
+        ctx_offset!(t1),
+        ctx_offset!(t2),
+        ctx_offset!(s0),
+        ctx_offset!(s1),
+        ctx_offset!(a0),
+        ctx_offset!(a1),
+        ctx_offset!(a2),
+        ctx_offset!(a3),
+        ctx_offset!(a4),
+        ctx_offset!(a5),
+        ctx_offset!(a6),
+        ctx_offset!(a7),
+        ctx_offset!(s2),
+        ctx_offset!(s3),
+        ctx_offset!(s4),
+        ctx_offset!(s5),
+        ctx_offset!(s6),
+        ctx_offset!(s7),
+        ctx_offset!(s8),
+        ctx_offset!(s9),
+        ctx_offset!(s10),
+        ctx_offset!(s11),
+        ctx_offset!(t3),
+        ctx_offset!(t4),
+        ctx_offset!(t5),
+        ctx_offset!(t6),
+    ];
+    let mut i = 0;
+    while i < order.len() {
+        assert!(order[i] == i * size_of::<usize>());
+        i += 1;
+    }
+    assert!(size_of::<Mstatus>() == size_of::<usize>());
+    // 所有偏移量都要放得下ld/sd的12位有符号立即数
+    assert!(size_of::<SupervisorContext>() <= 2048);
+    // 机器栈必须保持16字节对齐
+    assert!(MACHINE_FRAME_SIZE % 16 == 0 && MACHINE_FRAME_SIZE <= 2048);
+};
+
 #[naked]
 #[link_section = ".text"]
 unsafe extern "C" fn do_resume(_supervisor_context: *mut SupervisorContext) {
@@ -131,25 +256,41 @@ unsafe extern "C" fn do_resume(_supervisor_context: *mut SupervisorContext) {
 #[link_section = ".text"]
 unsafe extern "C" fn from_machine_save(_supervisor_context: *mut SupervisorContext) -> ! {
     asm!( // sp:机器栈顶
-        "addi   sp, sp, -15*8", // sp:机器栈顶
+        "addi   sp, sp, -{frame_size}", // sp:机器栈顶
         // 进入函数之前，已经保存了调用者寄存器，应当保存被调用者寄存器
-        "sd     ra, 0*8(sp)
-        sd      gp, 1*8(sp)
-        sd      tp, 2*8(sp)
-        sd      s0, 3*8(sp)
-        sd      s1, 4*8(sp)
-        sd      s2, 5*8(sp)
-        sd      s3, 6*8(sp)
-        sd      s4, 7*8(sp)
-        sd      s5, 8*8(sp)
-        sd      s6, 9*8(sp)
-        sd      s7, 10*8(sp)
-        sd      s8, 11*8(sp)
-        sd      s9, 12*8(sp)
-        sd      s10, 13*8(sp)
-        sd      s11, 14*8(sp)", 
+        "sd     ra, {ra}(sp)
+        sd      gp, {gp}(sp)
+        sd      tp, {tp}(sp)
+        sd      s0, {s0}(sp)
+        sd      s1, {s1}(sp)
+        sd      s2, {s2}(sp)
+        sd      s3, {s3}(sp)
+        sd      s4, {s4}(sp)
+        sd      s5, {s5}(sp)
+        sd      s6, {s6}(sp)
+        sd      s7, {s7}(sp)
+        sd      s8, {s8}(sp)
+        sd      s9, {s9}(sp)
+        sd      s10, {s10}(sp)
+        sd      s11, {s11}(sp)",
         // a0:特权级上下文
         "j      {to_supervisor_restore}",
+        frame_size = const MACHINE_FRAME_SIZE,
+        ra = const frame_offset!(ra),
+        gp = const frame_offset!(gp),
+        tp = const frame_offset!(tp),
+        s0 = const frame_offset!(s0),
+        s1 = const frame_offset!(s1),
+        s2 = const frame_offset!(s2),
+        s3 = const frame_offset!(s3),
+        s4 = const frame_offset!(s4),
+        s5 = const frame_offset!(s5),
+        s6 = const frame_offset!(s6),
+        s7 = const frame_offset!(s7),
+        s8 = const frame_offset!(s8),
+        s9 = const frame_offset!(s9),
+        s10 = const frame_offset!(s10),
+        s11 = const frame_offset!(s11),
         to_supervisor_restore = sym to_supervisor_restore,
         options(noreturn)
     )
@@ -160,51 +301,105 @@ unsafe extern "C" fn from_machine_save(_supervisor_context: *mut SupervisorConte
 pub unsafe extern "C" fn to_supervisor_restore(_supervisor_context: *mut SupervisorContext) -> ! {
     asm!(
         // a0:特权级上下文
-        "sd     sp, 33*8(a0)", // 机器栈顶放进特权级上下文
+        "sd     sp, {machine_stack}(a0)", // 机器栈顶放进特权级上下文
         "csrw   mscratch, a0", // 新mscratch:特权级上下文
         // mscratch:特权级上下文
         "mv     sp, a0", // 新sp:特权级上下文
-        "ld     t0, 31*8(sp)
-        ld      t1, 32*8(sp)
+        "ld     t0, {mstatus}(sp)
+        ld      t1, {mepc}(sp)
         csrw    mstatus, t0
         csrw    mepc, t1",
-        "ld     ra, 0*8(sp)
-        ld      gp, 2*8(sp)
-        ld      tp, 3*8(sp)
-        ld      t0, 4*8(sp)
-        ld      t1, 5*8(sp)
-        ld      t2, 6*8(sp)
-        ld      s0, 7*8(sp)
-        ld      s1, 8*8(sp)
-        ld      a0, 9*8(sp)
-        ld      a1, 10*8(sp)
-        ld      a2, 11*8(sp)
-        ld      a3, 12*8(sp)
-        ld      a4, 13*8(sp)
-        ld      a5, 14*8(sp)
-        ld      a6, 15*8(sp)
-        ld      a7, 16*8(sp)
-        ld      s2, 17*8(sp)
-        ld      s3, 18*8(sp)
-        ld      s4, 19*8(sp)
-        ld      s5, 20*8(sp)
-        ld      s6, 21*8(sp)
-        ld      s7, 22*8(sp)
-        ld      s8, 23*8(sp)
-        ld      s9, 24*8(sp)
-        ld     s10, 25*8(sp)
-        ld     s11, 26*8(sp)
-        ld      t3, 27*8(sp)
-        ld      t4, 28*8(sp)
-        ld      t5, 29*8(sp)
-        ld      t6, 30*8(sp)",
-        "ld     sp, 1*8(sp)", // 新sp:特权级栈
+        "ld     ra, {ra}(sp)
+        ld      gp, {gp}(sp)
+        ld      tp, {tp}(sp)
+        ld      t0, {t0}(sp)
+        ld      t1, {t1}(sp)
+        ld      t2, {t2}(sp)
+        ld      s0, {s0}(sp)
+        ld      s1, {s1}(sp)
+        ld      a0, {a0}(sp)
+        ld      a1, {a1}(sp)
+        ld      a2, {a2}(sp)
+        ld      a3, {a3}(sp)
+        ld      a4, {a4}(sp)
+        ld      a5, {a5}(sp)
+        ld      a6, {a6}(sp)
+        ld      a7, {a7}(sp)
+        ld      s2, {s2}(sp)
+        ld      s3, {s3}(sp)
+        ld      s4, {s4}(sp)
+        ld      s5, {s5}(sp)
+        ld      s6, {s6}(sp)
+        ld      s7, {s7}(sp)
+        ld      s8, {s8}(sp)
+        ld      s9, {s9}(sp)
+        ld     s10, {s10}(sp)
+        ld     s11, {s11}(sp)
+        ld      t3, {t3}(sp)
+        ld      t4, {t4}(sp)
+        ld      t5, {t5}(sp)
+        ld      t6, {t6}(sp)",
+        "ld     sp, {sp}(sp)", // 新sp:特权级栈
         // sp:特权级栈, mscratch:特权级上下文
         "mret",
+        machine_stack = const ctx_offset!(machine_stack),
+        mstatus = const ctx_offset!(mstatus),
+        mepc = const ctx_offset!(mepc),
+        ra = const ctx_offset!(ra),
+        sp = const ctx_offset!(sp),
+        gp = const ctx_offset!(gp),
+        tp = const ctx_offset!(tp),
+        t0 = const ctx_offset!(t0),
+        t1 = const ctx_offset!(t1),
+        t2 = const ctx_offset!(t2),
+        s0 = const ctx_offset!(s0),
+        s1 = const ctx_offset!(s1),
+        a0 = const ctx_offset!(a0),
+        a1 = const ctx_offset!(a1),
+        a2 = const ctx_offset!(a2),
+        a3 = const ctx_offset!(a3),
+        a4 = const ctx_offset!(a4),
+        a5 = const ctx_offset!(a5),
+        a6 = const ctx_offset!(a6),
+        a7 = const ctx_offset!(a7),
+        s2 = const ctx_offset!(s2),
+        s3 = const ctx_offset!(s3),
+        s4 = const ctx_offset!(s4),
+        s5 = const ctx_offset!(s5),
+        s6 = const ctx_offset!(s6),
+        s7 = const ctx_offset!(s7),
+        s8 = const ctx_offset!(s8),
+        s9 = const ctx_offset!(s9),
+        s10 = const ctx_offset!(s10),
+        s11 = const ctx_offset!(s11),
+        t3 = const ctx_offset!(t3),
+        t4 = const ctx_offset!(t4),
+        t5 = const ctx_offset!(t5),
+        t6 = const ctx_offset!(t6),
         options(noreturn)
     )
 }
 
+// 快速路径用`fast-trap`特性打开；跟踪输出需要看到每一次陷入，这时也不使用快速路径
+const FAST_PATH: bool = cfg!(all(feature = "fast-trap", not(feature = "trace")));
+
+// 陷入入口的快速路径直接访问CLINT
+const CLINT_MTIME: usize = 0x0200_BFF8;
+const CLINT_MTIMECMP: usize = 0x0200_4000;
+
+const FAST_RDTIME_COUNTER: usize =
+    offset_of!(HartState, stats) + HartStats::counter_offset(Counter::FastRdtime);
+const FAST_SET_TIMER_COUNTER: usize =
+    offset_of!(HartState, stats) + HartStats::counter_offset(Counter::FastSetTimer);
+
+// 陷入入口用到的状态块字段也必须在ld/sd的立即数范围内
+const _: () = {
+    assert!(offset_of!(HartState, trap_scratch) < 2048);
+    assert!(offset_of!(HartState, hart_id) < 2048);
+    assert!(offset_of!(HartState, sext_entry) < 2048);
+    assert!(FAST_RDTIME_COUNTER < 2048 && FAST_SET_TIMER_COUNTER < 2048);
+};
+
 // 中断开始
 
 #[naked]
@@ -213,44 +408,214 @@ pub unsafe extern "C" fn from_supervisor_save() -> ! {
     asm!( // sp:特权级栈,mscratch:特权级上下文
         ".p2align 2",
         "csrrw  sp, mscratch, sp", // 新mscratch:特权级栈, 新sp:特权级上下文
-        "sd     ra, 0*8(sp)
-        sd      gp, 2*8(sp)
-        sd      tp, 3*8(sp)
-        sd      t0, 4*8(sp)
-        sd      t1, 5*8(sp)
-        sd      t2, 6*8(sp)
-        sd      s0, 7*8(sp)
-        sd      s1, 8*8(sp)
-        sd      a0, 9*8(sp)
-        sd      a1, 10*8(sp)
-        sd      a2, 11*8(sp)
-        sd      a3, 12*8(sp)
-        sd      a4, 13*8(sp)
-        sd      a5, 14*8(sp)
-        sd      a6, 15*8(sp)
-        sd      a7, 16*8(sp)
-        sd      s2, 17*8(sp)
-        sd      s3, 18*8(sp)
-        sd      s4, 19*8(sp)
-        sd      s5, 20*8(sp)
-        sd      s6, 21*8(sp)
-        sd      s7, 22*8(sp)
-        sd      s8, 23*8(sp)
-        sd      s9, 24*8(sp)
-        sd     s10, 25*8(sp)
-        sd     s11, 26*8(sp)
-        sd      t3, 27*8(sp)
-        sd      t4, 28*8(sp)
-        sd      t5, 29*8(sp)
-        sd      t6, 30*8(sp)",
+        // 如果异常来自机器态自身（mstatus.MPP为Machine），不能覆盖特权级上下文，
+        // 转到单独的处理函数。判断时借用状态块中的一个临时位置保存t0
+        "sd     t0, {trap_scratch}(sp)
+        csrr    t0, mstatus
+        srli    t0, t0, 11
+        andi    t0, t0, 3
+        addi    t0, t0, -3
+        bnez    t0, 1f
+        j       {from_machine_trap}
+1:",
+        // 快速路径：rdtime指令和set_timer调用非常频繁，只保存t0~t2就直接处理，不经过完整的
+        // 上下文保存和execute_supervisor，因此不会出现在陷入记录中。只有打开`fast-trap`特性并且
+        // 不打开`trace`特性时才包含快速路径。
+        // t1、t2暂存在特权级上下文对应的位置，完整保存时会被覆盖，不影响慢速路径
+        ".if {fast_path}
+        sd      t1, {t1}(sp)
+        sd      t2, {t2}(sp)
+        csrr    t0, mcause
+        li      t1, 9
+        beq     t0, t1, 20f
+        li      t1, 2
+        bne     t0, t1, 8f",
+        // 非法指令：检查是不是rdtime。读取指令时打开MPRV和MXR，
+        // 刚刚成功取过这条指令，所以这里的读取不会出错。先检查低16位，避免读取压缩指令之后的内存
+        "csrr   t0, mepc
+        li      t1, (1 << 17) | (1 << 19)
+        csrrs   t1, mstatus, t1
+        lhu     t2, 0(t0)
+        csrw    mstatus, t1
+        li      t0, 0xF07F
+        and     t0, t2, t0
+        li      t1, 0x2073
+        bne     t0, t1, 8f
+        csrr    t0, mepc
+        li      t1, (1 << 17) | (1 << 19)
+        csrrs   t1, mstatus, t1
+        lhu     t0, 2(t0)
+        csrw    mstatus, t1
+        li      t1, 0xC010
+        bne     t0, t1, 8f",
+        // 是rdtime：t1 = rd, t0 = mtime。t0~t2和sp保存在别处，直接写到恢复时读取的位置
+        "srli   t1, t2, 7
+        andi    t1, t1, 31
+        li      t0, {clint_mtime}
+        ld      t0, 0(t0)
+        addi    t2, t1, -2
+        beqz    t2, 72f
+        addi    t2, t1, -5
+        beqz    t2, 75f
+        addi    t2, t1, -6
+        beqz    t2, 76f
+        addi    t2, t1, -7
+        beqz    t2, 77f
+        la      t2, 60f
+        slli    t1, t1, 3
+        add     t2, t2, t1
+        jr      t2",
+        // 跳转表，每一项8字节：mv xN, t0; j 7f
+        r"
+        .option push
+        .option norvc
+60:
+        .irp n, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31
+        addi    x\n, t0, 0
+        j       7f
+        .endr
+        .option pop
+        ",
+        "72:    csrw    mscratch, t0
+        j       7f
+75:     sd      t0, {trap_scratch}(sp)
+        j       7f
+76:     sd      t0, {t1}(sp)
+        j       7f
+77:     sd      t0, {t2}(sp)
+7:      ld      t0, {fast_rdtime}(sp)
+        addi    t0, t0, 1
+        sd      t0, {fast_rdtime}(sp)
+        j       10f",
+        // 特权级环境调用：旧版set_timer（a7 = 0）或TIME扩展的set_timer（a7 = 0x54494D45, a6 = 0）。
+        // 旧版调用需要和preprocess_supervisor_external相同的预处理，并且除a0以外不能改变任何寄存器，
+        // rustsbi的旧版调用原样返回a0和a1；TIME扩展返回a0 = 0（成功）和a1 = 0。
+        // 和慢速路径一样重新打开mie.MTIE：转发机器态时钟中断时关闭了它。
+        // 新的mtimecmp已经过去时，返回特权级后立即产生机器态时钟中断，由慢速路径转发
+        "20:    bnez    a7, 21f
+        csrr    t0, mip
+        andi    t0, t0, 1 << 7
+        beqz    t0, 23f
+        ld      t0, {sext_entry}(sp)
+        beqz    t0, 23f
+        li      t0, 1 << 11
+        csrs    mie, t0
+        j       23f
+21:     li      t0, 0x54494D45
+        bne     a7, t0, 8f
+        bnez    a6, 8f
+23:     ld      t1, {hart_id}(sp)
+        slli    t1, t1, 3
+        li      t0, {clint_mtimecmp}
+        add     t0, t0, t1
+        sd      a0, 0(t0)
+        li      t0, 1 << 5
+        csrc    mip, t0
+        li      t0, 1 << 7
+        csrs    mie, t0
+        ld      t0, {fast_set_timer}(sp)
+        addi    t0, t0, 1
+        sd      t0, {fast_set_timer}(sp)
+        beqz    a7, 10f
+        li      a0, 0
+        li      a1, 0",
+        // 跳过当前指令，恢复t0~t2和特权级栈，返回特权级
+        "10:    csrr    t0, mepc
+        addi    t0, t0, 4
+        csrw    mepc, t0
+        ld      t1, {t1}(sp)
+        ld      t2, {t2}(sp)
+        ld      t0, {trap_scratch}(sp)
+        csrrw   sp, mscratch, sp
+        mret
+        .endif",
+        // 慢速路径：恢复t0~t2，保存完整的上下文
+        "8:
+        .if {fast_path}
+        ld      t1, {t1}(sp)
+        ld      t2, {t2}(sp)
+        .endif
+        ld      t0, {trap_scratch}(sp)",
+        "sd     ra, {ra}(sp)
+        sd      gp, {gp}(sp)
+        sd      tp, {tp}(sp)
+        sd      t0, {t0}(sp)
+        sd      t1, {t1}(sp)
+        sd      t2, {t2}(sp)
+        sd      s0, {s0}(sp)
+        sd      s1, {s1}(sp)
+        sd      a0, {a0}(sp)
+        sd      a1, {a1}(sp)
+        sd      a2, {a2}(sp)
+        sd      a3, {a3}(sp)
+        sd      a4, {a4}(sp)
+        sd      a5, {a5}(sp)
+        sd      a6, {a6}(sp)
+        sd      a7, {a7}(sp)
+        sd      s2, {s2}(sp)
+        sd      s3, {s3}(sp)
+        sd      s4, {s4}(sp)
+        sd      s5, {s5}(sp)
+        sd      s6, {s6}(sp)
+        sd      s7, {s7}(sp)
+        sd      s8, {s8}(sp)
+        sd      s9, {s9}(sp)
+        sd     s10, {s10}(sp)
+        sd     s11, {s11}(sp)
+        sd      t3, {t3}(sp)
+        sd      t4, {t4}(sp)
+        sd      t5, {t5}(sp)
+        sd      t6, {t6}(sp)",
         "csrr   t0, mstatus
-        sd      t0, 31*8(sp)",
+        sd      t0, {mstatus}(sp)",
         "csrr   t1, mepc
-        sd      t1, 32*8(sp)",
+        sd      t1, {mepc}(sp)",
         // mscratch:特权级栈,sp:特权级上下文
         "csrrw  t2, mscratch, sp", // 新mscratch:特权级上下文,t2:特权级栈
-        "sd     t2, 1*8(sp)", // 保存特权级栈
+        "sd     t2, {sp}(sp)", // 保存特权级栈
         "j      {to_machine_restore}",
+        mstatus = const ctx_offset!(mstatus),
+        mepc = const ctx_offset!(mepc),
+        ra = const ctx_offset!(ra),
+        sp = const ctx_offset!(sp),
+        gp = const ctx_offset!(gp),
+        tp = const ctx_offset!(tp),
+        t0 = const ctx_offset!(t0),
+        t1 = const ctx_offset!(t1),
+        t2 = const ctx_offset!(t2),
+        s0 = const ctx_offset!(s0),
+        s1 = const ctx_offset!(s1),
+        a0 = const ctx_offset!(a0),
+        a1 = const ctx_offset!(a1),
+        a2 = const ctx_offset!(a2),
+        a3 = const ctx_offset!(a3),
+        a4 = const ctx_offset!(a4),
+        a5 = const ctx_offset!(a5),
+        a6 = const ctx_offset!(a6),
+        a7 = const ctx_offset!(a7),
+        s2 = const ctx_offset!(s2),
+        s3 = const ctx_offset!(s3),
+        s4 = const ctx_offset!(s4),
+        s5 = const ctx_offset!(s5),
+        s6 = const ctx_offset!(s6),
+        s7 = const ctx_offset!(s7),
+        s8 = const ctx_offset!(s8),
+        s9 = const ctx_offset!(s9),
+        s10 = const ctx_offset!(s10),
+        s11 = const ctx_offset!(s11),
+        t3 = const ctx_offset!(t3),
+        t4 = const ctx_offset!(t4),
+        t5 = const ctx_offset!(t5),
+        t6 = const ctx_offset!(t6),
+        trap_scratch = const offset_of!(HartState, trap_scratch),
+        hart_id = const offset_of!(HartState, hart_id),
+        sext_entry = const offset_of!(HartState, sext_entry),
+        fast_path = const FAST_PATH as usize,
+        fast_rdtime = const FAST_RDTIME_COUNTER,
+        fast_set_timer = const FAST_SET_TIMER_COUNTER,
+        clint_mtime = const CLINT_MTIME,
+        clint_mtimecmp = const CLINT_MTIMECMP,
+        from_machine_trap = sym machine_trap::from_machine_trap,
         to_machine_restore = sym to_machine_restore,
         options(noreturn)
     )
@@ -262,24 +627,41 @@ unsafe extern "C" fn to_machine_restore() -> ! {
     asm!(
         // mscratch:特权级上下文
         "csrr   sp, mscratch", // sp:特权级上下文
-        "ld     sp, 33*8(sp)", // sp:机器栈
-        "ld     ra, 0*8(sp)
-        ld      gp, 1*8(sp)
-        ld      tp, 2*8(sp)
-        ld      s0, 3*8(sp)
-        ld      s1, 4*8(sp)
-        ld      s2, 5*8(sp)
-        ld      s3, 6*8(sp)
-        ld      s4, 7*8(sp)
-        ld      s5, 8*8(sp)
-        ld      s6, 9*8(sp)
-        ld      s7, 10*8(sp)
-        ld      s8, 11*8(sp)
-        ld      s9, 12*8(sp)
-        ld      s10, 13*8(sp)
-        ld      s11, 14*8(sp)",
-        "addi   sp, sp, 15*8", // sp:机器栈顶
+        "ld     sp, {machine_stack}(sp)", // sp:机器栈
+        "ld     ra, {ra}(sp)
+        ld      gp, {gp}(sp)
+        ld      tp, {tp}(sp)
+        ld      s0, {s0}(sp)
+        ld      s1, {s1}(sp)
+        ld      s2, {s2}(sp)
+        ld      s3, {s3}(sp)
+        ld      s4, {s4}(sp)
+        ld      s5, {s5}(sp)
+        ld      s6, {s6}(sp)
+        ld      s7, {s7}(sp)
+        ld      s8, {s8}(sp)
+        ld      s9, {s9}(sp)
+        ld      s10, {s10}(sp)
+        ld      s11, {s11}(sp)",
+        "addi   sp, sp, {frame_size}", // sp:机器栈顶
         "jr     ra",           // 其实就是ret
+        machine_stack = const ctx_offset!(machine_stack),
+        frame_size = const MACHINE_FRAME_SIZE,
+        ra = const frame_offset!(ra),
+        gp = const frame_offset!(gp),
+        tp = const frame_offset!(tp),
+        s0 = const frame_offset!(s0),
+        s1 = const frame_offset!(s1),
+        s2 = const frame_offset!(s2),
+        s3 = const frame_offset!(s3),
+        s4 = const frame_offset!(s4),
+        s5 = const frame_offset!(s5),
+        s6 = const frame_offset!(s6),
+        s7 = const frame_offset!(s7),
+        s8 = const frame_offset!(s8),
+        s9 = const frame_offset!(s9),
+        s10 = const frame_offset!(s10),
+        s11 = const frame_offset!(s11),
         options(noreturn)
     )
 }
@@ -0,0 +1,415 @@
+// 从SD卡加载下一阶段程序，用`sd-boot`特性编译时才会包含。
+//
+// SD卡以SPI模式连接在SPI0上（Sipeed Maix系列开发板的接法），片选由GPIOHS控制。
+// 在卡上第一个FAT32分区中查找启动配置的内核文件，复制到下一阶段程序的入口地址；
+// 同时可以读取设备树文件，代替内嵌的设备树。没有卡、没有文件系统或没有内核文件时照常启动内存中的程序。
+use crate::{handoff, peripheral};
+use core::ptr::{read_volatile, write_volatile};
+use k210_boot::config;
+use k210_boot::fat::{BlockDevice, FatError, Volume, BLOCK_SIZE};
+use k210_hal::clint::mtime;
+use k210_hal::clock::Clocks;
+use rustsbi::println;
+
+// 开发板上SD卡使用的引脚
+const PIN_MISO: usize = 26;
+const PIN_SCLK: usize = 27;
+const PIN_MOSI: usize = 28;
+const PIN_CS: usize = 29;
+const CS_GPIOHS: u32 = 7;
+
+const FPIOA_BASE: usize = 0x502b_0000;
+const FUNC_SPI0_D0: u32 = 4;
+const FUNC_SPI0_D1: u32 = 5;
+const FUNC_SPI0_SCLK: u32 = 17;
+const FUNC_GPIOHS0: u32 = 24;
+// FPIOA的IO配置：驱动能力、输出使能及其取反、输入使能、施密特触发
+const IO_DS_MAX: u32 = 0xf << 8;
+const IO_OE_EN: u32 = 1 << 12;
+const IO_OE_INV: u32 = 1 << 13;
+const IO_IE_EN: u32 = 1 << 20;
+const IO_ST: u32 = 1 << 23;
+
+const GPIOHS_BASE: usize = 0x3800_1000;
+const GPIOHS_INPUT_EN: usize = 0x04;
+const GPIOHS_OUTPUT_EN: usize = 0x08;
+const GPIOHS_OUTPUT_VAL: usize = 0x0c;
+
+const SYSCTL_BASE: usize = 0x5044_0000;
+const SYSCTL_CLK_EN_PERI: usize = 0x2c;
+const CLK_EN_SPI0: u32 = 1 << 6;
+
+const SPI0_BASE: usize = 0x5200_0000;
+const CTRLR0: usize = 0x00;
+const SSIENR: usize = 0x08;
+const SER: usize = 0x10;
+const BAUDR: usize = 0x14;
+const RXFLR: usize = 0x24;
+const IMR: usize = 0x2c;
+const DMACR: usize = 0x4c;
+const DR: usize = 0x60;
+const SPI_CTRLR0: usize = 0xf4;
+
+// SPI0的CTRLR0：帧长度在第16到20位，传输模式在第8、9位，收发模式为0
+const CTRLR0_DFS_8: u32 = 7 << 16;
+
+// 初始化时SD卡的时钟不能超过400kHz，之后使用20MHz以下的时钟
+const INIT_CLOCK: u32 = 400_000;
+const DATA_CLOCK: u32 = 20_000_000;
+
+const CMD_GO_IDLE_STATE: u8 = 0;
+const CMD_SEND_IF_COND: u8 = 8;
+const CMD_SET_BLOCKLEN: u8 = 16;
+const CMD_READ_SINGLE_BLOCK: u8 = 17;
+const CMD_APP_CMD: u8 = 55;
+const CMD_READ_OCR: u8 = 58;
+const ACMD_SD_SEND_OP_COND: u8 = 41;
+const R1_IDLE: u8 = 0x01;
+const R1_ILLEGAL_COMMAND: u8 = 0x04;
+const OCR_CCS: u32 = 1 << 30;
+const TOKEN_START_BLOCK: u8 = 0xfe;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SdError {
+    // 卡没有进入空闲状态，通常是没有插卡
+    NoCard,
+    Unsupported,
+    InitTimeout,
+    Command { cmd: u8, r1: u8 },
+    DataTimeout,
+    DataError(u8),
+}
+
+struct SdCard {
+    // SDHC和SDXC卡按块寻址，SDSC卡按字节寻址
+    block_addressing: bool,
+}
+
+#[repr(C, align(8))]
+struct DeviceTreeBuffer([u8; config::DTB_SIZE_LIMIT]);
+
+// 从SD卡读取的设备树，由设备树模块修改后复制到最终的位置
+static mut DEVICE_TREE: DeviceTreeBuffer = DeviceTreeBuffer([0; config::DTB_SIZE_LIMIT]);
+static mut DEVICE_TREE_SIZE: usize = 0;
+
+// 在0号核进入下一阶段程序之前调用，把SD卡上的内核加载到下一阶段程序的入口地址
+pub fn load_payload() {
+    let card = match SdCard::init() {
+        Ok(card) => card,
+        Err(e) => {
+            println!("[rustsbi] SD card not available: {:?}", e);
+            return;
+        }
+    };
+    let mut volume = match Volume::open(card) {
+        Ok(volume) => volume,
+        Err(e) => {
+            println!("[rustsbi] no FAT32 filesystem on SD card: {:?}", e);
+            return;
+        }
+    };
+    let kernel = match volume.find(config::SD_KERNEL_PATH) {
+        Ok(file) if !file.is_dir => file,
+        Ok(_) | Err(_) => {
+            println!(
+                "[rustsbi] {} not found on SD card, booting the payload in RAM",
+                config::SD_KERNEL_PATH
+            );
+            return;
+        }
+    };
+    let start = handoff::next_stage().address;
+    let size = kernel.size as usize;
+    if !handoff::load_range_valid(start, size) {
+        println!(
+            "[rustsbi] {} of {:#x} bytes does not fit at {:#x}, booting the payload in RAM",
+            config::SD_KERNEL_PATH,
+            size,
+            start
+        );
+        return;
+    }
+    println!(
+        "[rustsbi] loading {} ({:#x} bytes) from SD card to {:#x}",
+        config::SD_KERNEL_PATH,
+        size,
+        start
+    );
+    let payload = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, size) };
+    if let Err(e) = volume.read_file(&kernel, payload) {
+        // 内存中原有的程序可能已经被覆盖，不能再启动
+        println!("[rustsbi] failed to read kernel from SD card: {:?}", e);
+        println!("[rustsbi] system shutdown scheduled due to SD card boot failure");
+        use rustsbi::Reset;
+        peripheral::Reset.system_reset(
+            rustsbi::reset::RESET_TYPE_SHUTDOWN,
+            rustsbi::reset::RESET_REASON_SYSTEM_FAILURE,
+        );
+        loop {}
+    }
+    unsafe { core::arch::asm!("fence.i") };
+    #[cfg(feature = "verified-boot")]
+    handoff::set_signed_image(start, size);
+    load_device_tree(&mut volume);
+}
+
+fn load_device_tree(volume: &mut Volume<SdCard>) {
+    let Ok(file) = volume.find(config::SD_DTB_PATH) else {
+        return;
+    };
+    let buf = unsafe { &mut (*core::ptr::addr_of_mut!(DEVICE_TREE)).0 };
+    match volume.read_file(&file, buf) {
+        Ok(size) => {
+            println!(
+                "[rustsbi] using device tree {} from SD card",
+                config::SD_DTB_PATH
+            );
+            unsafe { DEVICE_TREE_SIZE = size };
+        }
+        Err(FatError::FileTooLarge { size }) => println!(
+            "[rustsbi] ignored {}: {:#x} bytes is larger than {:#x}",
+            config::SD_DTB_PATH,
+            size,
+            config::DTB_SIZE_LIMIT
+        ),
+        Err(e) => println!("[rustsbi] ignored {}: {:?}", config::SD_DTB_PATH, e),
+    }
+}
+
+// 从SD卡读取的设备树，没有时返回None
+pub fn device_tree() -> Option<&'static [u8]> {
+    let size = unsafe { DEVICE_TREE_SIZE };
+    (size != 0).then(|| unsafe { &(*core::ptr::addr_of!(DEVICE_TREE)).0[..size] })
+}
+
+impl SdCard {
+    fn init() -> Result<Self, SdError> {
+        init_pins();
+        spi_init(INIT_CLOCK);
+        // 片选无效时发送至少74个时钟，让卡进入SPI模式
+        set_cs(false);
+        for _ in 0..10 {
+            transfer(0xff);
+        }
+        let r1 = command(CMD_GO_IDLE_STATE, 0);
+        end_command();
+        if r1 != R1_IDLE {
+            return Err(SdError::NoCard);
+        }
+        // 电压范围2.7-3.6V，检查模式0xaa
+        let version2 = match command(CMD_SEND_IF_COND, 0x1aa) {
+            R1_IDLE => {
+                let r7 = read_u32();
+                end_command();
+                if r7 & 0xfff != 0x1aa {
+                    return Err(SdError::Unsupported);
+                }
+                true
+            }
+            r1 if r1 & R1_ILLEGAL_COMMAND != 0 => {
+                end_command();
+                false
+            }
+            r1 => {
+                return Err(SdError::Command {
+                    cmd: CMD_SEND_IF_COND,
+                    r1,
+                })
+            }
+        };
+        let deadline = deadline_ms(1000);
+        loop {
+            command(CMD_APP_CMD, 0);
+            end_command();
+            let r1 = command(ACMD_SD_SEND_OP_COND, if version2 { 1 << 30 } else { 0 });
+            end_command();
+            if r1 == 0 {
+                break;
+            }
+            if r1 != R1_IDLE {
+                return Err(SdError::Command {
+                    cmd: ACMD_SD_SEND_OP_COND,
+                    r1,
+                });
+            }
+            if mtime::read() > deadline {
+                return Err(SdError::InitTimeout);
+            }
+        }
+        let mut block_addressing = false;
+        if version2 {
+            let r1 = command(CMD_READ_OCR, 0);
+            if r1 != 0 {
+                return Err(SdError::Command {
+                    cmd: CMD_READ_OCR,
+                    r1,
+                });
+            }
+            block_addressing = read_u32() & OCR_CCS != 0;
+            end_command();
+        }
+        if !block_addressing {
+            let r1 = command(CMD_SET_BLOCKLEN, BLOCK_SIZE as u32);
+            end_command();
+            if r1 != 0 {
+                return Err(SdError::Command {
+                    cmd: CMD_SET_BLOCKLEN,
+                    r1,
+                });
+            }
+        }
+        spi_init(DATA_CLOCK);
+        Ok(SdCard { block_addressing })
+    }
+}
+
+impl BlockDevice for SdCard {
+    type Error = SdError;
+
+    fn read_block(&mut self, lba: u32, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), SdError> {
+        let address = if self.block_addressing {
+            lba
+        } else {
+            lba * BLOCK_SIZE as u32
+        };
+        let result = read_block(address, buf);
+        end_command();
+        result
+    }
+}
+
+fn read_block(address: u32, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), SdError> {
+    let r1 = command(CMD_READ_SINGLE_BLOCK, address);
+    if r1 != 0 {
+        return Err(SdError::Command {
+            cmd: CMD_READ_SINGLE_BLOCK,
+            r1,
+        });
+    }
+    let deadline = deadline_ms(100);
+    loop {
+        match transfer(0xff) {
+            TOKEN_START_BLOCK => break,
+            0xff if mtime::read() <= deadline => {}
+            0xff => return Err(SdError::DataTimeout),
+            token => return Err(SdError::DataError(token)),
+        }
+    }
+    for byte in buf.iter_mut() {
+        *byte = transfer(0xff);
+    }
+    // 不检查数据的CRC16
+    transfer(0xff);
+    transfer(0xff);
+    Ok(())
+}
+
+// 发送命令并返回R1响应；片选保持有效，调用者读取后续数据以后调用end_command
+fn command(cmd: u8, arg: u32) -> u8 {
+    set_cs(true);
+    transfer(0xff);
+    // SPI模式下只有CMD0和CMD8检查CRC
+    let crc = match cmd {
+        CMD_GO_IDLE_STATE => 0x95,
+        CMD_SEND_IF_COND => 0x87,
+        _ => 0x01,
+    };
+    let [a3, a2, a1, a0] = arg.to_be_bytes();
+    for byte in [0x40 | cmd, a3, a2, a1, a0, crc] {
+        transfer(byte);
+    }
+    let mut r1 = 0xff;
+    for _ in 0..8 {
+        r1 = transfer(0xff);
+        if r1 & 0x80 == 0 {
+            break;
+        }
+    }
+    r1
+}
+
+fn end_command() {
+    set_cs(false);
+    transfer(0xff);
+}
+
+fn read_u32() -> u32 {
+    let mut bytes = [0; 4];
+    for byte in bytes.iter_mut() {
+        *byte = transfer(0xff);
+    }
+    u32::from_be_bytes(bytes)
+}
+
+fn deadline_ms(ms: u64) -> u64 {
+    mtime::read() + peripheral::timebase_frequency() as u64 * ms / 1000
+}
+
+fn init_pins() {
+    let io = |pin: usize, config: u32| unsafe {
+        write_volatile((FPIOA_BASE + pin * 4) as *mut u32, config)
+    };
+    io(PIN_SCLK, FUNC_SPI0_SCLK | IO_DS_MAX | IO_OE_EN);
+    io(
+        PIN_MOSI,
+        FUNC_SPI0_D0 | IO_DS_MAX | IO_OE_EN | IO_OE_INV | IO_IE_EN | IO_ST,
+    );
+    io(
+        PIN_MISO,
+        FUNC_SPI0_D1 | IO_DS_MAX | IO_OE_EN | IO_OE_INV | IO_IE_EN | IO_ST,
+    );
+    io(
+        PIN_CS,
+        (FUNC_GPIOHS0 + CS_GPIOHS) | IO_DS_MAX | IO_OE_EN | IO_OE_INV | IO_IE_EN | IO_ST,
+    );
+    unsafe {
+        modify(GPIOHS_BASE + GPIOHS_INPUT_EN, 1 << CS_GPIOHS, false);
+        modify(GPIOHS_BASE + GPIOHS_OUTPUT_EN, 1 << CS_GPIOHS, true);
+        modify(SYSCTL_BASE + SYSCTL_CLK_EN_PERI, CLK_EN_SPI0, true);
+    }
+}
+
+// 片选低电平有效
+fn set_cs(active: bool) {
+    unsafe { modify(GPIOHS_BASE + GPIOHS_OUTPUT_VAL, 1 << CS_GPIOHS, !active) };
+}
+
+// 默认配置下SPI0的时钟和CPU相同，分频系数必须是偶数
+fn spi_init(clock: u32) {
+    let spi_clock = Clocks::new().cpu().0;
+    let divider = (spi_clock.div_ceil(clock) + 1) & !1;
+    unsafe {
+        spi_write(SSIENR, 0);
+        spi_write(CTRLR0, CTRLR0_DFS_8);
+        spi_write(SPI_CTRLR0, 0);
+        spi_write(BAUDR, divider.clamp(2, 0xfffe));
+        spi_write(IMR, 0);
+        spi_write(DMACR, 0);
+        // 片选由GPIOHS控制，控制器的片选只用来启动传输
+        spi_write(SER, 1);
+        spi_write(SSIENR, 1);
+    }
+}
+
+// 全双工传输一个字节
+fn transfer(byte: u8) -> u8 {
+    unsafe {
+        spi_write(DR, byte as u32);
+        while spi_read(RXFLR) == 0 {}
+        spi_read(DR) as u8
+    }
+}
+
+unsafe fn modify(address: usize, mask: u32, set: bool) {
+    let value = read_volatile(address as *const u32);
+    let value = if set { value | mask } else { value & !mask };
+    write_volatile(address as *mut u32, value)
+}
+
+unsafe fn spi_write(offset: usize, value: u32) {
+    write_volatile((SPI0_BASE + offset) as *mut u32, value)
+}
+
+unsafe fn spi_read(offset: usize) -> u32 {
+    read_volatile((SPI0_BASE + offset) as *const u32)
+}
@@ -0,0 +1,100 @@
+// 串口下载模式，用`serial-boot`特性编译时才会包含。
+//
+// 启动配置打开了串口下载模式，或者启动时按住了按键，就在UARTHS上等待`cargo xtask send`
+// 用YMODEM协议发送内核，接收到下一阶段程序的入口地址后直接启动，不需要重新烧写闪存。
+use crate::uarths::Uarths;
+use crate::{handoff, peripheral};
+use core::ptr::{read_volatile, write_volatile};
+use k210_boot::config;
+use k210_boot::ymodem::{self, YmodemError};
+use k210_hal::clint::mtime;
+use rustsbi::println;
+
+// 没有开始传输时等待的时间，之后照常启动内存中的程序
+const WAIT_SECONDS: u32 = 30;
+
+const FPIOA_BASE: usize = 0x502b_0000;
+const FUNC_GPIOHS0: u32 = 24;
+// 按键使用的GPIOHS，SD卡的片选使用7号
+const KEY_GPIOHS: u32 = 8;
+const IO_PU: u32 = 1 << 16;
+const IO_IE_EN: u32 = 1 << 20;
+const IO_ST: u32 = 1 << 23;
+
+const GPIOHS_BASE: usize = 0x3800_1000;
+const GPIOHS_INPUT_VAL: usize = 0x00;
+const GPIOHS_INPUT_EN: usize = 0x04;
+const GPIOHS_OUTPUT_EN: usize = 0x08;
+
+// 在0号核进入下一阶段程序之前调用，把串口接收的内核放到下一阶段程序的入口地址；
+// force为真时不检查启动配置和按键，由环境变量`bootmode=serial`打开
+pub fn load_payload(force: bool) {
+    if !force && !config::SERIAL_BOOT && !key_pressed() {
+        return;
+    }
+    let start = handoff::next_stage().address;
+    // 内核不能覆盖设备树
+    let end = match config::DTB_ADDRESS {
+        Some(dtb) if dtb >= start => dtb,
+        _ => config::RAM_END,
+    };
+    if !handoff::load_range_valid(start, end - start) {
+        println!("[rustsbi] serial boot: cannot load at {:#x}", start);
+        return;
+    }
+    println!(
+        "[rustsbi] waiting {} seconds for a YMODEM upload to {:#x}, run `cargo xtask send`",
+        WAIT_SECONDS, start
+    );
+    let buf = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, end - start) };
+    match ymodem::receive(&mut Uarths, buf, WAIT_SECONDS) {
+        Ok(file) => {
+            unsafe { core::arch::asm!("fence.i") };
+            #[cfg(feature = "verified-boot")]
+            handoff::set_signed_image(start, file.size);
+            println!(
+                "[rustsbi] received {} ({:#x} bytes) over serial",
+                file.name(),
+                file.size
+            );
+        }
+        // 还没有写入内存，可以启动原来的程序
+        Err(e @ (YmodemError::NotStarted | YmodemError::TooLarge { .. })) => {
+            println!("[rustsbi] serial boot: {:?}, booting the payload in RAM", e);
+        }
+        Err(e) => {
+            println!("[rustsbi] serial boot: {:?}", e);
+            println!("[rustsbi] system shutdown scheduled due to serial boot failure");
+            use rustsbi::Reset;
+            peripheral::Reset.system_reset(
+                rustsbi::reset::RESET_TYPE_SHUTDOWN,
+                rustsbi::reset::RESET_REASON_SYSTEM_FAILURE,
+            );
+            loop {}
+        }
+    }
+}
+
+// 按键接地，引脚上拉，低电平表示按下
+fn key_pressed() -> bool {
+    let pin = FPIOA_BASE + config::SERIAL_BOOT_PIN * 4;
+    let mask = 1 << KEY_GPIOHS;
+    unsafe {
+        write_volatile(
+            pin as *mut u32,
+            (FUNC_GPIOHS0 + KEY_GPIOHS) | IO_PU | IO_IE_EN | IO_ST,
+        );
+        modify(GPIOHS_BASE + GPIOHS_OUTPUT_EN, mask, false);
+        modify(GPIOHS_BASE + GPIOHS_INPUT_EN, mask, true);
+        // 等待上拉生效
+        let deadline = mtime::read() + peripheral::timebase_frequency() as u64 / 1000;
+        while mtime::read() < deadline {}
+        read_volatile((GPIOHS_BASE + GPIOHS_INPUT_VAL) as *const u32) & mask == 0
+    }
+}
+
+unsafe fn modify(address: usize, mask: u32, set: bool) {
+    let value = read_volatile(address as *const u32);
+    let value = if set { value | mask } else { value & !mask };
+    write_volatile(address as *mut u32, value)
+}
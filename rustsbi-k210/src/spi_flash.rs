@@ -0,0 +1,157 @@
+// SPI3上板载闪存的读写，`flash-boot`和`env`特性共用。
+//
+// 只使用标准SPI模式和所有闪存芯片都支持的命令。写入之前要先擦除所在的扇区。
+use core::ptr::{read_volatile, write_volatile};
+
+// SPI3连接板载闪存，芯片启动时固化代码用它加载了RustSBI，时钟和引脚都已经配置好
+const SPI3_BASE: usize = 0x5400_0000;
+const CTRLR0: usize = 0x00;
+const CTRLR1: usize = 0x04;
+const SSIENR: usize = 0x08;
+const SER: usize = 0x10;
+const TXFLR: usize = 0x20;
+const RXFLR: usize = 0x24;
+const SR: usize = 0x28;
+const IMR: usize = 0x2c;
+const DMACR: usize = 0x4c;
+const DR: usize = 0x60;
+const SPI_CTRLR0: usize = 0xf4;
+
+// SPI3的CTRLR0：帧长度在第0到4位，传输模式在第10、11位，帧格式在第22、23位
+const CTRLR0_DFS_8: u32 = 7;
+const CTRLR0_TMOD_TX: u32 = 1 << 10;
+const CTRLR0_TMOD_EEPROM: u32 = 3 << 10;
+const SR_BUSY: u32 = 1 << 0;
+
+const FLASH_READ: u8 = 0x03;
+const FLASH_WRITE_ENABLE: u8 = 0x06;
+const FLASH_READ_STATUS: u8 = 0x05;
+const FLASH_PAGE_PROGRAM: u8 = 0x02;
+// 擦除4KiB的扇区
+const FLASH_SECTOR_ERASE: u8 = 0x20;
+const STATUS_BUSY: u8 = 1 << 0;
+// 每次读取不超过接收缓冲区的深度，不需要担心缓冲区溢出
+const READ_CHUNK: usize = 32;
+// 每次写入的长度，能整除页的大小
+const PROGRAM_CHUNK: usize = 16;
+
+// 用标准SPI模式的读命令从闪存读取数据
+pub fn read(offset: usize, buf: &mut [u8]) {
+    for (i, chunk) in buf.chunks_mut(READ_CHUNK).enumerate() {
+        let address = offset + i * READ_CHUNK;
+        receive(
+            &[
+                FLASH_READ,
+                (address >> 16) as u8,
+                (address >> 8) as u8,
+                address as u8,
+            ],
+            chunk,
+        );
+    }
+}
+
+// 擦除offset所在的扇区，扇区中的数据变为0xff
+pub fn erase_sector(offset: usize) {
+    transmit(&[FLASH_WRITE_ENABLE], &[]);
+    transmit(
+        &[
+            FLASH_SECTOR_ERASE,
+            (offset >> 16) as u8,
+            (offset >> 8) as u8,
+            offset as u8,
+        ],
+        &[],
+    );
+    wait_ready();
+}
+
+// 写入已经擦除的区域；每次写入的数据和命令一起放得进发送缓冲区，也不跨过256字节的页
+pub fn program(offset: usize, data: &[u8]) {
+    let mut done = 0;
+    while done < data.len() {
+        let address = offset + done;
+        let len = (PROGRAM_CHUNK - address % PROGRAM_CHUNK).min(data.len() - done);
+        transmit(&[FLASH_WRITE_ENABLE], &[]);
+        transmit(
+            &[
+                FLASH_PAGE_PROGRAM,
+                (address >> 16) as u8,
+                (address >> 8) as u8,
+                address as u8,
+            ],
+            &data[done..done + len],
+        );
+        wait_ready();
+        done += len;
+    }
+}
+
+fn wait_ready() {
+    let mut status = [0];
+    loop {
+        receive(&[FLASH_READ_STATUS], &mut status);
+        if status[0] & STATUS_BUSY == 0 {
+            break;
+        }
+    }
+}
+
+fn setup(tmod: u32) {
+    unsafe {
+        spi_write(SSIENR, 0);
+        spi_write(CTRLR0, CTRLR0_DFS_8 | tmod);
+        spi_write(SPI_CTRLR0, 0);
+        spi_write(IMR, 0);
+        spi_write(DMACR, 0);
+    }
+}
+
+// 发送命令后接收数据；buf不超过接收缓冲区的深度
+fn receive(command: &[u8], buf: &mut [u8]) {
+    setup(CTRLR0_TMOD_EEPROM);
+    unsafe {
+        spi_write(CTRLR1, buf.len() as u32 - 1);
+        spi_write(SSIENR, 1);
+        for &byte in command {
+            spi_write(DR, byte as u32);
+        }
+        // 写入片选后开始传输
+        spi_write(SER, 1);
+        let mut received = 0;
+        while received < buf.len() {
+            let available = spi_read(RXFLR) as usize;
+            for _ in 0..available.min(buf.len() - received) {
+                buf[received] = spi_read(DR) as u8;
+                received += 1;
+            }
+        }
+        while spi_read(SR) & SR_BUSY != 0 {}
+        spi_write(SER, 0);
+        spi_write(SSIENR, 0);
+    }
+}
+
+// 只发送不接收；命令和数据一起放入发送缓冲区，保证片选在整个传输期间有效
+fn transmit(command: &[u8], data: &[u8]) {
+    setup(CTRLR0_TMOD_TX);
+    unsafe {
+        spi_write(SSIENR, 1);
+        for &byte in command.iter().chain(data) {
+            spi_write(DR, byte as u32);
+        }
+        spi_write(SER, 1);
+        while spi_read(TXFLR) != 0 {}
+        while spi_read(SR) & SR_BUSY != 0 {}
+        spi_write(SER, 0);
+        spi_write(SSIENR, 0);
+    }
+}
+
+unsafe fn spi_write(offset: usize, value: u32) {
+    write_volatile((SPI3_BASE + offset) as *mut u32, value)
+}
+
+unsafe fn spi_read(offset: usize) -> u32 {
+    read_volatile((SPI3_BASE + offset) as *const u32)
+}
@@ -0,0 +1,69 @@
+// SBI栈的溢出检测和最大深度统计。
+//
+// 每个核只有PER_HART_STACK_SIZE大小的栈，核1的栈紧挨在核0的栈上方，溢出会悄悄破坏另一个核的栈。
+// 启动时把栈中未使用的部分填充为STACK_PAINT，并在每个核栈的最低处放置金丝雀值；
+// 每次返回特权级前检查金丝雀，统计最大深度时从栈底向上找第一个被改写的位置。
+use crate::hart::NUM_HARTS;
+use crate::{PER_HART_STACK_SIZE, SBI_STACK};
+use core::arch::asm;
+use core::ptr::{read_volatile, write_volatile};
+
+const STACK_PAINT: usize = 0x5353_4249_5354_4b21; // "SSBISTK!"
+const STACK_CANARY: usize = 0xdead_beef_c0de_cafe;
+const CANARY_WORDS: usize = 4;
+// 填充时跳过当前栈顶下方的一段空间，留给正在执行的函数
+const PAINT_MARGIN: usize = 256;
+
+// 当前核的SBI栈范围[bottom, top)
+pub fn hart_stack_bounds(hart_id: usize) -> (usize, usize) {
+    let bottom = unsafe { SBI_STACK.0.as_ptr() as usize } + hart_id * PER_HART_STACK_SIZE;
+    (bottom, bottom + PER_HART_STACK_SIZE)
+}
+
+// 每个核启动时填充自己的栈
+pub fn paint(hart_id: usize) {
+    let (bottom, _) = hart_stack_bounds(hart_id);
+    let sp: usize;
+    unsafe { asm!("mv {}, sp", out(reg) sp) };
+    let canary = bottom as *mut usize;
+    for i in 0..CANARY_WORDS {
+        unsafe { write_volatile(canary.add(i), STACK_CANARY) };
+    }
+    let mut addr = bottom + CANARY_WORDS * 8;
+    while addr < sp - PAINT_MARGIN {
+        unsafe { write_volatile(addr as *mut usize, STACK_PAINT) };
+        addr += 8;
+    }
+}
+
+pub fn canary_intact(hart_id: usize) -> bool {
+    let (bottom, _) = hart_stack_bounds(hart_id);
+    let canary = bottom as *const usize;
+    (0..CANARY_WORDS).all(|i| unsafe { read_volatile(canary.add(i)) } == STACK_CANARY)
+}
+
+pub fn check_canary(hart_id: usize) {
+    if !canary_intact(hart_id) {
+        panic!(
+            "SBI stack overflow on hart {}, stack limit {:#x}",
+            hart_id,
+            hart_stack_bounds(hart_id).0
+        );
+    }
+}
+
+// 启动以来栈使用的最大深度（字节）；溢出时返回整个栈的大小
+pub fn max_depth(hart_id: usize) -> Option<usize> {
+    if hart_id >= NUM_HARTS {
+        return None;
+    }
+    if !canary_intact(hart_id) {
+        return Some(PER_HART_STACK_SIZE);
+    }
+    let (bottom, top) = hart_stack_bounds(hart_id);
+    let mut addr = bottom + CANARY_WORDS * 8;
+    while addr < top && unsafe { read_volatile(addr as *const usize) } == STACK_PAINT {
+        addr += 8;
+    }
+    Some(top - addr)
+}
@@ -0,0 +1,189 @@
+// 每个核的陷入和模拟统计，用来衡量1.9.1兼容层的开销。
+//
+// 每处理一次陷入，execute_supervisor调用count记录走过的路径；同时按路径所属的类别，
+// 用mcycle统计从陷入到返回特权级之间在机器态花费的周期数。
+// 统计数据通过厂商扩展函数读取，系统复位时打印出来。
+use crate::hart;
+use core::mem::{offset_of, size_of};
+use riscv::register::mcycle;
+use rustsbi::println;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum Counter {
+    Traps = 0,
+    SbiLegacy,
+    SbiBase,
+    SbiTimer,
+    SbiIpi,
+    SbiRfence,
+    SbiHsm,
+    SbiReset,
+    SbiVendor,
+    SbiOther,
+    EmulateRdtime,
+    EmulateSfenceVma,
+    IllegalInstruction,
+    InstructionPageFault,
+    LoadPageFault,
+    StorePageFault,
+    InstructionAccessFault,
+    LoadAccessFault,
+    StoreAccessFault,
+    ExternalInterrupt,
+    TimerInterrupt,
+    SoftInterrupt,
+    // 由陷入入口的快速路径处理，不经过execute_supervisor，也不统计周期数；没有快速路径时总是0
+    FastRdtime,
+    FastSetTimer,
+}
+
+pub const NUM_COUNTERS: usize = Counter::FastSetTimer as usize + 1;
+
+const COUNTER_NAMES: [&str; NUM_COUNTERS] = [
+    "traps",
+    "sbi legacy",
+    "sbi base",
+    "sbi timer",
+    "sbi ipi",
+    "sbi rfence",
+    "sbi hsm",
+    "sbi reset",
+    "sbi vendor",
+    "sbi other",
+    "emulate rdtime",
+    "emulate sfence.vma",
+    "illegal instruction",
+    "instruction page fault",
+    "load page fault",
+    "store page fault",
+    "instruction access fault",
+    "load access fault",
+    "store access fault",
+    "external interrupt",
+    "timer interrupt",
+    "soft interrupt",
+    "fast rdtime",
+    "fast set_timer",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum Category {
+    SbiCall = 0,
+    Emulation,
+    Fault,
+    Interrupt,
+}
+
+pub const NUM_CATEGORIES: usize = Category::Interrupt as usize + 1;
+
+const CATEGORY_NAMES: [&str; NUM_CATEGORIES] = ["sbi call", "emulation", "fault", "interrupt"];
+
+impl Counter {
+    pub fn sbi_extension(extension: usize) -> Counter {
+        match extension {
+            0x00..=0x08 => Counter::SbiLegacy,
+            0x10 => Counter::SbiBase,
+            0x54494D45 => Counter::SbiTimer,
+            0x735049 => Counter::SbiIpi,
+            0x52464E43 => Counter::SbiRfence,
+            0x48534D => Counter::SbiHsm,
+            0x53525354 => Counter::SbiReset,
+            0x0A000004 => Counter::SbiVendor,
+            _ => Counter::SbiOther,
+        }
+    }
+
+    fn category(self) -> Option<Category> {
+        use Counter::*;
+        match self {
+            Traps | FastRdtime | FastSetTimer => None,
+            SbiLegacy | SbiBase | SbiTimer | SbiIpi | SbiRfence | SbiHsm | SbiReset | SbiVendor
+            | SbiOther => Some(Category::SbiCall),
+            EmulateRdtime | EmulateSfenceVma | IllegalInstruction => Some(Category::Emulation),
+            InstructionPageFault
+            | LoadPageFault
+            | StorePageFault
+            | InstructionAccessFault
+            | LoadAccessFault
+            | StoreAccessFault => Some(Category::Fault),
+            ExternalInterrupt | TimerInterrupt | SoftInterrupt => Some(Category::Interrupt),
+        }
+    }
+}
+
+pub struct HartStats {
+    counters: [usize; NUM_COUNTERS],
+    cycles: [usize; NUM_CATEGORIES],
+    // 当前陷入所属的类别和陷入时的mcycle
+    category: Option<Category>,
+    trap_start: usize,
+}
+
+impl HartStats {
+    // 计数器相对HartStats的偏移量，供陷入入口的汇编代码使用
+    pub const fn counter_offset(counter: Counter) -> usize {
+        offset_of!(HartStats, counters) + counter as usize * size_of::<usize>()
+    }
+
+    pub const fn new() -> Self {
+        HartStats {
+            counters: [0; NUM_COUNTERS],
+            cycles: [0; NUM_CATEGORIES],
+            category: None,
+            trap_start: 0,
+        }
+    }
+
+    pub fn trap_enter(&mut self) {
+        self.counters[Counter::Traps as usize] += 1;
+        self.category = None;
+        self.trap_start = mcycle::read();
+    }
+
+    pub fn trap_exit(&mut self) {
+        if let Some(category) = self.category.take() {
+            let elapsed = mcycle::read().wrapping_sub(self.trap_start);
+            self.cycles[category as usize] += elapsed;
+        }
+    }
+
+    pub fn count(&mut self, counter: Counter) {
+        self.counters[counter as usize] += 1;
+        if let Some(category) = counter.category() {
+            self.category = Some(category);
+        }
+    }
+
+    pub fn counter(&self, id: usize) -> Option<usize> {
+        self.counters.get(id).copied()
+    }
+
+    pub fn cycles(&self, id: usize) -> Option<usize> {
+        self.cycles.get(id).copied()
+    }
+
+    pub fn reset(&mut self) {
+        self.counters = [0; NUM_COUNTERS];
+        self.cycles = [0; NUM_CATEGORIES];
+    }
+
+    pub fn dump(&self, hart_id: usize) {
+        println!("[rustsbi] trap statistics (hart {}):", hart_id);
+        for (name, count) in COUNTER_NAMES.iter().zip(self.counters.iter()) {
+            if *count != 0 {
+                println!("[rustsbi]   {:<24} {}", name, count);
+            }
+        }
+        for (name, cycles) in CATEGORY_NAMES.iter().zip(self.cycles.iter()) {
+            println!("[rustsbi]   cycles in {:<15} {}", name, cycles);
+        }
+    }
+}
+
+// 记录当前核的陷入走过的路径
+#[inline]
+pub fn count(counter: Counter) {
+    hart::this_hart().stats.count(counter)
+}
@@ -0,0 +1,107 @@
+// SBI调用、指令模拟和异常转交的跟踪输出，用`trace`特性编译时才会包含。
+//
+// 跟踪掩码决定记录哪些事件。启动时的掩码来自配置`K210_TRACE_MASK`，启动时可以被环境变量
+// `trace_mask`和启动监视器修改，运行时可以通过厂商扩展函数修改。输出可以直接写到串口，也可以写到内存中的环形缓冲区，
+// 以后再通过厂商扩展函数打印出来，避免串口输出拖慢内核。
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use k210_boot::config;
+use rustsbi::print;
+
+pub const TRACE_SBI_CALL: usize = 1 << 0;
+pub const TRACE_EMULATE: usize = 1 << 1;
+pub const TRACE_TRANSFER: usize = 1 << 2;
+pub const TRACE_ALL: usize = TRACE_SBI_CALL | TRACE_EMULATE | TRACE_TRANSFER;
+
+pub const OUTPUT_CONSOLE: usize = 0;
+pub const OUTPUT_MEMORY: usize = 1;
+
+const TRACE_BUFFER_SIZE: usize = 4096;
+
+static TRACE_MASK: AtomicUsize = AtomicUsize::new(0);
+static TRACE_OUTPUT: AtomicUsize = AtomicUsize::new(OUTPUT_CONSOLE);
+
+static BUFFER_LOCK: AtomicBool = AtomicBool::new(false);
+static mut BUFFER: TraceBuffer = TraceBuffer {
+    data: [0; TRACE_BUFFER_SIZE],
+    next: 0,
+    len: 0,
+};
+
+struct TraceBuffer {
+    data: [u8; TRACE_BUFFER_SIZE],
+    next: usize,
+    len: usize,
+}
+
+impl Write for TraceBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            self.data[self.next] = byte;
+            self.next = (self.next + 1) % TRACE_BUFFER_SIZE;
+            if self.len < TRACE_BUFFER_SIZE {
+                self.len += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn init() {
+    TRACE_MASK.store(config::TRACE_MASK & TRACE_ALL, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn enabled(mask: usize) -> bool {
+    TRACE_MASK.load(Ordering::Relaxed) & mask != 0
+}
+
+// 设置跟踪掩码和输出位置，返回原来的掩码
+pub fn set_mask(mask: usize, output: usize) -> Option<usize> {
+    if mask & !TRACE_ALL != 0 || (output != OUTPUT_CONSOLE && output != OUTPUT_MEMORY) {
+        return None;
+    }
+    TRACE_OUTPUT.store(output, Ordering::Relaxed);
+    Some(TRACE_MASK.swap(mask, Ordering::Relaxed))
+}
+
+pub fn write(args: fmt::Arguments) {
+    if TRACE_OUTPUT.load(Ordering::Relaxed) == OUTPUT_MEMORY {
+        with_buffer(|buffer| {
+            let _ = buffer.write_fmt(args);
+            let _ = buffer.write_str("\n");
+        })
+    } else {
+        print!("{}\n", args);
+    }
+}
+
+// 把内存缓冲区中的跟踪记录打印到串口，并清空缓冲区
+pub fn dump_buffer() {
+    with_buffer(|buffer| {
+        let start = (buffer.next + TRACE_BUFFER_SIZE - buffer.len) % TRACE_BUFFER_SIZE;
+        for i in 0..buffer.len {
+            print!("{}", buffer.data[(start + i) % TRACE_BUFFER_SIZE] as char);
+        }
+        buffer.len = 0;
+    })
+}
+
+fn with_buffer(f: impl FnOnce(&mut TraceBuffer)) {
+    while BUFFER_LOCK
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        core::hint::spin_loop();
+    }
+    f(unsafe { &mut *core::ptr::addr_of_mut!(BUFFER) });
+    BUFFER_LOCK.store(false, Ordering::Release);
+}
+
+macro_rules! trace {
+    ($mask:expr, $($arg:tt)*) => {
+        if $crate::trace::enabled($mask) {
+            $crate::trace::write(format_args!($($arg)*))
+        }
+    };
+}
@@ -0,0 +1,132 @@
+// 每个核最近处理过的陷入记录，内核出问题时用来查看SBI在此之前做了什么。
+//
+// execute_supervisor每处理一次陷入就写入一条记录，环形缓冲区写满后覆盖最旧的记录。
+// 在RustSBI panic、系统复位时，以及通过厂商扩展函数可以打印出来。
+use rustsbi::{print, println};
+
+pub const TRAP_HISTORY_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+pub enum TrapOutcome {
+    // 还没有处理完成
+    Pending,
+    // SBI调用的返回值
+    SbiRet { error: usize, value: usize },
+    // 在机器态模拟完成
+    Emulated,
+    // 作为异常转交给特权级
+    Transferred(usize),
+    // 中断转发给特权级，或在机器态处理完成
+    Forwarded,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TrapRecord {
+    pub mcause: usize,
+    pub mepc: usize,
+    pub mtval: usize,
+    // 非法指令异常时读到的指令
+    pub instruction: Option<u32>,
+    // SBI调用时的扩展号和函数号
+    pub sbi_call: Option<(usize, usize)>,
+    pub outcome: TrapOutcome,
+}
+
+impl TrapRecord {
+    pub const fn new(mcause: usize, mepc: usize, mtval: usize) -> Self {
+        TrapRecord {
+            mcause,
+            mepc,
+            mtval,
+            instruction: None,
+            sbi_call: None,
+            outcome: TrapOutcome::Pending,
+        }
+    }
+}
+
+pub struct TrapHistory {
+    records: [TrapRecord; TRAP_HISTORY_LEN],
+    next: usize,
+    len: usize,
+}
+
+impl TrapHistory {
+    pub const fn new() -> Self {
+        TrapHistory {
+            records: [TrapRecord::new(0, 0, 0); TRAP_HISTORY_LEN],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, record: TrapRecord) {
+        self.records[self.next] = record;
+        self.next = (self.next + 1) % TRAP_HISTORY_LEN;
+        if self.len < TRAP_HISTORY_LEN {
+            self.len += 1;
+        }
+    }
+
+    // 最近一条记录，也就是当前正在处理的陷入
+    pub fn last_mut(&mut self) -> Option<&mut TrapRecord> {
+        if self.len == 0 {
+            return None;
+        }
+        let idx = (self.next + TRAP_HISTORY_LEN - 1) % TRAP_HISTORY_LEN;
+        Some(&mut self.records[idx])
+    }
+
+    // 从最旧到最新
+    pub fn iter(&self) -> impl Iterator<Item = &TrapRecord> {
+        let start = (self.next + TRAP_HISTORY_LEN - self.len) % TRAP_HISTORY_LEN;
+        (0..self.len).map(move |i| &self.records[(start + i) % TRAP_HISTORY_LEN])
+    }
+
+    pub fn dump(&self, hart_id: usize) {
+        println!(
+            "[rustsbi] trap history (hart {}), {} record(s), oldest first:",
+            hart_id, self.len
+        );
+        for record in self.iter() {
+            dump_record(record);
+        }
+    }
+}
+
+fn dump_record(record: &TrapRecord) {
+    print!(
+        "[rustsbi]   mcause {:#x} mepc {:#018x} mtval {:#x}",
+        record.mcause, record.mepc, record.mtval
+    );
+    if let Some(ins) = record.instruction {
+        print!(" ins {:#010x} ({})", ins, decode_instruction(ins));
+    }
+    if let Some((extension, function)) = record.sbi_call {
+        print!(" sbi eid {:#x} fid {:#x}", extension, function);
+    }
+    match record.outcome {
+        TrapOutcome::Pending => println!(" -> pending"),
+        TrapOutcome::SbiRet { error, value } => {
+            println!(" -> error {} value {:#x}", error as isize, value)
+        }
+        TrapOutcome::Emulated => println!(" -> emulated"),
+        TrapOutcome::Transferred(scause) => println!(" -> transferred, scause {:#x}", scause),
+        TrapOutcome::Forwarded => println!(" -> forwarded"),
+    }
+}
+
+// 只识别机器态会模拟的指令，其余的按操作码分类
+fn decode_instruction(ins: u32) -> &'static str {
+    if ins & 0xFFFFF07F == 0xC0102073 {
+        "rdtime"
+    } else if ins & 0xFE007FFF == 0x12000073 {
+        "sfence.vma"
+    } else if ins & 0x7F == 0x73 {
+        "system"
+    } else if ins & 0b11 != 0b11 {
+        "compressed"
+    } else {
+        "unknown"
+    }
+}
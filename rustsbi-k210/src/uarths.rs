@@ -0,0 +1,46 @@
+// 直接读写UARTHS的寄存器，`serial-boot`、`monitor`和`gdb-stub`特性共用。
+//
+// 和rustsbi的控制台使用同一个串口，但不经过它的缓冲，可以带超时地等待输入。
+use crate::peripheral;
+use core::convert::Infallible;
+use core::ptr::{read_volatile, write_volatile};
+use k210_boot::ymodem::Port;
+use k210_hal::clint::mtime;
+
+const UARTHS_BASE: usize = 0x3800_0000;
+const UARTHS_TXDATA: usize = 0x00;
+const UARTHS_RXDATA: usize = 0x04;
+// 发送队列满，或接收队列空
+const UARTHS_FIFO_FLAG: u32 = 1 << 31;
+
+// YMODEM传输期间不能再用println输出
+pub struct Uarths;
+
+impl Port for Uarths {
+    type Error = Infallible;
+
+    fn read_byte(&mut self, timeout_ms: u32) -> Result<Option<u8>, Infallible> {
+        let ticks = peripheral::timebase_frequency() as u64 * timeout_ms as u64 / 1000;
+        let deadline = mtime::read() + ticks;
+        loop {
+            let data = unsafe { read_volatile((UARTHS_BASE + UARTHS_RXDATA) as *const u32) };
+            if data & UARTHS_FIFO_FLAG == 0 {
+                return Ok(Some(data as u8));
+            }
+            if mtime::read() >= deadline {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), Infallible> {
+        let txdata = (UARTHS_BASE + UARTHS_TXDATA) as *mut u32;
+        for &byte in bytes {
+            unsafe {
+                while read_volatile(txdata) & UARTHS_FIFO_FLAG != 0 {}
+                write_volatile(txdata, byte as u32);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+// 解压LZ4压缩的下一阶段程序（格式见k210_boot::lz4）。
+//
+// 解压后的程序从加载地址开始，压缩数据暂存在它的后面，两者不会重叠。
+// 合并镜像中的压缩数据位于加载地址，先整体移动到暂存区再解压；
+// 闪存中的压缩数据直接读到暂存区。
+use crate::{handoff, peripheral};
+use k210_boot::config;
+use k210_boot::lz4;
+use rustsbi::println;
+
+/// 帧描述符的最大长度，不包括预设字典编号
+pub const FRAME_HEADER_SIZE: usize = 15;
+
+/// 解压的安排：解压后的长度和压缩数据的暂存地址
+#[derive(Clone, Copy)]
+pub struct Plan {
+    pub size: usize,
+    pub staging: usize,
+}
+
+/// 根据帧描述符安排解压到start的程序；放不下时打印原因并返回None
+pub fn plan(start: usize, header: &[u8], compressed_size: usize) -> Option<Plan> {
+    let info = match lz4::frame_info(header) {
+        Ok(info) => info,
+        Err(e) => {
+            println!("[rustsbi] bad LZ4 frame header: {:?}", e);
+            return None;
+        }
+    };
+    let Some(size) = info.content_size else {
+        println!("[rustsbi] LZ4 frame does not record the content size");
+        return None;
+    };
+    let size = size as usize;
+    let staging = start.checked_add(size)?.checked_add(7)? & !7;
+    let end = staging.checked_add(compressed_size)?;
+    if !handoff::load_range_valid(start, end - start) {
+        println!(
+            "[rustsbi] payload of {:#x} bytes unpacked at {:#x} does not fit in SRAM",
+            size, start
+        );
+        return None;
+    }
+    Some(Plan { size, staging })
+}
+
+/// 把暂存区中的压缩数据解压到start
+pub fn unpack(start: usize, plan: Plan, compressed_size: usize) -> Result<(), lz4::Lz4Error> {
+    let src = unsafe { core::slice::from_raw_parts(plan.staging as *const u8, compressed_size) };
+    let dst = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, plan.size) };
+    lz4::decompress(src, dst)?;
+    unsafe { core::arch::asm!("fence.i") };
+    Ok(())
+}
+
+// 0号核读取交接区之后调用；合并镜像中的程序没有压缩时什么也不做。
+// 复位后交接区可能是随机值，加载地址处还必须是LZ4帧
+pub fn unpack_fused_payload() {
+    let entry = config::HANDOFF_ADDRESS + config::HANDOFF_COMPRESSED_SIZE_OFFSET;
+    let compressed_size = unsafe { core::ptr::read_volatile(entry as *const u64) } as usize;
+    let start = config::PAYLOAD_ADDRESS;
+    let magic = unsafe { core::ptr::read_volatile(start as *const u32) };
+    if compressed_size == 0 || u32::from_le(magic) != lz4::LZ4_FRAME_MAGIC {
+        return;
+    }
+    if compressed_size > config::RAM_END - start {
+        fail(format_args!(
+            "compressed payload of {:#x} bytes is larger than SRAM",
+            compressed_size
+        ));
+    }
+    let header = unsafe {
+        core::slice::from_raw_parts(start as *const u8, compressed_size.min(FRAME_HEADER_SIZE))
+    };
+    let Some(plan) = plan(start, header, compressed_size) else {
+        fail(format_args!("cannot unpack the payload at {:#x}", start));
+    };
+    // 暂存区在压缩数据之后，从后往前复制
+    unsafe { core::ptr::copy(start as *const u8, plan.staging as *mut u8, compressed_size) };
+    if let Err(e) = unpack(start, plan, compressed_size) {
+        fail(format_args!("{:?}", e));
+    }
+    println!(
+        "[rustsbi] unpacked {:#x} bytes of payload from {:#x} bytes",
+        plan.size, compressed_size
+    );
+}
+
+fn fail(args: core::fmt::Arguments) -> ! {
+    println!("[rustsbi] unpack payload: {}", args);
+    println!("[rustsbi] system shutdown scheduled due to payload unpack failure");
+    use rustsbi::Reset;
+    peripheral::Reset.system_reset(
+        rustsbi::reset::RESET_TYPE_SHUTDOWN,
+        rustsbi::reset::RESET_REASON_SYSTEM_FAILURE,
+    );
+    loop {}
+}
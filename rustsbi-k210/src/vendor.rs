@@ -0,0 +1,216 @@
+// RustSBI-K210的厂商扩展（extension id: 0x0A000004）。
+//
+// 函数0x210（注册S态外部中断处理函数）见feature::supervisor_interrupt，其余函数在这里处理。
+// 没有列出的函数交给rustsbi，返回SBI_ERR_NOT_SUPPORTED。
+use crate::hart;
+use crate::runtime::SupervisorContext;
+use crate::stack;
+use crate::stats::HartStats;
+#[cfg(feature = "trace")]
+use crate::trace;
+#[cfg(feature = "env")]
+use k210_boot::{config, env::EnvError};
+
+pub const EXTENSION_RUSTSBI_K210: usize = 0x0A000004;
+
+// a0: hart id; 返回值: 这个核的SBI栈启动以来使用的最大深度（字节）
+const FUNCTION_GET_STACK_USAGE: usize = 0x211;
+// a0: hart id; 在控制台上打印这个核最近处理过的陷入记录
+const FUNCTION_DUMP_TRAP_HISTORY: usize = 0x212;
+// a0: 跟踪掩码, a1: 输出位置（0: 串口, 1: 内存缓冲区）; 返回值: 原来的跟踪掩码。
+// 只有用`trace`特性编译时才支持
+#[cfg(feature = "trace")]
+const FUNCTION_SET_TRACE_MASK: usize = 0x213;
+// 把内存缓冲区中的跟踪记录打印到串口，并清空缓冲区
+#[cfg(feature = "trace")]
+const FUNCTION_DUMP_TRACE_BUFFER: usize = 0x214;
+// a0: hart id, a1: 计数器编号（见stats::Counter）; 返回值: 计数
+const FUNCTION_GET_STATISTIC: usize = 0x215;
+// a0: hart id, a1: 类别编号（见stats::Category）; 返回值: 这一类陷入在机器态花费的周期数
+const FUNCTION_GET_CYCLES: usize = 0x216;
+// a0: hart id; 清零这个核的统计数据
+const FUNCTION_RESET_STATISTICS: usize = 0x217;
+// 确认当前闪存槽位启动成功，之后的启动不再消耗尝试次数; 返回值: 当前槽位（0为A，1为B）。
+// 只有用`flash-boot`特性编译、并且闪存中有启动控制记录时才支持
+#[cfg(feature = "flash-boot")]
+const FUNCTION_MARK_BOOT_SUCCESSFUL: usize = 0x218;
+// a0, a1: 变量名的物理地址和长度, a2, a3: 缓冲区的物理地址和长度; 返回值: 值的完整长度，
+// 缓冲区不够时只复制前一部分。只有用`env`特性编译时才支持
+#[cfg(feature = "env")]
+const FUNCTION_ENV_GET: usize = 0x219;
+// a0, a1: 变量名的物理地址和长度, a2, a3: 值的物理地址和长度，长度为0时删除变量。
+// 修改后立即写回闪存，下次启动时生效
+#[cfg(feature = "env")]
+const FUNCTION_ENV_SET: usize = 0x21A;
+// a0: 超时毫秒数; 启动看门狗或修改超时，超时后芯片复位; 返回值: 实际的超时毫秒数，
+// 看门狗只能按2的幂计时，会向上取整。只有用`watchdog`特性编译时才支持
+#[cfg(feature = "watchdog")]
+const FUNCTION_WATCHDOG_ARM: usize = 0x21B;
+// 喂狗，重新开始计时
+#[cfg(feature = "watchdog")]
+const FUNCTION_WATCHDOG_KICK: usize = 0x21C;
+// 停止看门狗
+#[cfg(feature = "watchdog")]
+const FUNCTION_WATCHDOG_DISABLE: usize = 0x21D;
+
+// 变量名的最大长度
+#[cfg(feature = "env")]
+const ENV_NAME_MAX: usize = 64;
+
+const SBI_SUCCESS: usize = 0;
+#[cfg(feature = "env")]
+const SBI_ERR_FAILED: usize = -1isize as usize;
+#[cfg(feature = "flash-boot")]
+const SBI_ERR_NOT_SUPPORTED: usize = -2isize as usize;
+const SBI_ERR_INVALID_PARAM: usize = -3isize as usize;
+#[cfg(feature = "env")]
+const SBI_ERR_INVALID_ADDRESS: usize = -5isize as usize;
+
+pub fn emulate_sbi_rustsbi_k210_vendor(ctx: &mut SupervisorContext) -> bool {
+    if ctx.a7 != EXTENSION_RUSTSBI_K210 {
+        return false;
+    }
+    let (error, value) = match ctx.a6 {
+        FUNCTION_GET_STACK_USAGE => get_stack_usage(ctx.a0),
+        FUNCTION_DUMP_TRAP_HISTORY => dump_trap_history(ctx.a0),
+        #[cfg(feature = "trace")]
+        FUNCTION_SET_TRACE_MASK => match trace::set_mask(ctx.a0, ctx.a1) {
+            Some(previous) => (SBI_SUCCESS, previous),
+            None => (SBI_ERR_INVALID_PARAM, 0),
+        },
+        #[cfg(feature = "trace")]
+        FUNCTION_DUMP_TRACE_BUFFER => {
+            trace::dump_buffer();
+            (SBI_SUCCESS, 0)
+        }
+        FUNCTION_GET_STATISTIC => get_statistic(ctx.a0, |stats| stats.counter(ctx.a1)),
+        FUNCTION_GET_CYCLES => get_statistic(ctx.a0, |stats| stats.cycles(ctx.a1)),
+        FUNCTION_RESET_STATISTICS => reset_statistics(ctx.a0),
+        #[cfg(feature = "flash-boot")]
+        FUNCTION_MARK_BOOT_SUCCESSFUL => match crate::flash::mark_boot_successful() {
+            Some(slot) => (SBI_SUCCESS, slot.index()),
+            None => (SBI_ERR_NOT_SUPPORTED, 0),
+        },
+        #[cfg(feature = "env")]
+        FUNCTION_ENV_GET => env_get(ctx.a0, ctx.a1, ctx.a2, ctx.a3),
+        #[cfg(feature = "env")]
+        FUNCTION_ENV_SET => env_set(ctx.a0, ctx.a1, ctx.a2, ctx.a3),
+        #[cfg(feature = "watchdog")]
+        FUNCTION_WATCHDOG_ARM => match crate::watchdog::arm(ctx.a0) {
+            Some(timeout) => (SBI_SUCCESS, timeout),
+            None => (SBI_ERR_INVALID_PARAM, 0),
+        },
+        #[cfg(feature = "watchdog")]
+        FUNCTION_WATCHDOG_KICK => {
+            crate::watchdog::kick();
+            (SBI_SUCCESS, 0)
+        }
+        #[cfg(feature = "watchdog")]
+        FUNCTION_WATCHDOG_DISABLE => {
+            crate::watchdog::disable();
+            (SBI_SUCCESS, 0)
+        }
+        _ => return false,
+    };
+    ctx.a0 = error; // SbiRet::error
+    ctx.a1 = value; // SbiRet::value
+    ctx.mepc = ctx.mepc.wrapping_add(4); // PC += 4
+    true
+}
+
+fn get_stack_usage(hart_id: usize) -> (usize, usize) {
+    match stack::max_depth(hart_id) {
+        Some(depth) => (SBI_SUCCESS, depth),
+        None => (SBI_ERR_INVALID_PARAM, 0),
+    }
+}
+
+fn dump_trap_history(hart_id: usize) -> (usize, usize) {
+    match hart::hart(hart_id) {
+        Some(hart) => {
+            hart.history.dump(hart_id);
+            (SBI_SUCCESS, 0)
+        }
+        None => (SBI_ERR_INVALID_PARAM, 0),
+    }
+}
+
+fn get_statistic(hart_id: usize, f: impl FnOnce(&HartStats) -> Option<usize>) -> (usize, usize) {
+    match hart::hart(hart_id).and_then(|hart| f(&hart.stats)) {
+        Some(value) => (SBI_SUCCESS, value),
+        None => (SBI_ERR_INVALID_PARAM, 0),
+    }
+}
+
+fn reset_statistics(hart_id: usize) -> (usize, usize) {
+    if hart_id != hart::this_hart().hart_id {
+        // 其它核可能正在修改统计数据
+        return (SBI_ERR_INVALID_PARAM, 0);
+    }
+    hart::this_hart().stats.reset();
+    (SBI_SUCCESS, 0)
+}
+
+#[cfg(feature = "env")]
+fn env_get(name: usize, name_len: usize, buf: usize, buf_len: usize) -> (usize, usize) {
+    let mut name_buf = [0; ENV_NAME_MAX];
+    let name = match read_env_name(name, name_len, &mut name_buf) {
+        Ok(name) => name,
+        Err(error) => return (error, 0),
+    };
+    if !kernel_memory_valid(buf, buf_len) {
+        return (SBI_ERR_INVALID_ADDRESS, 0);
+    }
+    let buf = unsafe { core::slice::from_raw_parts_mut(buf as *mut u8, buf_len) };
+    match crate::env::get(name, buf) {
+        Some(len) => (SBI_SUCCESS, len),
+        None => (SBI_ERR_FAILED, 0),
+    }
+}
+
+#[cfg(feature = "env")]
+fn env_set(name: usize, name_len: usize, value: usize, value_len: usize) -> (usize, usize) {
+    let mut name_buf = [0; ENV_NAME_MAX];
+    let name = match read_env_name(name, name_len, &mut name_buf) {
+        Ok(name) => name,
+        Err(error) => return (error, 0),
+    };
+    let value = match value_len {
+        0 => None,
+        _ if !kernel_memory_valid(value, value_len) => return (SBI_ERR_INVALID_ADDRESS, 0),
+        _ => {
+            let value = unsafe { core::slice::from_raw_parts(value as *const u8, value_len) };
+            match core::str::from_utf8(value) {
+                Ok(value) => Some(value),
+                Err(_) => return (SBI_ERR_INVALID_PARAM, 0),
+            }
+        }
+    };
+    match crate::env::set(name, value) {
+        Ok(()) => (SBI_SUCCESS, 0),
+        Err(EnvError::Full) => (SBI_ERR_FAILED, 0),
+        Err(_) => (SBI_ERR_INVALID_PARAM, 0),
+    }
+}
+
+// 变量名复制到SBI栈上，内核的缓冲区可以和它重叠
+#[cfg(feature = "env")]
+fn read_env_name(address: usize, len: usize, buf: &mut [u8; ENV_NAME_MAX]) -> Result<&str, usize> {
+    if len > ENV_NAME_MAX {
+        return Err(SBI_ERR_INVALID_PARAM);
+    }
+    if !kernel_memory_valid(address, len) {
+        return Err(SBI_ERR_INVALID_ADDRESS);
+    }
+    let name = unsafe { core::slice::from_raw_parts(address as *const u8, len) };
+    buf[..len].copy_from_slice(name);
+    core::str::from_utf8(&buf[..len]).map_err(|_| SBI_ERR_INVALID_PARAM)
+}
+
+// 内核传入的物理地址只能在下一阶段程序可以使用的SRAM中
+#[cfg(feature = "env")]
+fn kernel_memory_valid(address: usize, len: usize) -> bool {
+    address
+        .checked_add(len)
+        .is_some_and(|end| address >= config::PAYLOAD_ADDRESS && end <= config::RAM_END)
+}
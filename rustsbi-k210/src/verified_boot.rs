@@ -0,0 +1,48 @@
+// 验证启动，用`verified-boot`特性编译时才会包含。
+//
+// 构建时嵌入`K210_VERIFY_KEY`指定的公钥。进入下一阶段程序之前，检查内核镜像末尾的
+// Ed25519签名，签名的是内核的SHA-256摘要；检查不通过时拒绝启动并关机。
+// 签名尾部由`cargo xtask sign`或`cargo xtask k210 --sign`生成。
+use crate::{handoff, peripheral};
+use k210_boot::signature::{self, PUBLIC_KEY_SIZE};
+use rustsbi::println;
+
+static PUBLIC_KEY: [u8; PUBLIC_KEY_SIZE] =
+    *include_bytes!(concat!(env!("OUT_DIR"), "/verify-key.bin"));
+
+// 在0号核加载完内核之后、初始化设备树之前调用
+pub fn check() {
+    let Some((start, size)) = handoff::signed_image() else {
+        fail(format_args!("no signed kernel image"));
+    };
+    let image = unsafe { core::slice::from_raw_parts(start as *const u8, size) };
+    let payload_len = match signature::verify(image, &PUBLIC_KEY) {
+        Ok(len) => len,
+        Err(e) => fail(format_args!("kernel at {:#x}: {:?}", start, e)),
+    };
+    // 入口必须在签名覆盖的范围内
+    let entry = handoff::next_stage().address;
+    if entry < start || entry >= start + payload_len {
+        fail(format_args!(
+            "entry {:#x} is outside of the signed kernel {:#x}..{:#x}",
+            entry,
+            start,
+            start + payload_len
+        ));
+    }
+    println!(
+        "[rustsbi] verified kernel signature at {:#x} ({:#x} bytes)",
+        start, payload_len
+    );
+}
+
+fn fail(args: core::fmt::Arguments) -> ! {
+    println!("[rustsbi] verified boot: {}", args);
+    println!("[rustsbi] system shutdown scheduled due to verified boot failure");
+    use rustsbi::Reset;
+    peripheral::Reset.system_reset(
+        rustsbi::reset::RESET_TYPE_SHUTDOWN,
+        rustsbi::reset::RESET_REASON_SYSTEM_FAILURE,
+    );
+    loop {}
+}
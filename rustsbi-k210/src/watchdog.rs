@@ -0,0 +1,111 @@
+// 看门狗，用`watchdog`特性编译时才会包含。
+//
+// 使用WDT0，超时后直接复位芯片。0号核初始化外设后读取并清除SYSCTL记录的复位原因，打印出来并写入设备树；
+// 进入内核之前按`K210_WATCHDOG_TIMEOUT_MS`启动看门狗，之后由内核通过厂商SBI调用重新设置、喂狗或者关闭。
+// 系统重启也借助看门狗完成。
+use core::ptr::{read_volatile, write_volatile};
+use k210_boot::config;
+use k210_boot::watchdog::{self, ResetReason};
+use rustsbi::println;
+
+const SYSCTL_BASE: usize = 0x5044_0000;
+const SYSCTL_CLK_EN_CENT: usize = 0x28;
+const SYSCTL_CLK_EN_PERI: usize = 0x2c;
+const SYSCTL_PERI_RESET: usize = 0x34;
+const SYSCTL_CLK_TH6: usize = 0x50;
+const SYSCTL_RESET_STATUS: usize = 0x60;
+const CLK_EN_APB1: u32 = 1 << 4;
+const PERI_WDT0: u32 = 1 << 24;
+const RESET_STATUS_CLEAR: u32 = 1 << 0;
+// WDT0的分频阈值，为0时时钟是watchdog::CLOCK_HZ
+const CLK_TH6_WDT0: u32 = 0xff;
+
+const WDT0_BASE: usize = 0x5040_0000;
+const WDT_CR: usize = 0x00;
+const WDT_TORR: usize = 0x04;
+const WDT_CRR: usize = 0x0c;
+// 响应模式位保持为0，超时直接复位，不先产生中断
+const WDT_CR_ENABLE: u32 = 1 << 0;
+const WDT_CRR_RESTART: u32 = 0x76;
+
+static mut RESET_REASON: ResetReason = ResetReason::PowerOn;
+
+// 0号核初始化外设之后调用
+pub fn init() {
+    let status = unsafe { read_volatile(sysctl(SYSCTL_RESET_STATUS)) };
+    let reason = ResetReason::from_status(status);
+    unsafe {
+        RESET_REASON = reason;
+        // 写1再写0清除记录，下次复位重新记录
+        write_volatile(sysctl(SYSCTL_RESET_STATUS), status | RESET_STATUS_CLEAR);
+        write_volatile(sysctl(SYSCTL_RESET_STATUS), status & !RESET_STATUS_CLEAR);
+    }
+    if let ResetReason::Watchdog(id) = reason {
+        println!("[rustsbi] last reset was caused by watchdog {}", id);
+    }
+    unsafe {
+        modify(sysctl(SYSCTL_CLK_EN_CENT), |v| v | CLK_EN_APB1);
+        modify(sysctl(SYSCTL_CLK_TH6), |v| v & !CLK_TH6_WDT0);
+        modify(sysctl(SYSCTL_PERI_RESET), |v| v | PERI_WDT0);
+        // 复位至少保持几个看门狗时钟周期
+        for _ in 0..1000 {
+            core::hint::spin_loop();
+        }
+        modify(sysctl(SYSCTL_PERI_RESET), |v| v & !PERI_WDT0);
+        modify(sysctl(SYSCTL_CLK_EN_PERI), |v| v | PERI_WDT0);
+    }
+}
+
+pub fn reset_reason() -> ResetReason {
+    unsafe { RESET_REASON }
+}
+
+// 进入内核之前调用
+pub fn arm_at_boot() {
+    if config::WATCHDOG_TIMEOUT_MS == 0 {
+        return;
+    }
+    let timeout = arm(config::WATCHDOG_TIMEOUT_MS).unwrap();
+    println!("[rustsbi] watchdog armed, timeout {} ms", timeout);
+}
+
+// 启动看门狗或者修改超时，返回实际的超时毫秒数；超时为0或太长时返回None
+pub fn arm(timeout_ms: usize) -> Option<usize> {
+    let top = watchdog::timeout_top(timeout_ms)?;
+    unsafe {
+        // 初始超时和喂狗后的超时相同
+        write_volatile(wdt(WDT_TORR), (top << 4) | top);
+        write_volatile(wdt(WDT_CRR), WDT_CRR_RESTART);
+        modify(wdt(WDT_CR), |v| v | WDT_CR_ENABLE);
+    }
+    Some(watchdog::top_timeout_ms(top))
+}
+
+// 重新开始计时；看门狗没有启动时什么也不做
+pub fn kick() {
+    unsafe { write_volatile(wdt(WDT_CRR), WDT_CRR_RESTART) };
+}
+
+pub fn disable() {
+    unsafe { modify(wdt(WDT_CR), |v| v & !WDT_CR_ENABLE) };
+}
+
+// 用最短的超时让看门狗复位芯片
+pub fn reboot() -> ! {
+    arm(1);
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+fn sysctl(offset: usize) -> *mut u32 {
+    (SYSCTL_BASE + offset) as *mut u32
+}
+
+fn wdt(offset: usize) -> *mut u32 {
+    (WDT0_BASE + offset) as *mut u32
+}
+
+unsafe fn modify(register: *mut u32, f: impl FnOnce(u32) -> u32) {
+    write_volatile(register, f(read_volatile(register)));
+}
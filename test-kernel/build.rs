@@ -1,18 +1,23 @@
+use k210_boot::config;
 use std::env;
 use std::fs;
-use std::io::Write;
 use std::path::PathBuf;
 
 fn main() {
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
 
-    // Put the linker script somewhere the linker can find it
-    fs::File::create(out_dir.join("linker.ld"))
-        .unwrap()
-        .write_all(include_bytes!("src/linker.ld"))
-        .unwrap();
+    // Link the kernel at the payload address RustSBI jumps to,
+    // then put the linker script somewhere the linker can find it
+    let script = include_str!("src/linker.ld").replace(
+        "${PAYLOAD_ADDRESS}",
+        &format!("{:#x}", config::PAYLOAD_ADDRESS),
+    );
+    fs::write(out_dir.join("linker.ld"), script).unwrap();
     println!("cargo:rustc-link-search={}", out_dir.display());
 
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=src/linker.ld");
+    for var in config::ENV_VARS {
+        println!("cargo:rerun-if-env-changed={}", var);
+    }
 }
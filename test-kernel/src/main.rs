@@ -45,6 +45,37 @@ pub fn test_emulate_rdtime() {
     println!(">> Test-kernel: Testing SBI instruction emulation");
     let time = riscv::register::time::read64();
     println!("<< Test-kernel: Current time: {:x}", time);
+    bench_rdtime_set_timer();
+}
+
+// K210的mtime频率是CPU频率的1/50
+const CYCLES_PER_TICK: u64 = 50;
+const BENCH_ROUNDS: u64 = 1000;
+
+// 测量rdtime和set_timer往返机器态的开销，只打印测得的周期数
+fn bench_rdtime_set_timer() {
+    let start = riscv::register::time::read64();
+    for _ in 0..BENCH_ROUNDS {
+        let _ = riscv::register::time::read64();
+    }
+    let ticks = riscv::register::time::read64() - start;
+    println!(
+        "<< Test-kernel: rdtime: {} ticks / {} rounds, ~{} cycles each",
+        ticks,
+        BENCH_ROUNDS,
+        ticks * CYCLES_PER_TICK / BENCH_ROUNDS
+    );
+    let start = riscv::register::time::read64();
+    for _ in 0..BENCH_ROUNDS {
+        sbi::set_timer(usize::MAX);
+    }
+    let ticks = riscv::register::time::read64() - start;
+    println!(
+        "<< Test-kernel: set_timer: {} ticks / {} rounds, ~{} cycles each",
+        ticks,
+        BENCH_ROUNDS,
+        ticks * CYCLES_PER_TICK / BENCH_ROUNDS
+    );
 }
 
 fn init_bss() {
@@ -0,0 +1,78 @@
+use crate::ktool_write;
+use k210_boot::config;
+use k210_boot::env::{self, Env, ENV_SIZE};
+use std::{fs, path::Path, process};
+
+// Load the current copy from an environment image, or start an empty
+// environment when the image does not exist yet
+fn load(path: &Path) -> (Env, u8) {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return (Env::new(), 0),
+        Err(e) => {
+            eprintln!("xtask: cannot read {}: {}", path.display(), e);
+            process::exit(1)
+        }
+    };
+    if bytes.len() != 2 * ENV_SIZE {
+        eprintln!(
+            "xtask: {} is {:#x} bytes, expected an environment image of {:#x} bytes",
+            path.display(),
+            bytes.len(),
+            2 * ENV_SIZE
+        );
+        process::exit(1);
+    }
+    let copy = |index: usize| &bytes[index * ENV_SIZE..(index + 1) * ENV_SIZE];
+    let mut env = Env::new();
+    let mut generations = [None; 2];
+    for (index, generation) in generations.iter_mut().enumerate() {
+        env.raw_mut().copy_from_slice(copy(index));
+        *generation = Env::check(env.as_bytes()).ok();
+    }
+    let Some(index) = env::current(generations) else {
+        eprintln!("xtask: {} has no valid copy", path.display());
+        process::exit(1)
+    };
+    env.raw_mut().copy_from_slice(copy(index));
+    (env, generations[index].unwrap())
+}
+
+// Create or edit an environment image offline, then optionally write it
+// to the reserved flash sectors at K210_FLASH_ENV_OFFSET
+pub fn xtask_env(path: &Path, set: &[&str], unset: &[&str], port: Option<&str>) {
+    let (mut env, generation) = load(path);
+    let mut changed = !path.exists();
+    for assignment in set {
+        let Some((name, value)) = assignment.split_once('=') else {
+            eprintln!("xtask: expected name=value, got {}", assignment);
+            process::exit(1)
+        };
+        changed |= env.set(name, value).unwrap_or_else(|e| {
+            eprintln!("xtask: cannot set {}: {:?}", name, e);
+            process::exit(1)
+        });
+    }
+    for name in unset {
+        changed |= env.remove(name);
+    }
+    for (name, value) in env.iter() {
+        println!("{}={}", name, value);
+    }
+    if changed {
+        // Both copies carry the new generation, RustSBI picks the first one
+        env.seal(generation.wrapping_add(1));
+        let mut image = env.as_bytes().to_vec();
+        image.extend_from_slice(env.as_bytes());
+        fs::write(path, image).expect("write environment image");
+        println!("xtask: wrote {}", path.display());
+    }
+    if let Some(port) = port {
+        println!(
+            "xtask: writing {} to flash at {:#x}",
+            path.display(),
+            config::FLASH_ENV_OFFSET
+        );
+        ktool_write(port, config::FLASH_ENV_OFFSET, path);
+    }
+}
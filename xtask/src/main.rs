@@ -1,7 +1,17 @@
+mod bootenv;
 mod detect;
+mod send;
+mod sign;
+mod slot;
 mod test;
 
 use clap::{clap_app, crate_authors, crate_description, crate_version};
+use k210_boot::bootctl::DEFAULT_TRIES;
+use k210_boot::config;
+use k210_boot::fw_dynamic::{FwDynamicInfo, BOOT_HART_ANY, NEXT_MODE_S};
+use k210_boot::initrd::InitrdHeader;
+use k210_boot::payload::{PayloadHeader, FLAG_LZ4};
+use lz4_flex::frame::{BlockSize, FrameEncoder, FrameInfo};
 use std::{
     env, fs,
     io::{Seek, SeekFrom, Write},
@@ -12,6 +22,10 @@ use std::{
 #[derive(Debug)]
 struct XtaskEnv {
     compile_mode: CompileMode,
+    boot_hart: Option<u64>,
+    initrd: Option<PathBuf>,
+    sign: Option<PathBuf>,
+    compress: bool,
 }
 
 #[derive(Debug)]
@@ -34,6 +48,47 @@ fn main() {
         (@subcommand k210 =>
             (about: "Run project on actual board")
             (@arg release: --release "Build artifacts in release mode, with optimizations")
+            (@arg boot_hart: --("boot-hart") +takes_value "Hart that enters the payload first, defaults to 0")
+            (@arg initrd: --initrd +takes_value "Initial ramdisk to load along with the kernel")
+            (@arg sign: --sign +takes_value "Sign the kernel with this key for verified boot")
+            (@arg compress: --compress "Compress the kernel with LZ4 to shorten the upload")
+        )
+        (@subcommand mkimage =>
+            (about: "Wrap a raw kernel binary into a flash payload image")
+            (@arg input: +required "Raw kernel binary")
+            (@arg output: +required "Output image, to be written at K210_FLASH_PAYLOAD_OFFSET")
+            (@arg sha256: --sha256 "Also check the payload against its SHA-256 digest")
+            (@arg compress: --compress "Compress the payload with LZ4")
+        )
+        (@subcommand slot =>
+            (about: "Write a kernel into flash slot A or B and make it the active slot")
+            (@arg slot: +required "Flash slot to write, a or b")
+            (@arg release: --release "Build the test kernel in release mode")
+            (@arg kernel: --kernel +takes_value "Raw kernel binary, defaults to the test kernel")
+            (@arg tries: --tries +takes_value "Boot attempts before falling back to the other slot, defaults to 3")
+            (@arg compress: --compress "Compress the kernel with LZ4")
+        )
+        (@subcommand env =>
+            (about: "Create or edit a boot environment image, optionally writing it to flash")
+            (@arg image: +required "Environment image, created when it does not exist")
+            (@arg set: --set +takes_value +multiple number_of_values(1) "Set a variable, as name=value")
+            (@arg unset: --unset +takes_value +multiple number_of_values(1) "Remove a variable")
+            (@arg flash: --flash "Write the image to flash at K210_FLASH_ENV_OFFSET")
+        )
+        (@subcommand keygen =>
+            (about: "Create a signing key for verified boot")
+            (@arg output: "Signing key to create, defaults to target/xtask/signing-key")
+        )
+        (@subcommand sign =>
+            (about: "Append a verified boot signature to a raw kernel binary")
+            (@arg key: +required "Signing key created by `cargo xtask keygen`")
+            (@arg input: +required "Raw kernel binary")
+            (@arg output: +required "Signed kernel image")
+        )
+        (@subcommand send =>
+            (about: "Send a kernel to RustSBI's serial download mode over YMODEM")
+            (@arg release: --release "Build the test kernel in release mode")
+            (@arg kernel: --kernel +takes_value "Raw kernel binary, defaults to the test kernel")
         )
         (@subcommand detect =>
             (about: "Detect target serial port")
@@ -48,6 +103,10 @@ fn main() {
     .get_matches();
     let mut xtask_env = XtaskEnv {
         compile_mode: CompileMode::Debug,
+        boot_hart: None,
+        initrd: None,
+        sign: None,
+        compress: false,
     };
     // Read: python xtask/ktool.py -p COM11 -a 0x80000000 -R -L 0x20000 ./target/xtask/flash_dump.bin
     if let Some(matches) = matches.subcommand_matches("k210") {
@@ -70,6 +129,15 @@ fn main() {
         if matches.is_present("release") {
             xtask_env.compile_mode = CompileMode::Release;
         }
+        if let Some(hart) = matches.value_of("boot_hart") {
+            xtask_env.boot_hart = Some(hart.parse().unwrap_or_else(|_| {
+                eprintln!("xtask: invalid boot hart {}", hart);
+                process::exit(1)
+            }));
+        }
+        xtask_env.initrd = matches.value_of("initrd").map(PathBuf::from);
+        xtask_env.sign = matches.value_of("sign").map(PathBuf::from);
+        xtask_env.compress = matches.is_present("compress");
         println!("xtask: mode: {:?}", xtask_env.compile_mode);
         xtask_build_sbi(&xtask_env);
         xtask_binary_sbi(&xtask_env);
@@ -84,6 +152,87 @@ fn main() {
         println!("xtask: mode: {:?}", xtask_env.compile_mode);
         xtask_build_sbi(&xtask_env);
         xtask_binary_sbi(&xtask_env);
+    } else if let Some(matches) = matches.subcommand_matches("send") {
+        let port = match detect::read_serial_port_choose_file() {
+            Ok(string) => string,
+            Err(_e) => detect_save_port_or_exit(),
+        };
+        if matches.is_present("release") {
+            xtask_env.compile_mode = CompileMode::Release;
+        }
+        let kernel = match matches.value_of("kernel") {
+            Some(path) => PathBuf::from(path),
+            None => {
+                xtask_build_test_kernel(&xtask_env);
+                xtask_binary_test_kernel(&xtask_env);
+                dist_dir(&xtask_env).join("test-kernel.bin")
+            }
+        };
+        send::send_kernel(&port, &kernel);
+    } else if let Some(matches) = matches.subcommand_matches("slot") {
+        let slot = slot::parse_slot(matches.value_of("slot").unwrap());
+        let tries = match matches.value_of("tries") {
+            Some(tries) => tries.parse().unwrap_or_else(|_| {
+                eprintln!("xtask: invalid number of tries {}", tries);
+                process::exit(1)
+            }),
+            None => DEFAULT_TRIES,
+        };
+        let port = match detect::read_serial_port_choose_file() {
+            Ok(string) => string,
+            Err(_e) => detect_save_port_or_exit(),
+        };
+        if matches.is_present("release") {
+            xtask_env.compile_mode = CompileMode::Release;
+        }
+        let kernel = match matches.value_of("kernel") {
+            Some(path) => PathBuf::from(path),
+            None => {
+                xtask_build_test_kernel(&xtask_env);
+                xtask_binary_test_kernel(&xtask_env);
+                dist_dir(&xtask_env).join("test-kernel.bin")
+            }
+        };
+        slot::write_slot(&port, slot, &kernel, tries, matches.is_present("compress"));
+    } else if let Some(matches) = matches.subcommand_matches("mkimage") {
+        xtask_mkimage(
+            Path::new(matches.value_of("input").unwrap()),
+            Path::new(matches.value_of("output").unwrap()),
+            matches.is_present("sha256"),
+            matches.is_present("compress"),
+        );
+    } else if let Some(matches) = matches.subcommand_matches("env") {
+        let port = if matches.is_present("flash") {
+            Some(match detect::read_serial_port_choose_file() {
+                Ok(string) => string,
+                Err(_e) => detect_save_port_or_exit(),
+            })
+        } else {
+            None
+        };
+        let values = |name| {
+            matches
+                .values_of(name)
+                .map_or(Vec::new(), Iterator::collect)
+        };
+        bootenv::xtask_env(
+            Path::new(matches.value_of("image").unwrap()),
+            &values("set"),
+            &values("unset"),
+            port.as_deref(),
+        );
+    } else if let Some(matches) = matches.subcommand_matches("keygen") {
+        let path = matches
+            .value_of("output")
+            .map(PathBuf::from)
+            .unwrap_or_else(sign::default_key_path);
+        sign::keygen(&path);
+    } else if let Some(matches) = matches.subcommand_matches("sign") {
+        sign::xtask_sign(
+            Path::new(matches.value_of("key").unwrap()),
+            Path::new(matches.value_of("input").unwrap()),
+            Path::new(matches.value_of("output").unwrap()),
+        );
     } else if let Some(_matches) = matches.subcommand_matches("detect") {
         let ans = detect::detect_serial_ports();
         if let Some((port_name, info)) = ans {
@@ -207,18 +356,196 @@ fn xtask_fuse_binary(xtask_env: &XtaskEnv) {
     let sbi_binary_path = dist_dir(xtask_env).join("rustsbi-k210.bin");
     let test_kernel_binary_path = dist_dir(xtask_env).join("test-kernel.bin");
     let output_path = dist_dir(xtask_env).join("k210-fused.bin");
-    let offset = 0x20000;
+    let offset = config::PAYLOAD_OFFSET as u64;
+    let sbi_size = fs::metadata(&sbi_binary_path)
+        .expect("read sbi binary")
+        .len();
+    if sbi_size > config::HANDOFF_OFFSET as u64 {
+        eprintln!(
+            "xtask: sbi binary is {:#x} bytes, which overlaps the handoff area at offset {:#x}",
+            sbi_size,
+            config::HANDOFF_OFFSET
+        );
+        process::exit(1);
+    }
     fs::copy(sbi_binary_path, &output_path).expect("copy sbi base");
     let mut output = fs::OpenOptions::new()
         .read(true)
         .write(true)
         .open(output_path)
         .expect("open output file");
-    let buf = fs::read(test_kernel_binary_path).expect("read kernel binary");
+    // Tell RustSBI where and how to start the payload, the same way
+    // a previous boot stage would do with OpenSBI's fw_dynamic
+    let info = FwDynamicInfo::new(
+        config::PAYLOAD_ADDRESS as u64,
+        NEXT_MODE_S,
+        0,
+        xtask_env.boot_hart.unwrap_or(BOOT_HART_ANY),
+    );
+    output
+        .seek(SeekFrom::Start(config::HANDOFF_OFFSET as u64))
+        .expect("seek to handoff area");
+    output
+        .write_all(&info.to_bytes())
+        .expect("write fw_dynamic_info");
+    let mut buf = fs::read(test_kernel_binary_path).expect("read kernel binary");
+    if let Some(key_path) = &xtask_env.sign {
+        // The signature trailer follows the kernel, and the handoff area
+        // tells RustSBI how long the signed image is
+        buf = sign::sign(&buf, &sign::read_key(key_path));
+        output
+            .seek(SeekFrom::Start(
+                (config::HANDOFF_OFFSET + config::HANDOFF_IMAGE_SIZE_OFFSET) as u64,
+            ))
+            .expect("seek to image size");
+        output
+            .write_all(&(buf.len() as u64).to_le_bytes())
+            .expect("write image size");
+        println!("xtask: signed kernel with {}", key_path.display());
+    }
+    // RustSBI moves the compressed kernel past the end of the unpacked one,
+    // so the initrd has to start after both
+    let mut kernel_end = config::PAYLOAD_ADDRESS + buf.len();
+    // A Linux Image also needs room for its .bss, which is not in the file
+    let image_end = config::PAYLOAD_ADDRESS + linux_image_size(&buf).unwrap_or(0);
+    if xtask_env.compress {
+        let frame = lz4_compress(&buf);
+        println!(
+            "xtask: compressed kernel from {:#x} to {:#x} bytes",
+            buf.len(),
+            frame.len()
+        );
+        output
+            .seek(SeekFrom::Start(
+                (config::HANDOFF_OFFSET + config::HANDOFF_COMPRESSED_SIZE_OFFSET) as u64,
+            ))
+            .expect("seek to compressed size");
+        output
+            .write_all(&(frame.len() as u64).to_le_bytes())
+            .expect("write compressed size");
+        kernel_end = ((kernel_end + 7) & !7) + frame.len();
+        buf = frame;
+    }
     output
         .seek(SeekFrom::Start(offset))
         .expect("seek to offset");
     output.write(&buf).expect("write output");
+    if let Some(initrd_path) = &xtask_env.initrd {
+        fuse_initrd(&mut output, initrd_path, kernel_end.max(image_end));
+    }
+}
+
+// The effective image size from the header of a RISC-V Linux Image
+fn linux_image_size(image: &[u8]) -> Option<usize> {
+    if image.get(0x38..0x3c)? != b"RSC\x05" {
+        return None;
+    }
+    Some(u64::from_le_bytes(image[0x10..0x18].try_into().unwrap()) as usize)
+}
+
+// Put the initrd into the fused image, at the configured address or on the
+// first page after the kernel, and describe it in the handoff area for RustSBI.
+// Anything between the kernel and a configured address is zero padding in the
+// image, so only an explicit K210_INITRD_ADDRESS makes the image bigger
+fn fuse_initrd(output: &mut fs::File, path: &Path, kernel_end: usize) {
+    let initrd = fs::read(path).unwrap_or_else(|e| {
+        eprintln!("xtask: cannot read initrd {}: {}", path.display(), e);
+        process::exit(1)
+    });
+    let start = config::INITRD_ADDRESS.unwrap_or((kernel_end + 0xfff) & !0xfff);
+    let end = start + initrd.len();
+    let dtb = config::DTB_ADDRESS.map(|dtb| (dtb, dtb + config::DTB_SIZE_LIMIT));
+    if start < kernel_end
+        || end > config::RAM_END
+        || dtb.is_some_and(|(dtb_start, dtb_end)| start < dtb_end && dtb_start < end)
+    {
+        eprintln!(
+            "xtask: initrd at {:#x}..{:#x} does not fit in SRAM after the kernel",
+            start, end
+        );
+        process::exit(1);
+    }
+    println!("xtask: initrd at {:#x}..{:#x}", start, end);
+    let header = InitrdHeader::new(start as u64, initrd.len() as u64);
+    output
+        .seek(SeekFrom::Start(
+            (config::HANDOFF_OFFSET + config::HANDOFF_INITRD_OFFSET) as u64,
+        ))
+        .expect("seek to initrd header");
+    output
+        .write_all(&header.to_bytes())
+        .expect("write initrd header");
+    output
+        .seek(SeekFrom::Start((start - config::SBI_START) as u64))
+        .expect("seek to initrd");
+    output.write_all(&initrd).expect("write initrd");
+}
+
+// Prefix the kernel with the header RustSBI looks for in SPI flash when built
+// with the `flash-boot` feature
+fn xtask_mkimage(input: &Path, output: &Path, with_sha256: bool, compress: bool) {
+    let payload = fs::read(input).unwrap_or_else(|e| {
+        eprintln!("xtask: cannot read {}: {}", input.display(), e);
+        process::exit(1)
+    });
+    let (header, image) = payload_image(&payload, with_sha256, compress);
+    fs::write(output, image).expect("write payload image");
+    println!(
+        "xtask: wrote {}, payload {:#x} bytes, stored {:#x} bytes, crc32 {:#010x}",
+        output.display(),
+        payload.len(),
+        header.size,
+        header.crc32
+    );
+}
+
+// The kernel is loaded and entered at PAYLOAD_ADDRESS
+fn payload_image(kernel: &[u8], with_sha256: bool, compress: bool) -> (PayloadHeader, Vec<u8>) {
+    let address = config::PAYLOAD_ADDRESS as u64;
+    let payload = if compress {
+        lz4_compress(kernel)
+    } else {
+        kernel.to_vec()
+    };
+    let mut header = PayloadHeader::new(address, address, &payload, with_sha256);
+    if compress {
+        header.flags |= FLAG_LZ4;
+    }
+    let mut image = header.to_bytes().to_vec();
+    image.extend_from_slice(&payload);
+    (header, image)
+}
+
+// RustSBI needs the content size to plan where to unpack, and checks the
+// content checksum after unpacking
+fn lz4_compress(data: &[u8]) -> Vec<u8> {
+    let info = FrameInfo::new()
+        .content_size(Some(data.len() as u64))
+        .content_checksum(true)
+        .block_size(BlockSize::Max4MB);
+    let mut encoder = FrameEncoder::with_frame_info(info, Vec::new());
+    encoder.write_all(data).expect("compress kernel");
+    encoder.finish().expect("compress kernel")
+}
+
+// Write a file to SPI flash at the given address with ktool.py
+fn ktool_write(port: &str, address: usize, path: &Path) {
+    let status = Command::new("python")
+        .current_dir(project_root().join("xtask"))
+        .arg("ktool.py")
+        .args(["--port", port])
+        .args(["--baudrate", "1500000"])
+        .args(["--address", &format!("{:#x}", address)])
+        .arg(path)
+        .status()
+        .unwrap();
+    if !status.success() {
+        eprintln!(
+            "xtask: run ktool.py failed with code {}",
+            status.code().unwrap()
+        );
+        process::exit(status.code().unwrap())
+    }
 }
 
 fn dist_dir(xtask_env: &XtaskEnv) -> PathBuf {
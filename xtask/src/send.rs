@@ -0,0 +1,88 @@
+use k210_boot::ymodem::{self, Port};
+use serialport::SerialPort;
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+    process,
+    time::Duration,
+};
+
+// Same baudrate as the RustSBI console on UARTHS, unless the boot environment changes it
+const BAUDRATE: u32 = 115_200;
+// RustSBI waits 30 seconds for the upload after the key is pressed
+const WAIT_SECONDS: u32 = 60;
+
+struct Serial(Box<dyn SerialPort>);
+
+impl Port for Serial {
+    type Error = io::ErrorKind;
+
+    fn read_byte(&mut self, timeout_ms: u32) -> Result<Option<u8>, io::ErrorKind> {
+        self.0
+            .set_timeout(Duration::from_millis(timeout_ms as u64))
+            .map_err(|e| io::Error::from(e).kind())?;
+        let mut byte = [0];
+        match self.0.read(&mut byte) {
+            Ok(1) => Ok(Some(byte[0])),
+            Ok(_) => Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => Ok(None),
+            Err(e) => Err(e.kind()),
+        }
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), io::ErrorKind> {
+        self.0.write_all(bytes).map_err(|e| e.kind())
+    }
+}
+
+// Send the kernel to RustSBI's serial download mode, then show the console
+pub fn send_kernel(port_name: &str, kernel_path: &Path) {
+    let kernel = fs::read(kernel_path).unwrap_or_else(|e| {
+        eprintln!("xtask: cannot read {}: {}", kernel_path.display(), e);
+        process::exit(1)
+    });
+    let port = serialport::new(port_name, BAUDRATE)
+        .open()
+        .unwrap_or_else(|e| {
+            eprintln!("xtask: cannot open {}: {}", port_name, e);
+            process::exit(1)
+        });
+    let name = kernel_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("kernel.bin");
+    println!(
+        "xtask: sending {} ({} bytes), reset the board, then hold the serial boot key",
+        kernel_path.display(),
+        kernel.len()
+    );
+    let mut serial = Serial(port);
+    let result = ymodem::send(&mut serial, name, &kernel, WAIT_SECONDS, |sent| {
+        print!("\rxtask: sent {}/{} bytes", sent, kernel.len());
+        io::stdout().flush().ok();
+    });
+    println!();
+    if let Err(e) = result {
+        eprintln!("xtask: upload failed: {:?}", e);
+        process::exit(1);
+    }
+    println!("xtask: upload finished, console output follows (Ctrl-C to exit)");
+    let mut port = serial.0;
+    port.set_timeout(Duration::from_secs(3600)).ok();
+    let mut stdout = io::stdout();
+    let mut buf = [0; 256];
+    loop {
+        match port.read(&mut buf) {
+            Ok(n) => {
+                stdout.write_all(&buf[..n]).ok();
+                stdout.flush().ok();
+            }
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => {
+                eprintln!("xtask: serial port error: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+}
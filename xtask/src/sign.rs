@@ -0,0 +1,83 @@
+use crate::project_root;
+use k210_boot::signature::{self, Trailer, SECRET_KEY_SIZE};
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    process,
+};
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Default location of the signing key; the public key goes next to it with a .pub suffix
+pub fn default_key_path() -> PathBuf {
+    project_root()
+        .join("target")
+        .join("xtask")
+        .join("signing-key")
+}
+
+// Create a new signing key from the system random source
+pub fn keygen(path: &Path) {
+    if path.exists() {
+        eprintln!(
+            "xtask: {} already exists, remove it first to make a new key",
+            path.display()
+        );
+        process::exit(1);
+    }
+    let mut seed = [0; SECRET_KEY_SIZE];
+    fs::File::open("/dev/urandom")
+        .and_then(|mut random| random.read_exact(&mut seed))
+        .expect("read random seed");
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).expect("create folder");
+    }
+    let public_path = path.with_extension("pub");
+    fs::write(path, hex(&seed) + "\n").expect("write signing key");
+    fs::write(&public_path, hex(&signature::public_key(&seed)) + "\n").expect("write public key");
+    println!(
+        "xtask: wrote signing key {} and public key {}",
+        path.display(),
+        public_path.display()
+    );
+    println!(
+        "xtask: build RustSBI with `--features verified-boot` and K210_VERIFY_KEY={}",
+        public_path.display()
+    );
+}
+
+pub fn read_key(path: &Path) -> [u8; SECRET_KEY_SIZE] {
+    let text = fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("xtask: cannot read signing key {}: {}", path.display(), e);
+        process::exit(1)
+    });
+    signature::parse_key(&text).unwrap_or_else(|| {
+        eprintln!("xtask: {} is not a signing key", path.display());
+        process::exit(1)
+    })
+}
+
+// Append the signature trailer RustSBI checks when built with `verified-boot`
+pub fn sign(kernel: &[u8], key: &[u8; SECRET_KEY_SIZE]) -> Vec<u8> {
+    let mut image = kernel.to_vec();
+    image.extend_from_slice(&Trailer::sign(kernel, key).to_bytes());
+    image
+}
+
+pub fn xtask_sign(key_path: &Path, input: &Path, output: &Path) {
+    let key = read_key(key_path);
+    let kernel = fs::read(input).unwrap_or_else(|e| {
+        eprintln!("xtask: cannot read {}: {}", input.display(), e);
+        process::exit(1)
+    });
+    fs::write(output, sign(&kernel, &key)).expect("write signed image");
+    println!(
+        "xtask: wrote {}, kernel {:#x} bytes, sha256 {}",
+        output.display(),
+        kernel.len(),
+        hex(&signature::digest(&kernel))
+    );
+}
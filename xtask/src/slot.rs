@@ -0,0 +1,53 @@
+use crate::{ktool_write, payload_image, project_root};
+use k210_boot::bootctl::{BootControl, Slot, BOOT_CONTROL_SECTOR_SIZE};
+use k210_boot::config;
+use std::{fs, path::Path, process};
+
+pub fn parse_slot(name: &str) -> Slot {
+    match name {
+        "a" | "A" => Slot::A,
+        "b" | "B" => Slot::B,
+        _ => {
+            eprintln!("xtask: unknown flash slot {}, expected a or b", name);
+            process::exit(1)
+        }
+    }
+}
+
+// Write the kernel into one flash slot for A/B boot, then make it the
+// active slot with `tries` boot attempts before RustSBI falls back
+pub fn write_slot(port: &str, slot: Slot, kernel_path: &Path, tries: u8, compress: bool) {
+    let kernel = fs::read(kernel_path).unwrap_or_else(|e| {
+        eprintln!("xtask: cannot read {}: {}", kernel_path.display(), e);
+        process::exit(1)
+    });
+    let (_, image) = payload_image(&kernel, true, compress);
+    if image.len() > config::FLASH_SLOT_SIZE {
+        eprintln!(
+            "xtask: image is {:#x} bytes, larger than the flash slot size {:#x}",
+            image.len(),
+            config::FLASH_SLOT_SIZE
+        );
+        process::exit(1);
+    }
+    let dir = project_root().join("target").join("xtask");
+    fs::create_dir_all(&dir).expect("create folder");
+    let image_path = dir.join(format!("slot-{:?}.bin", slot).to_lowercase());
+    fs::write(&image_path, image).expect("write slot image");
+    // Both copies of the record are written: the first one holds the new
+    // record and the second one is erased, so an older copy cannot win
+    let mut record = vec![0xff; 2 * BOOT_CONTROL_SECTOR_SIZE];
+    record[..16].copy_from_slice(&BootControl::new(slot, tries).to_bytes());
+    let record_path = dir.join("boot-control.bin");
+    fs::write(&record_path, record).expect("write boot control record");
+    println!(
+        "xtask: writing {} to flash slot {:?} at {:#x}",
+        kernel_path.display(),
+        slot,
+        slot.flash_offset()
+    );
+    ktool_write(port, slot.flash_offset(), &image_path);
+    // The record goes last, so an interrupted upload leaves the old slot active
+    println!("xtask: making slot {:?} active with {} tries", slot, tries);
+    ktool_write(port, config::FLASH_BOOT_CONTROL_OFFSET, &record_path);
+}